@@ -1,10 +1,28 @@
 #[derive(Debug, Clone)]
 pub struct Program {
-    #[allow(dead_code)]
     pub package: String,
     pub imports: Vec<Import>,
     pub functions: Vec<Function>,
-    pub modules: std::collections::HashMap<String, Module>,
+    #[allow(dead_code)]
+    pub enums: Vec<EnumDef>,
+    pub modules: std::collections::BTreeMap<String, Module>,
+}
+
+impl Program {
+    /// Finds `function` in `module`; a bare call inside a module resolves here before the top level.
+    pub fn module_function(&self, module: &str, function: &str) -> Option<&Function> {
+        self.modules.get(module).and_then(|m| m.functions.iter().find(|f| f.name == function))
+    }
+
+    /// Functions declared to return `string`: `name` at the top level, `module_name` in a module.
+    pub fn string_functions(&self) -> std::collections::HashSet<String> {
+        let returns_string = |f: &&Function| f.return_type.as_deref() == Some("string");
+        self.functions.iter().filter(returns_string).map(|f| f.name.clone())
+            .chain(self.modules.iter().flat_map(|(module_name, module)| {
+                module.functions.iter().filter(returns_string).map(move |f| format!("{}_{}", module_name, f.name))
+            }))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +39,12 @@ pub struct Module {
     pub functions: Vec<Function>,
 }
 
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<(String, i64)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
@@ -29,6 +53,56 @@ pub struct Function {
     pub return_type: Option<String>,
     pub body: Vec<Statement>,
     pub is_exported: bool,
+    pub file: String,
+    /// Line of the `fn` keyword.
+    pub line: usize,
+    /// Line of every statement in `body`, in the order a depth-first walk visits
+    /// them: each statement comes before the statements nested inside it.
+    pub statement_lines: Vec<usize>,
+}
+
+/// Follows a function's `statement_lines` while its statements are generated in source
+/// order, so code can name the line it came from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceCursor {
+    file: String,
+    lines: Vec<usize>,
+    next: usize,
+    line: usize,
+}
+
+impl SourceCursor {
+    pub fn new(func: &Function) -> Self {
+        SourceCursor {
+            file: func.file.clone(),
+            lines: func.statement_lines.clone(),
+            next: 0,
+            line: func.line,
+        }
+    }
+
+    /// Moves to the next statement and returns its line.
+    pub fn advance(&mut self) -> Option<usize> {
+        let line = self.lines.get(self.next).copied();
+        self.next += 1;
+        if let Some(line) = line {
+            self.line = line;
+        }
+        line
+    }
+
+    /// `file:line` of the current statement.
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.file, self.line)
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +110,7 @@ pub struct Parameter {
     pub name: String,
     #[allow(dead_code)]
     pub param_type: String,
+    pub is_variadic: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +121,10 @@ pub enum Statement {
         var_type: Option<String>,
         value: Option<Expression>,
     },
+    MultiVarDecl {
+        names: Vec<String>,
+        value: Expression,
+    },
     ArrayDecl {
         name: String,
         #[allow(dead_code)]
@@ -71,10 +150,8 @@ pub enum Statement {
         else_body: Option<Vec<Statement>>,
     },
     For {
-        #[allow(dead_code)]
         init: Option<Box<Statement>>,
         condition: Option<Expression>,
-        #[allow(dead_code)]
         post: Option<Box<Statement>>,
         body: Vec<Statement>,
     },
@@ -83,6 +160,72 @@ pub enum Statement {
     InlineAsm {
         parts: Vec<AsmPart>,
     },
+    Block(Vec<Statement>),
+}
+
+impl Statement {
+    /// Whether control never falls through past this statement.
+    pub fn always_returns(&self) -> bool {
+        match self {
+            Statement::Return(_) => true,
+            Statement::If { then_body, else_body: Some(else_body), .. } => {
+                always_returns(then_body) && always_returns(else_body)
+            }
+            Statement::Block(body) => always_returns(body),
+            Statement::For { condition: None, .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl Statement {
+    /// One-line source form of the statement, without nested bodies.
+    pub fn summary(&self) -> String {
+        match self {
+            Statement::VarDecl { name, var_type, value } => {
+                let mut text = format!("var {}", name);
+                if let Some(ty) = var_type {
+                    text.push_str(&format!(": {}", ty));
+                }
+                if let Some(value) = value {
+                    text.push_str(&format!(" = {}", value));
+                }
+                text
+            }
+            Statement::MultiVarDecl { names, value } => format!("var {} = {}", names.join(", "), value),
+            Statement::ArrayDecl { name, element_type, size } => format!("var {} [{}]{}", name, size, element_type),
+            Statement::Assignment { name, value } => format!("{} = {}", name, value),
+            Statement::ArrayAssignment { name, index, value } => format!("{}[{}] = {}", name, index, value),
+            Statement::PointerAssignment { target, value } => format!("*{} = {}", target, value),
+            Statement::If { condition, .. } => format!("if {}", condition),
+            Statement::For { init: None, condition: Some(condition), post: None, .. } => format!("for {}", condition),
+            Statement::For { init: None, condition: None, post: None, .. } => "for".to_string(),
+            Statement::For { init, condition, post, .. } => format!(
+                "for {}; {}; {}",
+                init.as_ref().map(|s| s.summary()).unwrap_or_default(),
+                condition.as_ref().map(|c| c.to_string()).unwrap_or_default(),
+                post.as_ref().map(|s| s.summary()).unwrap_or_default(),
+            ),
+            Statement::Return(Some(value)) => format!("return {}", value),
+            Statement::Return(None) => "return".to_string(),
+            Statement::Expression(expr) => expr.to_string(),
+            Statement::InlineAsm { .. } => "asm { ... }".to_string(),
+            Statement::Block(_) => "{ ... }".to_string(),
+        }
+    }
+}
+
+/// Bytes per element of an array declared with `element_type`.
+pub fn array_element_size(element_type: &str) -> usize {
+    match element_type {
+        "u8" | "byte" => 1,
+        _ => 8,
+    }
+}
+
+/// Whether every path through `body` ends in a `return` (or never finishes).
+pub fn always_returns(body: &[Statement]) -> bool {
+    body.iter().any(Statement::always_returns)
 }
 
 #[derive(Debug, Clone)]
@@ -106,6 +249,10 @@ pub enum Expression {
         function: String,
         args: Vec<Expression>,
     },
+    IndirectCall {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+    },
     ModuleCall {
         module: String,
         function: String,
@@ -119,6 +266,11 @@ pub enum Expression {
         string: Box<Expression>,
         index: Box<Expression>,
     },
+    Slice {
+        base: Box<Expression>,
+        start: Option<Box<Expression>>,
+        end: Option<Box<Expression>>,
+    },
     AddressOf {
         operand: Box<Expression>,
     },
@@ -128,6 +280,49 @@ pub enum Expression {
     Eval {
         instruction: Box<Expression>,
     },
+    Tuple(Vec<Expression>),
+}
+
+impl Expression {
+    /// Returns `k` when the expression is the constant `2^k` with `1 <= k <= 31`.
+    pub fn power_of_two_shift(&self) -> Option<u32> {
+        match self {
+            Expression::Number(n) if *n >= 2 && *n <= 1 << 31 && n.count_ones() == 1 => Some(n.trailing_zeros()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an integer literal, including a negated one.
+    pub fn constant_value(&self) -> Option<i64> {
+        match self {
+            Expression::Number(n) => Some(*n),
+            Expression::Unary { op: UnaryOp::Neg, operand } => operand.constant_value().map(i64::wrapping_neg),
+            _ => None,
+        }
+    }
+
+    /// Whether evaluating the expression may call a function or run raw code.
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Expression::Number(_) | Expression::String(_) | Expression::Identifier(_) => false,
+            Expression::Call { .. } | Expression::IndirectCall { .. } | Expression::ModuleCall { .. }
+            | Expression::Eval { .. } => true,
+            Expression::TemplateString { parts } => parts.iter().any(|part| match part {
+                TemplateStringPart::Expression { expr, .. } => expr.has_side_effects(),
+                TemplateStringPart::Literal(_) => false,
+            }),
+            Expression::Binary { left, right, .. } => left.has_side_effects() || right.has_side_effects(),
+            Expression::Unary { operand, .. } | Expression::AddressOf { operand } | Expression::Deref { operand } => {
+                operand.has_side_effects()
+            }
+            Expression::ArrayAccess { index, .. } => index.has_side_effects(),
+            Expression::StringIndex { string, index } => string.has_side_effects() || index.has_side_effects(),
+            Expression::Slice { base, start, end } => {
+                base.has_side_effects() || [start, end].into_iter().flatten().any(|bound| bound.has_side_effects())
+            }
+            Expression::Tuple(values) => values.iter().any(Expression::has_side_effects),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -148,10 +343,27 @@ pub enum AsmPart {
 #[derive(Debug, Clone)]
 pub struct FormatSpec {
     pub width: Option<usize>,
-    #[allow(dead_code)]
     pub precision: Option<usize>,
     pub format_type: FormatType,
     pub padding: char,
+    pub left_align: bool,
+}
+
+impl FormatSpec {
+    /// Formats a string value like C's `%s`: a precision truncates it, and it is padded with
+    /// spaces to the width, on the right for `-` and on the left otherwise.
+    pub fn pad(&self, text: &str) -> String {
+        let text: String = match self.precision {
+            Some(precision) => text.chars().take(precision).collect(),
+            None => text.to_string(),
+        };
+        let fill = " ".repeat(self.width.unwrap_or(0).saturating_sub(text.len()));
+        if self.left_align {
+            format!("{}{}", text, fill)
+        } else {
+            format!("{}{}", fill, text)
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -159,6 +371,7 @@ pub enum FormatType {
     Decimal,
     Hex,
     HexUpper,
+    Binary,
     String,
     Auto,
 }
@@ -186,3 +399,107 @@ pub enum UnaryOp {
     Neg,
     Not,
 }
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Concat => "++",
+        }
+    }
+}
+
+fn write_list(f: &mut std::fmt::Formatter, items: &[Expression]) -> std::fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn write_operand(f: &mut std::fmt::Formatter, operand: &Expression) -> std::fmt::Result {
+    match operand {
+        Expression::Binary { .. } => write!(f, "({})", operand),
+        _ => write!(f, "{}", operand),
+    }
+}
+
+/// Source-like rendering; nested binary operands are parenthesized rather than
+/// relying on precedence.
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Number(n) => write!(f, "{}", n),
+            Expression::String(s) => write!(f, "{:?}", s),
+            Expression::TemplateString { parts } => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match part {
+                        TemplateStringPart::Literal(text) => write!(f, "{}", text.escape_debug())?,
+                        TemplateStringPart::Expression { expr, .. } => write!(f, "$({})", expr)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Expression::Identifier(name) => write!(f, "{}", name),
+            Expression::Binary { op, left, right } => {
+                write_operand(f, left)?;
+                write!(f, " {} ", op.symbol())?;
+                write_operand(f, right)
+            }
+            Expression::Unary { op, operand } => {
+                write!(f, "{}", if *op == UnaryOp::Neg { "-" } else { "!" })?;
+                write_operand(f, operand)
+            }
+            Expression::Call { function, args } => {
+                write!(f, "{}(", function)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expression::IndirectCall { callee, args } => {
+                write!(f, "({})(", callee)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expression::ModuleCall { module, function, args } => {
+                write!(f, "{}.{}(", module, function)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expression::ArrayAccess { name, index } => write!(f, "{}[{}]", name, index),
+            Expression::StringIndex { string, index } => write!(f, "{}[{}]", string, index),
+            Expression::Slice { base, start, end } => {
+                write!(f, "{}[", base)?;
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, ":")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                write!(f, "]")
+            }
+            Expression::AddressOf { operand } => write!(f, "&{}", operand),
+            Expression::Deref { operand } => {
+                write!(f, "*")?;
+                write_operand(f, operand)
+            }
+            Expression::Eval { instruction } => write!(f, "eval({})", instruction),
+            Expression::Tuple(values) => write_list(f, values),
+        }
+    }
+}
@@ -0,0 +1,779 @@
+use crate::ast::*;
+use crate::error::{CompileError, ErrorKind, Result};
+use super::asm_generator::AsmGenerator;
+use std::collections::{HashMap, HashSet};
+
+const ARG_REGS: usize = 8;
+
+/// Frame space for one template string: it is formatted into the frame instead of the heap.
+const TEMPLATE_BUFFER: i32 = 1024;
+
+pub struct Arm64Generator {
+    output: String,
+    label_counter: usize,
+    string_literals: Vec<String>,
+    variables: HashMap<String, i32>,
+    stack_offset: i32,
+    string_vars: HashSet<String>,
+    user_functions: HashSet<String>,
+    module_functions: HashSet<String>,
+    /// User and module functions declared to return `string`, named like `module_functions`.
+    string_functions: HashSet<String>,
+    current_module: Option<String>,
+    current_function: String,
+    source: SourceCursor,
+    entry: String,
+    symbol_prefix: String,
+    entry_symbol: String,
+    error: Option<CompileError>,
+}
+
+impl Arm64Generator {
+    pub fn new() -> Self {
+        Arm64Generator {
+            output: String::new(),
+            label_counter: 0,
+            string_literals: Vec::new(),
+            variables: HashMap::new(),
+            stack_offset: 0,
+            string_vars: HashSet::new(),
+            user_functions: HashSet::new(),
+            module_functions: HashSet::new(),
+            string_functions: HashSet::new(),
+            current_module: None,
+            current_function: String::new(),
+            source: SourceCursor::default(),
+            entry: "main".to_string(),
+            symbol_prefix: String::new(),
+            entry_symbol: "main".to_string(),
+            error: None,
+        }
+    }
+
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
+    /// Prefixes user function labels with `prefix`, as `AsmGenerator::set_symbol_prefix` does.
+    pub fn set_symbol_prefix(&mut self, prefix: &str, mangle_entry: bool) {
+        self.symbol_prefix = prefix.to_string();
+        if mangle_entry {
+            self.entry_symbol = format!("{}main", prefix);
+        }
+    }
+
+    /// A function whose label would clash with the entry symbol, such as `fn main` under
+    /// `--entry kmain`, gets a `.user` suffix.
+    fn function_symbol(&self, name: &str) -> String {
+        if name == self.entry {
+            return self.entry_symbol.clone();
+        }
+        let symbol = format!("{}{}", self.symbol_prefix, name);
+        if symbol == self.entry_symbol {
+            format!("{}.user", symbol)
+        } else {
+            symbol
+        }
+    }
+
+    fn module_symbol(&self, module: &str, function: &str) -> String {
+        format!("{}{}_{}", self.symbol_prefix, module, function)
+    }
+
+    fn begin_stdio_function(&mut self, name: &str) {
+        let label = self.module_symbol("stdio", name);
+        self.output.push_str(&format!("    .globl {}\n", label));
+        self.output.push_str(&format!("{}:\n", label));
+    }
+
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    fn unsupported(&mut self, what: &str) {
+        self.fail(format!("the ARM64 target does not support {}", what));
+    }
+
+    fn next_label(&mut self) -> String {
+        let label = format!(".L{}", self.label_counter);
+        self.label_counter += 1;
+        label
+    }
+
+    /// Flushes stdout, prints `format` to stderr with `x2` as its optional argument, and exits with status 1.
+    fn generate_fatal(&mut self, format: &str) {
+        self.emit("mov     x19, x2");
+        self.emit("mov     x0, #0");
+        self.emit("bl      fflush");
+        self.emit("mov     x2, x19");
+        self.emit("adrp    x0, :got:stderr");
+        self.emit("ldr     x0, [x0, :got_lo12:stderr]");
+        self.emit("ldr     x0, [x0]");
+        self.load_string("x1", format);
+        self.emit("bl      fprintf");
+        self.emit("mov     x0, #1");
+        self.emit("bl      exit");
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        self.output.push_str("    ");
+        self.output.push_str(instruction);
+        self.output.push('\n');
+    }
+
+    fn push(&mut self, reg: &str) {
+        self.emit(&format!("str     {}, [sp, #-16]!", reg));
+    }
+
+    fn pop(&mut self, reg: &str) {
+        self.emit(&format!("ldr     {}, [sp], #16", reg));
+    }
+
+    /// `mov` covers 16-bit values; anything wider is built a halfword at a time.
+    fn immediate(reg: &str, value: i64) -> String {
+        if (-0x10000..=0xffff).contains(&value) {
+            return format!("    mov     {}, #{}\n", reg, value);
+        }
+        let bits = value as u64;
+        let mut code = format!("    movz    {}, #{}\n", reg, bits & 0xffff);
+        for shift in [16, 32, 48] {
+            let chunk = (bits >> shift) & 0xffff;
+            if chunk != 0 {
+                code.push_str(&format!("    movk    {}, #{}, lsl #{}\n", reg, chunk, shift));
+            }
+        }
+        code
+    }
+
+    fn load_immediate(&mut self, reg: &str, value: i64) {
+        let code = Self::immediate(reg, value);
+        self.output.push_str(&code);
+    }
+
+    fn load_address(&mut self, reg: &str, label: &str) {
+        self.emit(&format!("adrp    {}, {}", reg, label));
+        self.emit(&format!("add     {}, {}, :lo12:{}", reg, reg, label));
+    }
+
+    fn load_string(&mut self, reg: &str, s: &str) {
+        let idx = self.string_literals.len();
+        self.string_literals.push(s.to_string());
+        self.load_address(reg, &format!(".LS{}", idx));
+    }
+
+    /// Puts `x29 + offset` in `reg`, for frame offsets too far for an immediate.
+    fn frame_address(&mut self, reg: &str, offset: i32) {
+        let distance = -(offset as i64);
+        if distance <= 4095 {
+            self.emit(&format!("sub     {}, x29, #{}", reg, distance));
+        } else {
+            self.load_immediate(reg, distance);
+            self.emit(&format!("sub     {}, x29, {}", reg, reg));
+        }
+    }
+
+    /// The memory operand of a frame slot. `ldr`/`str` only reach 256 bytes
+    /// below the frame pointer, deeper slots go through `x9`.
+    fn slot(&mut self, offset: i32) -> String {
+        if offset >= -256 {
+            format!("[x29, #{}]", offset)
+        } else {
+            self.frame_address("x9", offset);
+            "[x9]".to_string()
+        }
+    }
+
+    fn emit_prologue(&mut self) {
+        self.emit("stp     x29, x30, [sp, #-16]!");
+        self.emit("mov     x29, sp");
+    }
+
+    fn emit_epilogue(&mut self) {
+        self.emit("mov     sp, x29");
+        self.emit("ldp     x29, x30, [sp], #16");
+        self.emit("ret");
+    }
+
+    pub fn generate(&mut self, program: &Program) -> Result<String> {
+        self.output.push_str("    .text\n");
+
+        self.user_functions = program.functions.iter().map(|f| f.name.clone()).collect();
+        self.module_functions = program.modules.iter()
+            .flat_map(|(module_name, module)| module.functions.iter().map(move |f| format!("{}_{}", module_name, f.name)))
+            .collect();
+        self.string_functions = program.string_functions();
+
+        for (module_name, module) in &program.modules {
+            if module_name == "stdio" || module_name == "sys" || module_name == "time" {
+                continue;
+            }
+            self.current_module = Some(module_name.clone());
+            for func in &module.functions {
+                let label = self.module_symbol(module_name, &func.name);
+                self.current_function = format!("{}.{}", module_name, func.name);
+                self.generate_function(&label, func, func.is_exported);
+            }
+            self.current_module = None;
+        }
+
+        for func in &program.functions {
+            if func.name != self.entry {
+                self.current_function = func.name.clone();
+                let label = self.function_symbol(&func.name);
+                self.generate_function(&label, func, true);
+            }
+        }
+
+        if program.modules.contains_key("stdio") {
+            self.generate_stdio_functions();
+        }
+
+        if let Some(main_func) = program.functions.iter().find(|f| f.name == self.entry) {
+            self.current_function = main_func.name.clone();
+            self.output.push_str(&format!("    .globl {}\n", self.entry_symbol));
+            self.output.push_str(&format!("{}:\n", self.entry_symbol));
+            self.emit_prologue();
+            let frame_pos = self.output.len();
+            self.source = SourceCursor::new(main_func);
+            self.variables.clear();
+            self.string_vars.clear();
+            self.stack_offset = 0;
+            for stmt in &main_func.body {
+                self.generate_statement(stmt);
+            }
+            self.reserve_frame(frame_pos);
+            self.emit("mov     x0, #0");
+            self.emit_epilogue();
+        }
+
+        if !self.string_literals.is_empty() {
+            self.output.push_str("\n    .section .rodata\n");
+            for (i, s) in self.string_literals.iter().enumerate() {
+                self.output.push_str(&format!(".LS{}:\n", i));
+                self.output.push_str(&format!("    .string \"{}\"\n", AsmGenerator::gas_escape(s)));
+            }
+        }
+
+        self.output.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self.output.clone()),
+        }
+    }
+
+    fn generate_function(&mut self, label: &str, func: &Function, global: bool) {
+        if func.params.iter().any(|p| p.is_variadic) {
+            self.unsupported("variadic parameters");
+        }
+        if func.params.len() > ARG_REGS {
+            self.fail(format!("function '{}' has more than {} parameters, which the ARM64 target does not support", label, ARG_REGS));
+        }
+
+        if global {
+            self.output.push_str(&format!("    .globl {}\n", label));
+        }
+        self.output.push_str(&format!("{}:\n", label));
+        self.emit_prologue();
+        let frame_pos = self.output.len();
+        self.source = SourceCursor::new(func);
+
+        self.variables.clear();
+        self.string_vars = func.params.iter().filter(|p| p.param_type == "string").map(|p| p.name.clone()).collect();
+        self.stack_offset = 0;
+        for (i, param) in func.params.iter().take(ARG_REGS).enumerate() {
+            self.stack_offset -= 8;
+            self.variables.insert(param.name.clone(), self.stack_offset);
+            self.emit(&format!("str     x{}, [x29, #{}]", i, self.stack_offset));
+        }
+
+        for stmt in &func.body {
+            self.generate_statement(stmt);
+        }
+        self.reserve_frame(frame_pos);
+
+        self.emit("mov     x0, #0");
+        self.emit_epilogue();
+        self.output.push('\n');
+    }
+
+    /// Inserts the frame reservation at `pos` once the body is generated. Scopes
+    /// never hand their slots back, so the final `stack_offset` is the deepest one.
+    fn reserve_frame(&mut self, pos: usize) {
+        let size = self.stack_offset.unsigned_abs().next_multiple_of(16) as i64;
+        if size == 0 {
+            return;
+        }
+        let reservation = if size <= 4095 {
+            format!("    sub     sp, sp, #{}\n", size)
+        } else {
+            format!("{}    sub     sp, sp, x9\n", Self::immediate("x9", size))
+        };
+        self.output.insert_str(pos, &reservation);
+    }
+
+    fn generate_stdio_functions(&mut self) {
+        for (name, format) in [("Println", "%ld\n"), ("Print", "%ld"), ("PrintStr", "%s"), ("PrintlnStr", "%s\n"), ("PrintPtr", "0x%016lx")] {
+            self.begin_stdio_function(name);
+            self.emit_prologue();
+            self.emit("mov     x1, x0");
+            self.load_string("x0", format);
+            self.emit("bl      printf");
+            self.emit("mov     x0, #0");
+            self.emit("ldp     x29, x30, [sp], #16");
+            self.emit("ret");
+            self.output.push('\n');
+        }
+
+        self.begin_stdio_function("PrintChar");
+        self.emit_prologue();
+        self.emit("bl      putchar");
+        self.emit("mov     x0, #0");
+        self.emit("ldp     x29, x30, [sp], #16");
+        self.emit("ret");
+        self.output.push('\n');
+
+        self.begin_stdio_function("ReadInt");
+        self.emit("stp     x29, x30, [sp, #-32]!");
+        self.emit("mov     x29, sp");
+        self.emit("str     xzr, [x29, #16]");
+        self.load_string("x0", "%ld");
+        self.emit("add     x1, x29, #16");
+        self.emit("bl      scanf");
+        self.emit("ldr     x0, [x29, #16]");
+        self.emit("ldp     x29, x30, [sp], #32");
+        self.emit("ret");
+        self.output.push('\n');
+
+        self.begin_stdio_function("ReadChar");
+        self.emit_prologue();
+        self.emit("bl      getchar");
+        self.emit("sxtw    x0, w0");
+        self.emit("ldp     x29, x30, [sp], #16");
+        self.emit("ret");
+        self.output.push('\n');
+
+        self.begin_stdio_function("ReadLine");
+        self.emit("stp     x29, x30, [sp, #-32]!");
+        self.emit("mov     x29, sp");
+        self.emit("str     x0, [x29, #16]");
+        self.emit("adrp    x2, :got:stdin");
+        self.emit("ldr     x2, [x2, :got_lo12:stdin]");
+        self.emit("ldr     x2, [x2]");
+        self.emit("bl      fgets");
+        self.emit("cbz     x0, .LReadLine_end");
+        self.emit("ldr     x0, [x29, #16]");
+        self.emit("bl      strlen");
+        self.output.push_str(".LReadLine_end:\n");
+        self.emit("ldp     x29, x30, [sp], #32");
+        self.emit("ret");
+        self.output.push('\n');
+
+        self.begin_stdio_function("Flush");
+        self.emit_prologue();
+        self.emit("adrp    x0, :got:stdout");
+        self.emit("ldr     x0, [x0, :got_lo12:stdout]");
+        self.emit("ldr     x0, [x0]");
+        self.emit("bl      fflush");
+        self.emit("mov     x0, #0");
+        self.emit("ldp     x29, x30, [sp], #16");
+        self.emit("ret");
+        self.output.push('\n');
+    }
+
+    /// Evaluates the arguments left to right onto the stack, then pops them into `x0`-`x7`.
+    fn generate_call_args(&mut self, args: &[Expression]) {
+        if args.len() > ARG_REGS {
+            self.fail(format!("calls with more than {} arguments are not supported on the ARM64 target", ARG_REGS));
+            return;
+        }
+        for arg in args {
+            self.generate_expression(arg);
+            self.push("x0");
+        }
+        for i in (0..args.len()).rev() {
+            self.pop(&format!("x{}", i));
+        }
+    }
+
+    fn is_builtin(&self, function: &str, builtin: &str, args: &[Expression], arity: usize) -> bool {
+        function == builtin && args.len() == arity && !self.user_functions.contains(function)
+    }
+
+    fn is_string_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::String(_) | Expression::TemplateString { .. } => true,
+            Expression::Identifier(name) => self.string_vars.contains(name),
+            Expression::Call { function, .. } => match &self.current_module {
+                Some(module) if self.module_functions.contains(&format!("{}_{}", module, function)) => {
+                    self.string_functions.contains(&format!("{}_{}", module, function))
+                }
+                _ => self.string_functions.contains(function),
+            },
+            Expression::ModuleCall { module, function, .. } => {
+                self.string_functions.contains(&format!("{}_{}", module, function))
+            }
+            _ => false,
+        }
+    }
+
+    /// Generates `body` in its own scope: declarations inside it are dropped at the closing brace.
+    fn generate_block(&mut self, body: &[Statement]) {
+        let saved_vars = self.variables.clone();
+        let saved_strings = self.string_vars.clone();
+        for stmt in body {
+            self.generate_statement(stmt);
+        }
+        self.variables = saved_vars;
+        self.string_vars = saved_strings;
+    }
+
+    fn generate_statement(&mut self, stmt: &Statement) {
+        self.source.advance();
+        match stmt {
+            Statement::VarDecl { name, var_type, value } => {
+                let is_string = var_type.as_deref() == Some("string")
+                    || value.as_ref().map(|v| self.is_string_expression(v)).unwrap_or(false);
+                if is_string {
+                    self.string_vars.insert(name.clone());
+                } else {
+                    self.string_vars.remove(name);
+                }
+
+                match value {
+                    Some(expr) => self.generate_expression(expr),
+                    None => self.emit("mov     x0, #0"),
+                }
+                self.stack_offset -= 8;
+                self.variables.insert(name.clone(), self.stack_offset);
+                let slot = self.slot(self.stack_offset);
+                self.emit(&format!("str     x0, {}", slot));
+            }
+            Statement::MultiVarDecl { names, value } => {
+                self.generate_expression(value);
+                for (name, reg) in names.iter().zip(["x0", "x1"]) {
+                    self.stack_offset -= 8;
+                    self.variables.insert(name.clone(), self.stack_offset);
+                    let slot = self.slot(self.stack_offset);
+                    self.emit(&format!("str     {}, {}", reg, slot));
+                }
+            }
+            Statement::Assignment { name, value } => {
+                self.generate_expression(value);
+                match self.variables.get(name).copied() {
+                    Some(offset) => {
+                        let slot = self.slot(offset);
+                        self.emit(&format!("str     x0, {}", slot));
+                    }
+                    None => self.fail(format!("Variable not found: {}", name)),
+                }
+            }
+            Statement::ArrayDecl { .. } | Statement::ArrayAssignment { .. } => self.unsupported("arrays"),
+            Statement::PointerAssignment { .. } => self.unsupported("pointers"),
+            Statement::InlineAsm { parts } => {
+                let mut asm_text = String::new();
+                for part in parts {
+                    match part {
+                        AsmPart::Literal(s) => asm_text.push_str(s),
+                        AsmPart::Variable(var_name) => {
+                            if let Some(&offset) = self.variables.get(var_name) {
+                                asm_text.push_str(&format!("[x29, #{}]", offset));
+                            } else {
+                                self.fail(format!("unknown variable '{}' in asm block", var_name));
+                            }
+                        }
+                    }
+                }
+
+                self.output.push_str("    // inline asm\n");
+                for line in asm_text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                    if line.ends_with(':') {
+                        self.output.push_str(&format!("{}\n", line));
+                    } else {
+                        self.emit(line);
+                    }
+                }
+            }
+            Statement::Return(expr) => {
+                if let Some(e) = expr {
+                    self.generate_expression(e);
+                } else {
+                    self.emit("mov     x0, #0");
+                }
+                self.emit_epilogue();
+            }
+            Statement::Expression(expr) => {
+                self.generate_expression(expr);
+            }
+            Statement::If { condition, then_body, else_body } => {
+                self.generate_expression(condition);
+                let else_label = self.next_label();
+                let end_label = self.next_label();
+
+                self.emit(&format!("cbz     x0, {}", else_label));
+                self.generate_block(then_body);
+                self.emit(&format!("b       {}", end_label));
+
+                self.output.push_str(&format!("{}:\n", else_label));
+                if let Some(body) = else_body {
+                    self.generate_block(body);
+                }
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            Statement::Block(body) => self.generate_block(body),
+            Statement::For { init, condition, post, body } => {
+                let saved_vars = self.variables.clone();
+                let saved_strings = self.string_vars.clone();
+                if let Some(init) = init {
+                    self.generate_statement(init);
+                }
+
+                let loop_label = self.next_label();
+                let end_label = self.next_label();
+                self.output.push_str(&format!("{}:\n", loop_label));
+                if let Some(cond) = condition {
+                    self.generate_expression(cond);
+                    self.emit(&format!("cbz     x0, {}", end_label));
+                }
+
+                self.generate_block(body);
+                if let Some(post) = post {
+                    self.generate_statement(post);
+                }
+
+                self.emit(&format!("b       {}", loop_label));
+                self.output.push_str(&format!("{}:\n", end_label));
+                self.variables = saved_vars;
+                self.string_vars = saved_strings;
+            }
+        }
+    }
+
+    fn generate_expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Tuple(values) => {
+                if let [first, second] = values.as_slice() {
+                    self.generate_expression(first);
+                    self.push("x0");
+                    self.generate_expression(second);
+                    self.emit("mov     x1, x0");
+                    self.pop("x0");
+                } else if let Some(first) = values.first() {
+                    self.generate_expression(first);
+                }
+            }
+            Expression::Number(n) => self.load_immediate("x0", *n),
+            Expression::Identifier(name) => match self.variables.get(name).copied() {
+                Some(offset) => {
+                    let slot = self.slot(offset);
+                    self.emit(&format!("ldr     x0, {}", slot));
+                }
+                None => self.fail(format!("Variable not found: {}", name)),
+            },
+            Expression::Binary { op: BinaryOp::Mul, left, .. } if self.is_string_expression(left) => {
+                self.unsupported("string repetition");
+            }
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                let short_label = self.next_label();
+                let end_label = self.next_label();
+                self.generate_expression(left);
+                let branch = if *op == BinaryOp::And { "cbz " } else { "cbnz" };
+                self.emit(&format!("{}    x0, {}", branch, short_label));
+                self.generate_expression(right);
+                self.emit("cmp     x0, #0");
+                self.emit("cset    x0, ne");
+                self.emit(&format!("b       {}", end_label));
+                self.output.push_str(&format!("{}:\n", short_label));
+                self.emit(if *op == BinaryOp::And { "mov     x0, #0" } else { "mov     x0, #1" });
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+            Expression::Binary { op, left, right } => {
+                self.generate_expression(left);
+                self.push("x0");
+                self.generate_expression(right);
+                self.emit("mov     x1, x0");
+                self.pop("x0");
+
+                let condition = match op {
+                    BinaryOp::Add => {
+                        self.emit("add     x0, x0, x1");
+                        None
+                    }
+                    BinaryOp::Sub => {
+                        self.emit("sub     x0, x0, x1");
+                        None
+                    }
+                    BinaryOp::Mul => {
+                        self.emit("mul     x0, x0, x1");
+                        None
+                    }
+                    BinaryOp::Div => {
+                        self.emit("sdiv    x0, x0, x1");
+                        None
+                    }
+                    BinaryOp::Mod => {
+                        self.emit("sdiv    x2, x0, x1");
+                        self.emit("msub    x0, x2, x1, x0");
+                        None
+                    }
+                    BinaryOp::Equal => Some("eq"),
+                    BinaryOp::NotEqual => Some("ne"),
+                    BinaryOp::Less => Some("lt"),
+                    BinaryOp::LessEqual => Some("le"),
+                    BinaryOp::Greater => Some("gt"),
+                    BinaryOp::GreaterEqual => Some("ge"),
+                    BinaryOp::Concat | BinaryOp::And | BinaryOp::Or => {
+                        self.unsupported("string concatenation");
+                        None
+                    }
+                };
+                if let Some(condition) = condition {
+                    self.emit("cmp     x0, x1");
+                    self.emit(&format!("cset    x0, {}", condition));
+                }
+            }
+            Expression::Unary { op, operand } => {
+                self.generate_expression(operand);
+                match op {
+                    UnaryOp::Neg => self.emit("neg     x0, x0"),
+                    UnaryOp::Not => {
+                        self.emit("cmp     x0, #0");
+                        self.emit("cset    x0, eq");
+                    }
+                }
+            }
+            Expression::Call { function, args }
+                if self.is_builtin(function, "min", args, 2) || self.is_builtin(function, "max", args, 2) =>
+            {
+                self.generate_expression(&args[0]);
+                self.push("x0");
+                self.generate_expression(&args[1]);
+                self.emit("mov     x1, x0");
+                self.pop("x0");
+                self.emit("cmp     x0, x1");
+                let condition = if function == "min" { "lt" } else { "gt" };
+                self.emit(&format!("csel    x0, x0, x1, {}", condition));
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "abs", args, 1) => {
+                self.generate_expression(&args[0]);
+                self.emit("cmp     x0, #0");
+                self.emit("cneg    x0, x0, lt");
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "assert", args, 1) => {
+                let ok = self.next_label();
+                self.generate_expression(&args[0]);
+                self.emit(&format!("cbnz    x0, {}", ok));
+                let message = format!("{}: assertion failed: {}\n", self.source.location(), args[0]);
+                self.generate_fatal(&message.replace('%', "%%"));
+                self.output.push_str(&format!("{}:\n", ok));
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "panic", args, 1) => {
+                self.generate_expression(&args[0]);
+                self.emit("mov     x2, x0");
+                self.generate_fatal("panic: %s\n");
+            }
+            Expression::Call { function, args }
+                if self.is_builtin(function, "itoa", args, 1) || self.is_builtin(function, "parseInt", args, 1) =>
+            {
+                self.unsupported(&format!("`{}`", function));
+            }
+            Expression::Call { function, args } => {
+                let symbol = match &self.current_module {
+                    Some(module) if self.module_functions.contains(&format!("{}_{}", module, function)) => {
+                        self.module_symbol(module, function)
+                    }
+                    _ if self.user_functions.contains(function) => self.function_symbol(function),
+                    _ => function.clone(),
+                };
+                self.generate_call_args(args);
+                self.emit(&format!("bl      {}", symbol));
+            }
+            Expression::ModuleCall { module, function, args } if module == "sys" && function == "Syscall" => {
+                self.generate_call_args(args);
+                self.emit("mov     x8, x0");
+                self.emit("mov     x0, x1");
+                self.emit("mov     x1, x2");
+                self.emit("mov     x2, x3");
+                self.emit("svc     #0");
+            }
+            Expression::ModuleCall { module, function, .. } if module == "time" => {
+                self.unsupported(&format!("time.{} (it reads the x86 time-stamp counter)", function));
+            }
+            Expression::ModuleCall { module, function, args } if module == "stdio" && function == "Printf" => {
+                self.generate_call_args(args);
+                self.emit("bl      printf");
+            }
+            Expression::ModuleCall { module, function, args } => {
+                let function = match function.as_str() {
+                    "Print" if module == "stdio" && args.len() == 1 && self.is_string_expression(&args[0]) => "PrintStr",
+                    "Println" if module == "stdio" && args.len() == 1 && self.is_string_expression(&args[0]) => "PrintlnStr",
+                    _ => function.as_str(),
+                };
+                self.generate_call_args(args);
+                let symbol = self.module_symbol(module, function);
+                self.emit(&format!("bl      {}", symbol));
+            }
+            Expression::String(s) => self.load_string("x0", s),
+            Expression::TemplateString { parts } => self.generate_template(parts),
+            Expression::ArrayAccess { .. } => self.unsupported("arrays"),
+            Expression::StringIndex { .. } | Expression::Slice { .. } => self.unsupported("string indexing"),
+            Expression::AddressOf { .. } | Expression::Deref { .. } | Expression::IndirectCall { .. } => {
+                self.unsupported("pointers");
+            }
+            Expression::Eval { instruction: _ } => self.emit("mov     x0, #0"),
+        }
+    }
+
+    /// Formats the template piece by piece into a buffer in the current frame
+    /// and leaves its address in `x0`.
+    fn generate_template(&mut self, parts: &[TemplateStringPart]) {
+        self.stack_offset -= TEMPLATE_BUFFER;
+        let buffer = self.stack_offset;
+        self.frame_address("x0", buffer);
+        self.emit("strb    wzr, [x0]");
+
+        for part in parts {
+            match part {
+                TemplateStringPart::Literal(lit) => {
+                    self.frame_address("x0", buffer);
+                    self.load_string("x1", lit);
+                    self.emit("bl      strcat");
+                }
+                TemplateStringPart::Expression { expr, format } => {
+                    let is_string_expr = self.is_string_expression(expr);
+                    if format.as_ref().is_some_and(|spec| spec.format_type == FormatType::Binary) {
+                        self.unsupported("the `b` template format");
+                        return;
+                    }
+
+                    self.generate_expression(expr);
+                    if is_string_expr && format.is_none() {
+                        self.emit("mov     x1, x0");
+                        self.frame_address("x0", buffer);
+                        self.emit("bl      strcat");
+                    } else {
+                        self.push("x0");
+                        self.frame_address("x0", buffer);
+                        self.emit("bl      strlen");
+                        self.frame_address("x1", buffer);
+                        self.emit("add     x0, x1, x0");
+                        self.load_string("x1", &AsmGenerator::template_format(format.as_ref(), is_string_expr));
+                        self.pop("x2");
+                        self.emit("bl      sprintf");
+                    }
+                }
+            }
+        }
+
+        self.frame_address("x0", buffer);
+    }
+}
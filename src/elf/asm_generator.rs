@@ -1,5 +1,8 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::error::{CompileError, ErrorKind, Result};
+use std::collections::{HashMap, HashSet};
+
+const ARG_REGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
 
 pub struct AsmGenerator {
     output: String,
@@ -7,6 +10,32 @@ pub struct AsmGenerator {
     string_literals: Vec<String>,
     variables: HashMap<String, i32>,
     stack_offset: i32,
+    variadic_functions: HashMap<String, usize>,
+    variadic_params: HashSet<String>,
+    string_vars: HashSet<String>,
+    /// Stack arrays in scope: name -> (length, element size).
+    arrays: HashMap<String, (usize, usize)>,
+    pointer_vars: HashSet<String>,
+    user_functions: HashSet<String>,
+    module_functions: HashSet<String>,
+    /// User and module functions declared to return `string`, named like `module_functions`.
+    string_functions: HashSet<String>,
+    current_module: Option<String>,
+    /// Assembly label of every generated function with its source name, for `--emit-map`.
+    function_labels: Vec<(String, String)>,
+    annotate: bool,
+    debug_info: bool,
+    /// Files named by `.file` directives so far; file number `n` is at index `n - 1`.
+    source_files: Vec<String>,
+    current_file: usize,
+    source: SourceCursor,
+    entry: String,
+    /// Prepended to user function labels by `--mangle`.
+    symbol_prefix: String,
+    entry_symbol: String,
+    /// Size of the `.bss` stack the entry point switches to, if one was requested.
+    stack_size: Option<usize>,
+    error: Option<CompileError>,
 }
 
 impl AsmGenerator {
@@ -17,31 +46,155 @@ impl AsmGenerator {
             string_literals: Vec::new(),
             variables: HashMap::new(),
             stack_offset: 0,
+            variadic_functions: HashMap::new(),
+            variadic_params: HashSet::new(),
+            string_vars: HashSet::new(),
+            arrays: HashMap::new(),
+            pointer_vars: HashSet::new(),
+            user_functions: HashSet::new(),
+            module_functions: HashSet::new(),
+            string_functions: HashSet::new(),
+            current_module: None,
+            function_labels: Vec::new(),
+            annotate: false,
+            debug_info: false,
+            source_files: Vec::new(),
+            current_file: 0,
+            source: SourceCursor::default(),
+            entry: "main".to_string(),
+            symbol_prefix: String::new(),
+            entry_symbol: "main".to_string(),
+            stack_size: None,
+            error: None,
+        }
+    }
+
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
+    /// Runs the program on a `size`-byte stack in `.bss` instead of the one the kernel set up.
+    pub fn set_stack_size(&mut self, size: usize) {
+        self.stack_size = Some(size.next_multiple_of(16));
+    }
+
+    /// Prefixes user function labels with `prefix`. The entry point keeps the `main` symbol
+    /// the C runtime calls unless `mangle_entry` is set.
+    pub fn set_symbol_prefix(&mut self, prefix: &str, mangle_entry: bool) {
+        self.symbol_prefix = prefix.to_string();
+        if mangle_entry {
+            self.entry_symbol = format!("{}main", prefix);
         }
     }
 
+    /// A function whose label would clash with the entry symbol, such as `fn main` under
+    /// `--entry kmain`, gets a `.user` suffix.
+    fn function_symbol(&self, name: &str) -> String {
+        if name == self.entry {
+            return self.entry_symbol.clone();
+        }
+        let symbol = format!("{}{}", self.symbol_prefix, name);
+        if symbol == self.entry_symbol {
+            format!("{}.user", symbol)
+        } else {
+            symbol
+        }
+    }
+
+    fn module_symbol(&self, module: &str, function: &str) -> String {
+        format!("{}{}_{}", self.symbol_prefix, module, function)
+    }
+
+    fn begin_stdio_function(&mut self, name: &str) {
+        let label = self.module_symbol("stdio", name);
+        self.output.push_str(&format!("    .globl {}\n", label));
+        self.output.push_str(&format!("{}:\n", label));
+    }
+
+    /// Precedes each statement's instructions with a `#` comment showing its source.
+    pub fn set_annotate(&mut self, annotate: bool) {
+        self.annotate = annotate;
+    }
+
+    /// Emits `.file`/`.loc` directives so the assembler can build a DWARF line table for the source.
+    pub fn set_debug_info(&mut self, debug_info: bool) {
+        self.debug_info = debug_info;
+    }
+
+    pub fn function_map(&self) -> &[(String, String)] {
+        &self.function_labels
+    }
+
     fn next_label(&mut self) -> String {
         let label = format!(".L{}", self.label_counter);
         self.label_counter += 1;
         label
     }
 
-    pub fn generate(&mut self, program: &Program) -> String {
+    /// Flushes stdout, prints `format` to stderr with `%rdx` as its optional argument, and exits with status 1.
+    fn generate_fatal(&mut self, format: String) {
+        let fmt = self.string_literals.len();
+        self.string_literals.push(format);
+        self.output.push_str("    movq    %rdx, %rbx\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
+        self.output.push_str("    xorl    %edi, %edi\n");
+        self.output.push_str("    call    fflush@PLT\n");
+        self.output.push_str("    movq    %rbx, %rdx\n");
+        self.output.push_str("    movq    stderr@GOTPCREL(%rip), %rax\n");
+        self.output.push_str("    movq    (%rax), %rdi\n");
+        self.output.push_str(&format!("    leaq    .LS{}(%rip), %rsi\n", fmt));
+        self.output.push_str("    xorl    %eax, %eax\n");
+        self.output.push_str("    call    fprintf@PLT\n");
+        self.output.push_str("    movl    $1, %edi\n");
+        self.output.push_str("    call    exit@PLT\n");
+    }
+
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    pub fn generate(&mut self, program: &Program) -> Result<String> {
         self.output.push_str("    .text\n");
 
+        self.user_functions = program.functions.iter().map(|f| f.name.clone()).collect();
+        self.module_functions = program.modules.iter()
+            .flat_map(|(module_name, module)| module.functions.iter().map(move |f| format!("{}_{}", module_name, f.name)))
+            .collect();
+        self.string_functions = program.string_functions();
+
+        for func in &program.functions {
+            if func.params.last().map(|p| p.is_variadic).unwrap_or(false) {
+                self.variadic_functions.insert(self.function_symbol(&func.name), func.params.len() - 1);
+            }
+        }
+        for (module_name, module) in &program.modules {
+            for func in &module.functions {
+                if func.params.last().map(|p| p.is_variadic).unwrap_or(false) {
+                    self.variadic_functions.insert(self.module_symbol(module_name, &func.name), func.params.len() - 1);
+                }
+            }
+        }
+
         for (module_name, module) in &program.modules {
-            if module_name == "stdio" {
+            if module_name == "stdio" || module_name == "sys" || module_name == "time" {
                 continue;
             }
             for func in &module.functions {
-                if func.is_exported {
-                    self.generate_module_function(module_name, func);
-                }
+                self.generate_module_function(module_name, func);
             }
         }
 
         for func in &program.functions {
-            if func.name != "main" {
+            if func.name != self.entry {
                 self.generate_user_function(func);
             }
         }
@@ -50,18 +203,35 @@ impl AsmGenerator {
             self.generate_stdio_functions();
         }
 
-        self.output.push_str("    .globl main\n");
-        self.output.push_str("main:\n");
+        let main_func = program.functions.iter().find(|f| f.name == self.entry);
+
+        self.output.push_str(&format!("    .globl {}\n", self.entry_symbol));
+        self.output.push_str(&format!("{}:\n", self.entry_symbol));
+        self.function_labels.push((self.entry_symbol.clone(), self.entry.clone()));
+        if let Some(size) = self.stack_size {
+            self.output.push_str("    pushq   %rbp\n");
+            self.output.push_str("    movq    %rsp, %rbp\n");
+            self.output.push_str(&format!("    leaq    .Lstack+{}(%rip), %rsp\n", size));
+            self.output.push_str("    call    .Lentry\n");
+            self.output.push_str("    leave\n");
+            self.output.push_str("    ret\n");
+            self.output.push_str(".Lentry:\n");
+        }
+        if let Some(main_func) = main_func {
+            self.begin_source(main_func);
+        }
 
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
-        self.output.push_str("    subq    $64, %rsp\n");
+        let frame_pos = self.output.len();
+        self.stack_offset = 0;
 
-        if let Some(main_func) = program.functions.iter().find(|f| f.name == "main") {
+        if let Some(main_func) = main_func {
             for stmt in &main_func.body {
                 self.generate_statement(stmt);
             }
         }
+        self.reserve_frame(frame_pos);
 
         self.output.push_str("    movl    $0, %eax\n");
         self.output.push_str("    leave\n");
@@ -71,22 +241,38 @@ impl AsmGenerator {
             self.output.push_str("\n    .section .rodata\n");
             for (i, s) in self.string_literals.iter().enumerate() {
                 self.output.push_str(&format!(".LS{}:\n", i));
-                self.output.push_str(&format!("    .string \"{}\"\n", s));
+                self.output.push_str(&format!("    .string \"{}\"\n", Self::gas_escape(s)));
             }
         }
 
-        self.output.clone()
+        if let Some(size) = self.stack_size {
+            self.output.push_str("\n    .bss\n");
+            self.output.push_str("    .balign 16\n");
+            self.output.push_str(".Lstack:\n");
+            self.output.push_str(&format!("    .skip {}\n", size));
+        }
+
+        self.output.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.output = super::peephole::optimize(&self.output);
+        Ok(self.output.clone())
     }
 
     fn generate_stdio_functions(&mut self) {
+        if self.debug_info && self.current_file > 0 {
+            self.output.push_str(&format!("    .loc {} 0\n", self.current_file));
+        }
 
-        self.output.push_str("    .globl stdio_Println\n");
-        self.output.push_str("stdio_Println:\n");
+        self.begin_stdio_function("Println");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    %rdi, %rsi\n");
         let idx1 = self.string_literals.len();
-        self.string_literals.push("%ld\\n".to_string());
+        self.string_literals.push("%ld\n".to_string());
         self.output.push_str(&format!("    leaq    .LS{}(%rip), %rdi\n", idx1));
         self.output.push_str("    xorl    %eax, %eax\n");
         self.output.push_str("    call    printf@PLT\n");
@@ -94,10 +280,10 @@ impl AsmGenerator {
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_Print\n");
-        self.output.push_str("stdio_Print:\n");
+        self.begin_stdio_function("Print");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    %rdi, %rsi\n");
         let idx2 = self.string_literals.len();
         self.string_literals.push("%ld".to_string());
@@ -108,10 +294,10 @@ impl AsmGenerator {
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_PrintStr\n");
-        self.output.push_str("stdio_PrintStr:\n");
+        self.begin_stdio_function("PrintStr");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    %rdi, %rsi\n");
         let idx3 = self.string_literals.len();
         self.string_literals.push("%s".to_string());
@@ -122,13 +308,13 @@ impl AsmGenerator {
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_PrintlnStr\n");
-        self.output.push_str("stdio_PrintlnStr:\n");
+        self.begin_stdio_function("PrintlnStr");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    %rdi, %rsi\n");
         let idx4 = self.string_literals.len();
-        self.string_literals.push("%s\\n".to_string());
+        self.string_literals.push("%s\n".to_string());
         self.output.push_str(&format!("    leaq    .LS{}(%rip), %rdi\n", idx4));
         self.output.push_str("    xorl    %eax, %eax\n");
         self.output.push_str("    call    printf@PLT\n");
@@ -136,20 +322,34 @@ impl AsmGenerator {
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_PrintChar\n");
-        self.output.push_str("stdio_PrintChar:\n");
+        self.begin_stdio_function("PrintPtr");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
+        self.output.push_str("    movq    %rdi, %rsi\n");
+        let idx_ptr = self.string_literals.len();
+        self.string_literals.push("0x%016lx".to_string());
+        self.output.push_str(&format!("    leaq    .LS{}(%rip), %rdi\n", idx_ptr));
+        self.output.push_str("    xorl    %eax, %eax\n");
+        self.output.push_str("    call    printf@PLT\n");
+        self.output.push_str("    xorl    %eax, %eax\n");
+        self.output.push_str("    leave\n");
+        self.output.push_str("    ret\n\n");
+
+        self.begin_stdio_function("PrintChar");
+        self.output.push_str("    pushq   %rbp\n");
+        self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movl    %edi, %edi\n");
         self.output.push_str("    call    putchar@PLT\n");
         self.output.push_str("    xorl    %eax, %eax\n");
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_ReadInt\n");
-        self.output.push_str("stdio_ReadInt:\n");
+        self.begin_stdio_function("ReadInt");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    subq    $16, %rsp\n");
         let idx5 = self.string_literals.len();
         self.string_literals.push("%ld".to_string());
@@ -161,20 +361,20 @@ impl AsmGenerator {
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_ReadChar\n");
-        self.output.push_str("stdio_ReadChar:\n");
+        self.begin_stdio_function("ReadChar");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    call    getchar@PLT\n");
         self.output.push_str("    cltq\n");
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_ReadLine\n");
-        self.output.push_str("stdio_ReadLine:\n");
+        self.begin_stdio_function("ReadLine");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
         self.output.push_str("    pushq   %rbx\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    %rdi, %rbx\n");
         self.output.push_str("    movq    %rsi, %rdx\n");
         self.output.push_str("    movq    %rbx, %rdi\n");
@@ -189,14 +389,14 @@ impl AsmGenerator {
         self.output.push_str(".LReadLine_fail:\n");
         self.output.push_str("    xorl    %eax, %eax\n");
         self.output.push_str(".LReadLine_end:\n");
-        self.output.push_str("    popq    %rbx\n");
+        self.output.push_str("    movq    -8(%rbp), %rbx\n");
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
 
-        self.output.push_str("    .globl stdio_Flush\n");
-        self.output.push_str("stdio_Flush:\n");
+        self.begin_stdio_function("Flush");
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
+        self.output.push_str("    andq    $-16, %rsp\n");
         self.output.push_str("    movq    stdout@GOTPCREL(%rip), %rax\n");
         self.output.push_str("    movq    (%rax), %rdi\n");
         self.output.push_str("    call    fflush@PLT\n");
@@ -206,36 +406,48 @@ impl AsmGenerator {
     }
 
     fn generate_user_function(&mut self, func: &Function) {
-        self.output.push_str(&format!("    .globl {}\n", func.name));
-        self.output.push_str(&format!("{}:\n", func.name));
+        let label = self.function_symbol(&func.name);
+        self.output.push_str(&format!("    .globl {}\n", label));
+        self.output.push_str(&format!("{}:\n", label));
+        self.function_labels.push((label, func.name.clone()));
+        self.begin_source(func);
 
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
-        self.output.push_str("    subq    $64, %rsp\n");
+        let frame_pos = self.output.len();
+        self.output.push_str("    andq    $-16, %rsp\n");
 
-        let arg_regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-        let mut local_vars = HashMap::new();
-        let mut local_offset = 0i32;
-
-        for (i, param) in func.params.iter().enumerate() {
-            if i < arg_regs.len() {
-                local_offset -= 8;
-                local_vars.insert(param.name.clone(), local_offset);
-                self.output.push_str(&format!("    movq    {}, {}(%rbp)\n", arg_regs[i], local_offset));
-            }
-        }
+        let (local_vars, local_offset) = self.bind_params(&func.params);
 
         let saved_vars = self.variables.clone();
         let saved_offset = self.stack_offset;
+        let saved_variadic = std::mem::replace(
+            &mut self.variadic_params,
+            func.params.iter().filter(|p| p.is_variadic).map(|p| p.name.clone()).collect(),
+        );
+        let saved_strings = std::mem::replace(
+            &mut self.string_vars,
+            func.params.iter().filter(|p| p.param_type == "string").map(|p| p.name.clone()).collect(),
+        );
+        let saved_arrays = std::mem::take(&mut self.arrays);
+        let saved_pointers = std::mem::replace(
+            &mut self.pointer_vars,
+            func.params.iter().filter(|p| p.param_type.starts_with('*')).map(|p| p.name.clone()).collect(),
+        );
         self.variables = local_vars;
         self.stack_offset = local_offset;
 
         for stmt in &func.body {
             self.generate_statement(stmt);
         }
+        self.reserve_frame(frame_pos);
 
         self.variables = saved_vars;
         self.stack_offset = saved_offset;
+        self.variadic_params = saved_variadic;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
 
         self.output.push_str("    movl    $0, %eax\n");
         self.output.push_str("    leave\n");
@@ -243,60 +455,371 @@ impl AsmGenerator {
     }
 
     fn generate_module_function(&mut self, module_name: &str, func: &Function) {
-        self.output.push_str(&format!("    .globl {}_{}\n", module_name, func.name));
-        self.output.push_str(&format!("{}_{}", module_name, func.name));
-        self.output.push_str(":\n");
+        let label = self.module_symbol(module_name, &func.name);
+        if func.is_exported {
+            self.output.push_str(&format!("    .globl {}\n", label));
+        }
+        self.output.push_str(&format!("{}:\n", label));
+        self.function_labels.push((label, format!("{}.{}", module_name, func.name)));
+        self.begin_source(func);
 
         self.output.push_str("    pushq   %rbp\n");
         self.output.push_str("    movq    %rsp, %rbp\n");
-        self.output.push_str("    subq    $64, %rsp\n");
-
-        let arg_regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-        let mut local_vars = HashMap::new();
-        let mut local_offset = 0i32;
+        let frame_pos = self.output.len();
+        self.output.push_str("    andq    $-16, %rsp\n");
 
-        for (i, param) in func.params.iter().enumerate() {
-            if i < arg_regs.len() {
-                local_offset -= 8;
-                local_vars.insert(param.name.clone(), local_offset);
-                self.output.push_str(&format!("    movq    {}, {}(%rbp)\n", arg_regs[i], local_offset));
-            }
-        }
+        let (local_vars, local_offset) = self.bind_params(&func.params);
 
         let saved_vars = self.variables.clone();
         let saved_offset = self.stack_offset;
+        let saved_variadic = std::mem::replace(
+            &mut self.variadic_params,
+            func.params.iter().filter(|p| p.is_variadic).map(|p| p.name.clone()).collect(),
+        );
+        let saved_strings = std::mem::replace(
+            &mut self.string_vars,
+            func.params.iter().filter(|p| p.param_type == "string").map(|p| p.name.clone()).collect(),
+        );
+        let saved_arrays = std::mem::take(&mut self.arrays);
+        let saved_pointers = std::mem::replace(
+            &mut self.pointer_vars,
+            func.params.iter().filter(|p| p.param_type.starts_with('*')).map(|p| p.name.clone()).collect(),
+        );
         self.variables = local_vars;
         self.stack_offset = local_offset;
+        self.current_module = Some(module_name.to_string());
 
         for stmt in &func.body {
             self.generate_statement(stmt);
         }
+        self.reserve_frame(frame_pos);
 
+        self.current_module = None;
         self.variables = saved_vars;
         self.stack_offset = saved_offset;
+        self.variadic_params = saved_variadic;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
 
         self.output.push_str("    movl    $0, %eax\n");
         self.output.push_str("    leave\n");
         self.output.push_str("    ret\n\n");
     }
 
+    /// Starts the line table for `func` at its `fn` line, naming its file on first use.
+    fn begin_source(&mut self, func: &Function) {
+        self.source = SourceCursor::new(func);
+        if !self.debug_info {
+            return;
+        }
+        self.current_file = match self.source_files.iter().position(|f| *f == func.file) {
+            Some(index) => index + 1,
+            None => {
+                self.source_files.push(func.file.clone());
+                self.output.push_str(&format!("    .file {} \"{}\"\n", self.source_files.len(), Self::gas_escape(&func.file)));
+                self.source_files.len()
+            }
+        };
+        self.output.push_str(&format!("    .loc {} {}\n", self.current_file, func.line));
+    }
+
+    /// Inserts the frame reservation at `pos` once the body is generated. Scopes
+    /// never hand their slots back, so the final `stack_offset` is the deepest one.
+    fn reserve_frame(&mut self, pos: usize) {
+        let size = self.stack_offset.unsigned_abs().next_multiple_of(16);
+        if size > 0 {
+            self.output.insert_str(pos, &format!("    subq    ${}, %rsp\n", size));
+        }
+    }
+
+    /// Spills the register parameters below `%rbp`; the 7th and later parameters
+    /// are read in place from the caller's frame above the return address.
+    fn bind_params(&mut self, params: &[Parameter]) -> (HashMap<String, i32>, i32) {
+        let mut local_vars = HashMap::new();
+        let mut local_offset = 0i32;
+
+        for (i, param) in params.iter().enumerate() {
+            match ARG_REGS.get(i) {
+                Some(reg) => {
+                    local_offset -= 8;
+                    local_vars.insert(param.name.clone(), local_offset);
+                    self.output.push_str(&format!("    movq    {}, {}(%rbp)\n", reg, local_offset));
+                }
+                None => {
+                    local_vars.insert(param.name.clone(), 16 + 8 * (i - ARG_REGS.len()) as i32);
+                }
+            }
+        }
+
+        (local_vars, local_offset)
+    }
+
+    /// Loads call arguments into the argument registers, evaluating them left to right.
+    /// Arguments past the sixth go on the stack as the System V ABI lays them out.
+    /// For a variadic callee the trailing arguments are packed on the stack as
+    /// `[count, arg1, .., argN]` and a pointer to that block is passed in place of the
+    /// variadic parameter. Returns the number of bytes the caller must pop after the call.
+    fn generate_call_args(&mut self, args: &[Expression], variadic_fixed: Option<usize>) -> usize {
+        let split = variadic_fixed.map_or(args.len(), |fixed| fixed.min(args.len()));
+        let (fixed, extra) = args.split_at(split);
+
+        let total = fixed.len() + usize::from(variadic_fixed.is_some());
+        let reg_count = total.min(ARG_REGS.len());
+        let stack_size = (total - reg_count).next_multiple_of(2) * 8;
+        let block_size = match variadic_fixed {
+            Some(_) => (extra.len() + 1).next_multiple_of(2) * 8,
+            None => 0,
+        };
+
+        if block_size > 0 {
+            self.output.push_str(&format!("    subq    ${}, %rsp\n", block_size));
+            self.output.push_str(&format!("    movq    ${}, (%rsp)\n", extra.len()));
+        }
+        if stack_size > 0 {
+            self.output.push_str(&format!("    subq    ${}, %rsp\n", stack_size));
+        }
+
+        for (i, arg) in fixed.iter().enumerate() {
+            self.generate_expression(arg);
+            self.pass_argument(i, reg_count);
+        }
+
+        if variadic_fixed.is_some() {
+            let block = reg_count.min(fixed.len()) * 8 + stack_size;
+            for (i, arg) in extra.iter().enumerate() {
+                self.generate_expression(arg);
+                self.output.push_str(&format!("    movq    %rax, {}(%rsp)\n", block + (i + 1) * 8));
+            }
+            self.output.push_str(&format!("    leaq    {}(%rsp), %rax\n", block));
+            self.pass_argument(fixed.len(), reg_count);
+        }
+
+        for reg in ARG_REGS.iter().take(reg_count).rev() {
+            self.output.push_str(&format!("    popq    {}\n", reg));
+        }
+
+        stack_size + block_size
+    }
+
+    /// Places `%rax` as argument `index`: register arguments are pushed until they are
+    /// popped into place, stack arguments are stored below the pushed ones.
+    fn pass_argument(&mut self, index: usize, reg_count: usize) {
+        if index < reg_count {
+            self.output.push_str("    pushq   %rax\n");
+        } else {
+            let offset = (reg_count + index - ARG_REGS.len()) * 8;
+            self.output.push_str(&format!("    movq    %rax, {}(%rsp)\n", offset));
+        }
+    }
+
+    fn is_builtin(&self, function: &str, builtin: &str, args: &[Expression], arity: usize) -> bool {
+        function == builtin && args.len() == arity && !self.user_functions.contains(function)
+    }
+
+    fn generate_string_index(&mut self, string: &Expression, index: &Expression) {
+        self.generate_expression(string);
+        self.output.push_str("    pushq   %rax\n");
+        self.generate_expression(index);
+        self.output.push_str("    popq    %rcx\n");
+        self.output.push_str("    addq    %rax, %rcx\n");
+        self.output.push_str("    movzbq  (%rcx), %rax\n");
+    }
+
+    fn is_string_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::String(_) | Expression::TemplateString { .. } | Expression::Slice { .. } => true,
+            Expression::Identifier(name) => self.string_vars.contains(name),
+            Expression::Call { function, args } => match &self.current_module {
+                Some(module) if self.module_functions.contains(&format!("{}_{}", module, function)) => {
+                    self.string_functions.contains(&format!("{}_{}", module, function))
+                }
+                _ => self.is_builtin(function, "itoa", args, 1) || self.string_functions.contains(function),
+            },
+            Expression::ModuleCall { module, function, .. } => {
+                self.string_functions.contains(&format!("{}_{}", module, function))
+            }
+            Expression::Binary { op: BinaryOp::Mul, left, .. } => self.is_string_expression(left),
+            _ => false,
+        }
+    }
+
+    /// Pointers step over 8-byte elements, so `p + n` advances by `n * 8` bytes.
+    fn is_pointer_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::AddressOf { .. } => true,
+            Expression::Identifier(name) => self.pointer_vars.contains(name),
+            Expression::Binary { op: BinaryOp::Add | BinaryOp::Sub, left, right } => {
+                self.is_pointer_expression(left) != self.is_pointer_expression(right)
+            }
+            _ => false,
+        }
+    }
+
+    /// Quotes `s` for a GAS `.string` directive, byte by byte.
+    pub(super) fn gas_escape(s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for byte in s.bytes() {
+            match byte {
+                b'"' => escaped.push_str("\\\""),
+                b'\\' => escaped.push_str("\\\\"),
+                b'\n' => escaped.push_str("\\n"),
+                b'\t' => escaped.push_str("\\t"),
+                0x20..=0x7e => escaped.push(byte as char),
+                _ => escaped.push_str(&format!("\\{:03o}", byte)),
+            }
+        }
+        escaped
+    }
+
+    /// Writes %rax in base 2, zero-filled to `width` digits, into the upper half of the
+    /// template scratch buffer at %r14 and leaves the start of the digits in %rsi.
+    fn generate_binary_digits(&mut self, width: usize) {
+        let digit_label = self.next_label();
+        let pad_label = self.next_label();
+        let done_label = self.next_label();
+
+        self.output.push_str("    leaq    255(%r14), %rsi\n");
+        self.output.push_str("    movb    $0, (%rsi)\n");
+        self.output.push_str("    movq    %rax, %rcx\n");
+        self.output.push_str("    xorl    %edx, %edx\n");
+        self.output.push_str(&format!("{}:\n", digit_label));
+        self.output.push_str("    decq    %rsi\n");
+        self.output.push_str("    movl    %ecx, %eax\n");
+        self.output.push_str("    andl    $1, %eax\n");
+        self.output.push_str("    addl    $48, %eax\n");
+        self.output.push_str("    movb    %al, (%rsi)\n");
+        self.output.push_str("    incq    %rdx\n");
+        self.output.push_str("    shrq    $1, %rcx\n");
+        self.output.push_str(&format!("    jnz     {}\n", digit_label));
+        self.output.push_str(&format!("{}:\n", pad_label));
+        self.output.push_str(&format!("    cmpq    ${}, %rdx\n", width.min(126)));
+        self.output.push_str(&format!("    jge     {}\n", done_label));
+        self.output.push_str("    decq    %rsi\n");
+        self.output.push_str("    movb    $48, (%rsi)\n");
+        self.output.push_str("    incq    %rdx\n");
+        self.output.push_str(&format!("    jmp     {}\n", pad_label));
+        self.output.push_str(&format!("{}:\n", done_label));
+    }
+
+    /// The `sprintf` conversion for one template interpolation. Binary digits arrive as a string.
+    pub(super) fn template_format(spec: Option<&crate::ast::FormatSpec>, is_string: bool) -> String {
+        use crate::ast::FormatType;
+
+        let Some(spec) = spec else {
+            return if is_string { "%s" } else { "%ld" }.to_string();
+        };
+        let conversion = match spec.format_type {
+            FormatType::Hex => "lx",
+            FormatType::HexUpper => "lX",
+            FormatType::Decimal => "ld",
+            FormatType::String | FormatType::Binary => "s",
+            FormatType::Auto if is_string => "s",
+            FormatType::Auto => "ld",
+        };
+        let flag = if spec.left_align {
+            "-"
+        } else if spec.padding == '0' && conversion != "s" {
+            "0"
+        } else {
+            ""
+        };
+        let width = spec.width.map(|w| w.to_string()).unwrap_or_default();
+        let precision = match spec.precision {
+            Some(p) if spec.format_type != FormatType::Binary => format!(".{}", p),
+            _ => String::new(),
+        };
+        format!("%{}{}{}{}", flag, width, precision, conversion)
+    }
+
+    /// Generates `body` in its own scope: declarations inside it are dropped at the closing brace.
+    fn generate_block(&mut self, body: &[Statement]) {
+        let saved_vars = self.variables.clone();
+        let saved_strings = self.string_vars.clone();
+        let saved_arrays = self.arrays.clone();
+        let saved_pointers = self.pointer_vars.clone();
+        for stmt in body {
+            self.generate_statement(stmt);
+        }
+        self.variables = saved_vars;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
+    }
+
+    fn is_byte_array(&self, name: &str) -> bool {
+        matches!(self.arrays.get(name), Some(&(_, 1)))
+    }
+
+    /// Turns a negative index in %rax into one counted from the end of the array.
+    fn wrap_array_index(&mut self, name: &str) {
+        if let Some(&(length, _)) = self.arrays.get(name) {
+            self.output.push_str(&format!("    leaq    {}(%rax), %rcx\n", length));
+            self.output.push_str("    testq   %rax, %rax\n");
+            self.output.push_str("    cmovsq  %rcx, %rax\n");
+        }
+    }
+
     fn generate_statement(&mut self, stmt: &Statement) {
+        if let Some(line) = self.source.advance() {
+            if self.debug_info {
+                self.output.push_str(&format!("    .loc {} {}\n", self.current_file, line));
+            }
+        }
+        if self.annotate {
+            self.output.push_str(&format!("    # {}\n", stmt.summary()));
+        }
         match stmt {
-            Statement::VarDecl { name, var_type: _, value } => {
-                if let Some(expr) = value {
-                    self.generate_expression(expr);
+            Statement::VarDecl { name, var_type, value } => {
+                let is_string = var_type.as_deref() == Some("string")
+                    || value.as_ref().map(|v| self.is_string_expression(v)).unwrap_or(false);
+                if is_string {
+                    self.string_vars.insert(name.clone());
+                } else {
+                    self.string_vars.remove(name);
+                }
+                self.arrays.remove(name);
+                let is_pointer = var_type.as_deref().is_some_and(|t| t.starts_with('*'))
+                    || value.as_ref().is_some_and(|v| self.is_pointer_expression(v));
+                if is_pointer {
+                    self.pointer_vars.insert(name.clone());
+                } else {
+                    self.pointer_vars.remove(name);
+                }
+
+                match value {
+                    Some(expr) => self.generate_expression(expr),
+                    None => self.output.push_str("    xorl    %eax, %eax\n"),
+                }
+                self.stack_offset -= 8;
+                self.variables.insert(name.clone(), self.stack_offset);
+                self.output.push_str(&format!("    movq    %rax, {}(%rbp)\n", self.stack_offset));
+            }
+            Statement::MultiVarDecl { names, value } => {
+                self.generate_expression(value);
+                for (name, reg) in names.iter().zip(["%rax", "%rdx"]) {
                     self.stack_offset -= 8;
                     self.variables.insert(name.clone(), self.stack_offset);
-                    self.output.push_str(&format!("    movq    %rax, {}(%rbp)\n", self.stack_offset));
+                    self.output.push_str(&format!("    movq    {}, {}(%rbp)\n", reg, self.stack_offset));
                 }
             }
-            Statement::ArrayDecl { name, element_type: _, size } => {
-                let array_size = (*size as i32) * 8;
-                self.stack_offset -= array_size;
+            Statement::ArrayDecl { name, element_type, size } => {
+                let element_size = array_element_size(element_type);
+                let words = (size * element_size).div_ceil(8);
+                self.stack_offset -= words as i32 * 8;
                 self.variables.insert(name.clone(), self.stack_offset);
-                for i in 0..*size {
-                    let offset = self.stack_offset + (i as i32 * 8);
-                    self.output.push_str(&format!("    movq    $0, {}(%rbp)\n", offset));
+                self.arrays.insert(name.clone(), (*size, element_size));
+                self.pointer_vars.remove(name);
+                if words <= 2 {
+                    for i in 0..words {
+                        let offset = self.stack_offset + (i as i32 * 8);
+                        self.output.push_str(&format!("    movq    $0, {}(%rbp)\n", offset));
+                    }
+                } else {
+                    self.output.push_str(&format!("    leaq    {}(%rbp), %rdi\n", self.stack_offset));
+                    self.output.push_str(&format!("    movl    ${}, %ecx\n", words));
+                    self.output.push_str("    xorl    %eax, %eax\n");
+                    self.output.push_str("    rep stosq\n");
                 }
             }
             Statement::Assignment { name, value } => {
@@ -317,34 +840,59 @@ impl AsmGenerator {
 
             Statement::InlineAsm { parts } => {
                 use crate::ast::AsmPart;
-                
-                self.output.push_str("    # inline asm\n");
-                
+
+                let mut asm_text = String::new();
                 for part in parts {
                     match part {
-                        AsmPart::Literal(s) => {
-                            self.output.push_str(s);
-                        }
+                        AsmPart::Literal(s) => asm_text.push_str(s),
                         AsmPart::Variable(var_name) => {
-                            self.output.push_str(&format!("    # Variable: {}\n", var_name));
+                            if let Some(&offset) = self.variables.get(var_name) {
+                                asm_text.push_str(&format!("{}(%rbp)", offset));
+                            } else {
+                                self.fail(format!("unknown variable '{}' in asm block", var_name));
+                            }
                         }
                     }
                 }
-                self.output.push_str("\n");
+
+                self.output.push_str("#APP\n");
+                for line in asm_text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                    if line.ends_with(':') {
+                        self.output.push_str(&format!("{}\n", line));
+                    } else {
+                        self.output.push_str(&format!("    {}\n", line));
+                    }
+                }
+                self.output.push_str("#NO_APP\n");
             }
             Statement::ArrayAssignment { name, index, value } => {
                 self.generate_expression(value);
                 self.output.push_str("    pushq   %rax\n");
 
                 self.generate_expression(index);
+                self.wrap_array_index(name);
 
-                if let Some(&base_offset) = self.variables.get(name) {
-                    self.output.push_str("    imulq   $8, %rax\n");
+                if self.pointer_vars.contains(name) {
+                    if let Some(&offset) = self.variables.get(name) {
+                        self.output.push_str(&format!("    movq    {}(%rbp), %rcx\n", offset));
+                        self.output.push_str("    leaq    (%rcx,%rax,8), %rax\n");
+                        self.output.push_str("    popq    %rcx\n");
+                        self.output.push_str("    movq    %rcx, (%rax)\n");
+                    }
+                } else if let Some(&base_offset) = self.variables.get(name) {
+                    let is_bytes = self.is_byte_array(name);
+                    if !is_bytes {
+                        self.output.push_str("    imulq   $8, %rax\n");
+                    }
                     self.output.push_str(&format!("    addq    ${}, %rax\n", base_offset));
                     self.output.push_str("    addq    %rbp, %rax\n");
 
                     self.output.push_str("    popq    %rcx\n");
-                    self.output.push_str("    movq    %rcx, (%rax)\n");
+                    if is_bytes {
+                        self.output.push_str("    movb    %cl, (%rax)\n");
+                    } else {
+                        self.output.push_str("    movq    %rcx, (%rax)\n");
+                    }
                 }
             }
             Statement::Return(expr) => {
@@ -367,20 +915,25 @@ impl AsmGenerator {
                 self.output.push_str("    testq   %rax, %rax\n");
                 self.output.push_str(&format!("    je      {}\n", else_label));
 
-                for stmt in then_body {
-                    self.generate_statement(stmt);
-                }
+                self.generate_block(then_body);
                 self.output.push_str(&format!("    jmp     {}\n", end_label));
 
                 self.output.push_str(&format!("{}:\n", else_label));
                 if let Some(body) = else_body {
-                    for stmt in body {
-                        self.generate_statement(stmt);
-                    }
+                    self.generate_block(body);
                 }
                 self.output.push_str(&format!("{}:\n", end_label));
             }
-            Statement::For { init: _, condition, post: _, body } => {
+            Statement::Block(body) => self.generate_block(body),
+            Statement::For { init, condition, post, body } => {
+                let saved_vars = self.variables.clone();
+                let saved_strings = self.string_vars.clone();
+                let saved_arrays = self.arrays.clone();
+                let saved_pointers = self.pointer_vars.clone();
+                if let Some(init) = init {
+                    self.generate_statement(init);
+                }
+
                 let loop_label = self.next_label();
                 let end_label = self.next_label();
 
@@ -392,18 +945,33 @@ impl AsmGenerator {
                     self.output.push_str(&format!("    je      {}\n", end_label));
                 }
 
-                for stmt in body {
-                    self.generate_statement(stmt);
+                self.generate_block(body);
+                if let Some(post) = post {
+                    self.generate_statement(post);
                 }
 
                 self.output.push_str(&format!("    jmp     {}\n", loop_label));
                 self.output.push_str(&format!("{}:\n", end_label));
+                self.variables = saved_vars;
+                self.string_vars = saved_strings;
+                self.arrays = saved_arrays;
+                self.pointer_vars = saved_pointers;
             }
         }
     }
 
     fn generate_expression(&mut self, expr: &Expression) {
         match expr {
+            Expression::Tuple(values) => {
+                if let [first, second] = values.as_slice() {
+                    self.generate_expression(second);
+                    self.output.push_str("    pushq   %rax\n");
+                    self.generate_expression(first);
+                    self.output.push_str("    popq    %rdx\n");
+                } else if let Some(first) = values.first() {
+                    self.generate_expression(first);
+                }
+            }
             Expression::Number(n) => {
                 self.output.push_str(&format!("    movq    ${}, %rax\n", n));
             }
@@ -412,6 +980,101 @@ impl AsmGenerator {
                     self.output.push_str(&format!("    movq    {}(%rbp), %rax\n", offset));
                 }
             }
+            Expression::Binary { op: op @ (BinaryOp::Div | BinaryOp::Mod), left, right }
+                if right.power_of_two_shift().is_some() =>
+            {
+                let shift = right.power_of_two_shift().unwrap_or(1);
+                self.generate_expression(left);
+                self.output.push_str("    movq    %rax, %rdx\n");
+                self.output.push_str("    sarq    $63, %rdx\n");
+                self.output.push_str(&format!("    shrq    ${}, %rdx\n", 64 - shift));
+                self.output.push_str("    addq    %rdx, %rax\n");
+                if *op == BinaryOp::Div {
+                    self.output.push_str(&format!("    sarq    ${}, %rax\n", shift));
+                } else {
+                    self.output.push_str(&format!("    andq    ${}, %rax\n", (1i64 << shift) - 1));
+                    self.output.push_str("    subq    %rdx, %rax\n");
+                }
+            }
+            Expression::Binary { op: op @ (BinaryOp::Add | BinaryOp::Sub), left, right }
+                if self.is_pointer_expression(left) || self.is_pointer_expression(right) =>
+            {
+                let (pointer, offset) = if self.is_pointer_expression(left) { (left, right) } else { (right, left) };
+                let pointer_difference = self.is_pointer_expression(offset);
+                self.generate_expression(offset);
+                if !pointer_difference {
+                    self.output.push_str("    shlq    $3, %rax\n");
+                }
+                self.output.push_str("    pushq   %rax\n");
+                self.generate_expression(pointer);
+                self.output.push_str("    popq    %rcx\n");
+                if *op == BinaryOp::Add {
+                    self.output.push_str("    addq    %rcx, %rax\n");
+                } else {
+                    self.output.push_str("    subq    %rcx, %rax\n");
+                }
+                if pointer_difference {
+                    self.output.push_str("    sarq    $3, %rax\n");
+                }
+            }
+            Expression::Binary { op: BinaryOp::Mul, left, right } if self.is_string_expression(left) => {
+                let loop_label = self.next_label();
+                let end_label = self.next_label();
+                self.generate_expression(right);
+                self.output.push_str("    pushq   %rax\n");
+                self.generate_expression(left);
+                self.output.push_str("    pushq   %rax\n");
+                self.output.push_str("    subq    $32, %rsp\n");
+                self.output.push_str("    movq    %rax, %rdi\n");
+                self.output.push_str("    call    strlen@PLT\n");
+                self.output.push_str("    movq    %rax, (%rsp)\n");
+                self.output.push_str("    movq    40(%rsp), %rcx\n");
+                self.output.push_str("    xorl    %edx, %edx\n");
+                self.output.push_str("    testq   %rcx, %rcx\n");
+                self.output.push_str("    cmovs   %rdx, %rcx\n");
+                self.output.push_str("    movq    %rcx, 40(%rsp)\n");
+                self.output.push_str("    imulq   %rcx, %rax\n");
+                self.output.push_str("    leaq    1(%rax), %rdi\n");
+                self.output.push_str("    call    malloc@PLT\n");
+                self.output.push_str("    movq    %rax, 8(%rsp)\n");
+                self.output.push_str("    movq    %rax, 16(%rsp)\n");
+                self.output.push_str(&format!("{}:\n", loop_label));
+                self.output.push_str("    cmpq    $0, 40(%rsp)\n");
+                self.output.push_str(&format!("    jle     {}\n", end_label));
+                self.output.push_str("    decq    40(%rsp)\n");
+                self.output.push_str("    movq    8(%rsp), %rdi\n");
+                self.output.push_str("    movq    32(%rsp), %rsi\n");
+                self.output.push_str("    movq    (%rsp), %rdx\n");
+                self.output.push_str("    call    memcpy@PLT\n");
+                self.output.push_str("    movq    (%rsp), %rax\n");
+                self.output.push_str("    addq    %rax, 8(%rsp)\n");
+                self.output.push_str(&format!("    jmp     {}\n", loop_label));
+                self.output.push_str(&format!("{}:\n", end_label));
+                self.output.push_str("    movq    8(%rsp), %rax\n");
+                self.output.push_str("    movb    $0, (%rax)\n");
+                self.output.push_str("    movq    16(%rsp), %rax\n");
+                self.output.push_str("    addq    $48, %rsp\n");
+            }
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                let short_label = self.next_label();
+                let end_label = self.next_label();
+                self.generate_expression(left);
+                self.output.push_str("    testq   %rax, %rax\n");
+                let jump = if *op == BinaryOp::And { "je " } else { "jne" };
+                self.output.push_str(&format!("    {}     {}\n", jump, short_label));
+                self.generate_expression(right);
+                self.output.push_str("    testq   %rax, %rax\n");
+                self.output.push_str("    setne   %al\n");
+                self.output.push_str("    movzbq  %al, %rax\n");
+                self.output.push_str(&format!("    jmp     {}\n", end_label));
+                self.output.push_str(&format!("{}:\n", short_label));
+                if *op == BinaryOp::And {
+                    self.output.push_str("    xorl    %eax, %eax\n");
+                } else {
+                    self.output.push_str("    movl    $1, %eax\n");
+                }
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
             Expression::Binary { op, left, right } => {
                 self.generate_expression(right);
                 self.output.push_str("    pushq   %rax\n");
@@ -485,48 +1148,135 @@ impl AsmGenerator {
                     }
                 }
             }
+            Expression::Call { function, args } if self.is_builtin(function, "itoa", args, 1) => {
+                let fmt = self.string_literals.len();
+                self.string_literals.push("%ld".to_string());
+
+                self.generate_expression(&args[0]);
+                self.output.push_str("    pushq   %rax\n");
+                self.output.push_str("    pushq   %rax\n");
+                self.output.push_str("    movl    $24, %edi\n");
+                self.output.push_str("    call    malloc@PLT\n");
+                self.output.push_str("    movq    %rax, (%rsp)\n");
+                self.output.push_str("    movq    %rax, %rdi\n");
+                self.output.push_str("    movl    $24, %esi\n");
+                self.output.push_str(&format!("    leaq    .LS{}(%rip), %rdx\n", fmt));
+                self.output.push_str("    movq    8(%rsp), %rcx\n");
+                self.output.push_str("    xorl    %eax, %eax\n");
+                self.output.push_str("    call    snprintf@PLT\n");
+                self.output.push_str("    popq    %rax\n");
+                self.output.push_str("    addq    $8, %rsp\n");
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "parseInt", args, 1) => {
+                self.generate_expression(&args[0]);
+                self.output.push_str("    movq    %rax, %rdi\n");
+                self.output.push_str("    xorl    %esi, %esi\n");
+                self.output.push_str("    movl    $10, %edx\n");
+                self.output.push_str("    call    strtol@PLT\n");
+            }
+            Expression::Call { function, args }
+                if self.is_builtin(function, "min", args, 2) || self.is_builtin(function, "max", args, 2) =>
+            {
+                self.generate_expression(&args[0]);
+                self.output.push_str("    pushq   %rax\n");
+                self.generate_expression(&args[1]);
+                self.output.push_str("    movq    %rax, %rcx\n");
+                self.output.push_str("    popq    %rax\n");
+                self.output.push_str("    cmpq    %rcx, %rax\n");
+                let cmov = if function == "min" { "cmovg" } else { "cmovl" };
+                self.output.push_str(&format!("    {}  %rcx, %rax\n", cmov));
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "abs", args, 1) => {
+                self.generate_expression(&args[0]);
+                self.output.push_str("    cqo\n");
+                self.output.push_str("    xorq    %rdx, %rax\n");
+                self.output.push_str("    subq    %rdx, %rax\n");
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "assert", args, 1) => {
+                let ok = self.next_label();
+                self.generate_expression(&args[0]);
+                self.output.push_str("    testq   %rax, %rax\n");
+                self.output.push_str(&format!("    jne     {}\n", ok));
+                let message = format!("{}: assertion failed: {}\n", self.source.location(), args[0]);
+                self.generate_fatal(message.replace('%', "%%"));
+                self.output.push_str(&format!("{}:\n", ok));
+            }
+            Expression::Call { function, args } if self.is_builtin(function, "panic", args, 1) => {
+                self.generate_expression(&args[0]);
+                self.output.push_str("    movq    %rax, %rdx\n");
+                self.generate_fatal("panic: %s\n".to_string());
+            }
             Expression::Call { function, args } => {
-                let arg_regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-                
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg);
-                    self.output.push_str("    pushq   %rax\n");
-                }
-                
-                for (i, _) in args.iter().enumerate() {
-                    if i < arg_regs.len() {
-                        self.output.push_str(&format!("    popq    {}\n", arg_regs[i]));
+                let symbol = match &self.current_module {
+                    Some(module) if self.module_functions.contains(&format!("{}_{}", module, function)) => {
+                        self.module_symbol(module, function)
                     }
+                    _ if self.user_functions.contains(function) => self.function_symbol(function),
+                    _ => function.clone(),
+                };
+                let cleanup = self.generate_call_args(args, self.variadic_functions.get(&symbol).copied());
+                self.output.push_str(&format!("    call    {}\n", symbol));
+                if cleanup > 0 {
+                    self.output.push_str(&format!("    addq    ${}, %rsp\n", cleanup));
                 }
-                
-                self.output.push_str(&format!("    call    {}\n", function));
+            }
+            Expression::ArrayAccess { name, index } if self.string_vars.contains(name) => {
+                self.generate_string_index(&Expression::Identifier(name.clone()), index);
             }
             Expression::ArrayAccess { name, index } => {
                 self.generate_expression(index);
+                self.wrap_array_index(name);
 
-                if let Some(&base_offset) = self.variables.get(name) {
-                    self.output.push_str("    imulq   $8, %rax\n");
+                if self.variadic_params.contains(name) || self.pointer_vars.contains(name) {
+                    if let Some(&offset) = self.variables.get(name) {
+                        self.output.push_str(&format!("    movq    {}(%rbp), %rcx\n", offset));
+                        self.output.push_str("    movq    (%rcx,%rax,8), %rax\n");
+                    }
+                } else if let Some(&base_offset) = self.variables.get(name) {
+                    let is_bytes = self.is_byte_array(name);
+                    if !is_bytes {
+                        self.output.push_str("    imulq   $8, %rax\n");
+                    }
                     self.output.push_str(&format!("    addq    ${}, %rax\n", base_offset));
                     self.output.push_str("    addq    %rbp, %rax\n");
 
-                    self.output.push_str("    movq    (%rax), %rax\n");
+                    if is_bytes {
+                        self.output.push_str("    movzbq  (%rax), %rax\n");
+                    } else {
+                        self.output.push_str("    movq    (%rax), %rax\n");
+                    }
                 }
             }
+            Expression::ModuleCall { module, function, args } if module == "sys" && function == "Syscall" => {
+                self.generate_call_args(args, None);
+                self.output.push_str("    movq    %rdi, %rax\n");
+                self.output.push_str("    movq    %rsi, %rdi\n");
+                self.output.push_str("    movq    %rdx, %rsi\n");
+                self.output.push_str("    movq    %rcx, %rdx\n");
+                self.output.push_str("    syscall\n");
+            }
+            Expression::ModuleCall { module, function, args } if module == "time" && function == "Rdtsc" && args.is_empty() => {
+                self.output.push_str("    rdtsc\n");
+                self.output.push_str("    salq    $32, %rdx\n");
+                self.output.push_str("    orq     %rdx, %rax\n");
+            }
+            Expression::ModuleCall { module, function, args } if module == "stdio" && function == "Printf" => {
+                self.generate_call_args(args, None);
+                self.output.push_str("    xorl    %eax, %eax\n");
+                self.output.push_str("    call    printf@PLT\n");
+            }
             Expression::ModuleCall { module, function, args } => {
-                let arg_regs = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg);
-                    self.output.push_str("    pushq   %rax\n");
-                }
-
-                for (i, _) in args.iter().enumerate() {
-                    if i < arg_regs.len() {
-                        self.output.push_str(&format!("    popq    {}\n", arg_regs[i]));
-                    }
+                let function = match function.as_str() {
+                    "Print" if module == "stdio" && args.len() == 1 && self.is_string_expression(&args[0]) => "PrintStr",
+                    "Println" if module == "stdio" && args.len() == 1 && self.is_string_expression(&args[0]) => "PrintlnStr",
+                    _ => function.as_str(),
+                };
+                let symbol = self.module_symbol(module, function);
+                let cleanup = self.generate_call_args(args, self.variadic_functions.get(&symbol).copied());
+                self.output.push_str(&format!("    call    {}\n", symbol));
+                if cleanup > 0 {
+                    self.output.push_str(&format!("    addq    ${}, %rsp\n", cleanup));
                 }
-
-                self.output.push_str(&format!("    call    {}_{}\n", module, function));
             }
             Expression::String(s) => {
                 let idx = self.string_literals.len();
@@ -541,7 +1291,7 @@ impl AsmGenerator {
                 
                 self.output.push_str("    movb    $0, (%r15)\n");
                 
-                self.output.push_str("    subq    $128, %rsp\n");
+                self.output.push_str("    subq    $256, %rsp\n");
                 self.output.push_str("    movq    %rsp, %r14\n");
                 
                 for part in parts {
@@ -555,10 +1305,7 @@ impl AsmGenerator {
                             self.output.push_str("    call    strcat@PLT\n");
                         }
                         TemplateStringPart::Expression { expr, format } => {
-                            let is_string_expr = matches!(
-                                expr.as_ref(),
-                                Expression::String(_) | Expression::Identifier(_)
-                            );
+                            let is_string_expr = self.is_string_expression(expr);
                             
                             self.generate_expression(expr);
                             
@@ -567,47 +1314,13 @@ impl AsmGenerator {
                                 self.output.push_str("    movq    %rax, %rsi\n");
                                 self.output.push_str("    call    strcat@PLT\n");
                             } else {
-                                let format_str = if let Some(spec) = format {
-                                    match spec.format_type {
-                                        FormatType::Hex => {
-                                            if let Some(width) = spec.width {
-                                                if spec.padding == '0' {
-                                                    format!("%0{}lx", width)
-                                                } else {
-                                                    format!("%{}lx", width)
-                                                }
-                                            } else {
-                                                "%lx".to_string()
-                                            }
-                                        }
-                                        FormatType::HexUpper => {
-                                            if let Some(width) = spec.width {
-                                                if spec.padding == '0' {
-                                                    format!("%0{}lX", width)
-                                                } else {
-                                                    format!("%{}lX", width)
-                                                }
-                                            } else {
-                                                "%lX".to_string()
-                                            }
-                                        }
-                                        FormatType::Decimal => {
-                                            if let Some(width) = spec.width {
-                                                if spec.padding == '0' {
-                                                    format!("%0{}ld", width)
-                                                } else {
-                                                    format!("%{}ld", width)
-                                                }
-                                            } else {
-                                                "%ld".to_string()
-                                            }
-                                        }
-                                        FormatType::String => "%s".to_string(),
-                                        FormatType::Auto => "%ld".to_string(),
-                                    }
-                                } else {
-                                    "%ld".to_string()
-                                };
+                                if let Some(spec) = format.as_ref().filter(|s| s.format_type == FormatType::Binary) {
+                                    let zero_fill = if spec.padding == '0' && !spec.left_align { spec.width.unwrap_or(0) } else { 0 };
+                                    let zero_fill = zero_fill.max(spec.precision.unwrap_or(0));
+                                    self.generate_binary_digits(zero_fill);
+                                    self.output.push_str("    movq    %rsi, %rax\n");
+                                }
+                                let format_str = Self::template_format(format.as_ref(), is_string_expr);
                                 
                                 let fmt_idx = self.string_literals.len();
                                 self.string_literals.push(format_str);
@@ -630,25 +1343,79 @@ impl AsmGenerator {
                 
             }
             Expression::StringIndex { string, index } => {
-                if let Expression::String(s) = string.as_ref() {
-                    let idx = self.string_literals.len();
-                    self.string_literals.push(s.clone());
-
-                    self.generate_expression(index);
-
-                    self.output.push_str(&format!("    leaq    .LS{}(%rip), %rcx\n", idx));
-                    self.output.push_str("    addq    %rax, %rcx\n");
-
-                    self.output.push_str("    movzbq  (%rcx), %rax\n");
+                self.generate_string_index(string, index);
+            }
+            Expression::Slice { base, start, end } => {
+                self.generate_expression(base);
+                self.output.push_str("    pushq   %rax\n");
+                match start {
+                    Some(start) => self.generate_expression(start),
+                    None => self.output.push_str("    movq    $0, %rax\n"),
                 }
+                self.output.push_str("    pushq   %rax\n");
+                match end {
+                    Some(end) => {
+                        self.generate_expression(end);
+                        self.output.push_str("    pushq   %rax\n");
+                        self.output.push_str("    movq    16(%rsp), %rdi\n");
+                        self.output.push_str("    call    strlen@PLT\n");
+                        self.output.push_str("    popq    %rdx\n");
+                    }
+                    None => {
+                        self.output.push_str("    movq    8(%rsp), %rdi\n");
+                        self.output.push_str("    call    strlen@PLT\n");
+                        self.output.push_str("    movq    %rax, %rdx\n");
+                    }
+                }
+                self.output.push_str("    popq    %rcx\n");
+                self.output.push_str("    popq    %rsi\n");
+                // Unsigned compares also catch negative bounds: 0 <= start <= end <= strlen(base).
+                let in_range = self.next_label();
+                let out_of_range = self.next_label();
+                self.output.push_str("    cmpq    %rdx, %rax\n");
+                self.output.push_str(&format!("    jb      {}\n", out_of_range));
+                self.output.push_str("    cmpq    %rcx, %rdx\n");
+                self.output.push_str(&format!("    jae     {}\n", in_range));
+                self.output.push_str(&format!("{}:\n", out_of_range));
+                let message = format!("{}: slice bounds out of range\n", self.source.location());
+                self.generate_fatal(message.replace('%', "%%"));
+                self.output.push_str(&format!("{}:\n", in_range));
+                self.output.push_str("    movq    %rdx, %rax\n");
+                self.output.push_str("    subq    %rcx, %rax\n");
+                self.output.push_str("    addq    %rcx, %rsi\n");
+                self.output.push_str("    pushq   %rsi\n");
+                self.output.push_str("    pushq   %rax\n");
+                self.output.push_str("    leaq    1(%rax), %rdi\n");
+                self.output.push_str("    call    malloc@PLT\n");
+                self.output.push_str("    movq    %rax, %rdi\n");
+                self.output.push_str("    popq    %rdx\n");
+                self.output.push_str("    popq    %rsi\n");
+                self.output.push_str("    pushq   %rdx\n");
+                self.output.push_str("    pushq   %rdi\n");
+                self.output.push_str("    call    memcpy@PLT\n");
+                self.output.push_str("    popq    %rax\n");
+                self.output.push_str("    popq    %rdx\n");
+                self.output.push_str("    movb    $0, (%rax,%rdx)\n");
             }
             Expression::AddressOf { operand } => {
                 if let Expression::Identifier(name) = operand.as_ref() {
                     if let Some(&offset) = self.variables.get(name) {
                         self.output.push_str(&format!("    leaq    {}(%rbp), %rax\n", offset));
+                    } else {
+                        self.output.push_str(&format!("    leaq    {}(%rip), %rax\n", self.function_symbol(name)));
                     }
                 }
             }
+            Expression::IndirectCall { callee, args } => {
+                self.generate_expression(callee);
+                self.output.push_str("    pushq   %rax\n");
+                self.output.push_str("    subq    $8, %rsp\n");
+
+                let cleanup = self.generate_call_args(args, None);
+                self.output.push_str(&format!("    movq    {}(%rsp), %rax\n", cleanup + 8));
+                self.output.push_str("    call    *%rax\n");
+                self.output.push_str(&format!("    addq    ${}, %rsp\n", cleanup + 16));
+            }
             Expression::Deref { operand } => {
                 self.generate_expression(operand);
                 self.output.push_str("    movq    (%rax), %rax\n");
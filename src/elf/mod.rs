@@ -1,5 +1,8 @@
 pub mod asm_generator;
+pub mod arm64_generator;
+mod peephole;
 #[allow(dead_code)]
 mod elf_writer;
 
 pub use asm_generator::AsmGenerator;
+pub use arm64_generator::Arm64Generator;
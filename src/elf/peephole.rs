@@ -0,0 +1,123 @@
+enum Line<'a> {
+    Instr { mnemonic: &'a str, operands: Vec<&'a str> },
+    Other,
+}
+
+fn parse_line(line: &str) -> Line<'_> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('.') || trimmed.ends_with(':') {
+        return Line::Other;
+    }
+
+    let (mnemonic, rest) = match trimmed.find(char::is_whitespace) {
+        Some(pos) => (&trimmed[..pos], trimmed[pos..].trim()),
+        None => (trimmed, ""),
+    };
+
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, ch) in rest.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(rest[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !rest.is_empty() {
+        operands.push(rest[start..].trim());
+    }
+
+    Line::Instr { mnemonic, operands }
+}
+
+/// A register, or a `%rbp`-relative slot whose address `other` cannot change.
+fn is_stable_operand(operand: &str, other: &str) -> bool {
+    if operand.starts_with('%') {
+        return true;
+    }
+    operand.ends_with("(%rbp)") && other != "%rbp"
+}
+
+enum Rewrite {
+    DropBoth,
+    DropSecond,
+    Replace(String),
+}
+
+fn rewrite_pair(first: &str, second: &str) -> Option<Rewrite> {
+    let (Line::Instr { mnemonic: m1, operands: o1 }, Line::Instr { mnemonic: m2, operands: o2 }) =
+        (parse_line(first), parse_line(second)) else {
+        return None;
+    };
+
+    match (m1, m2) {
+        ("pushq", "popq") if o1.len() == 1 && o2.len() == 1 && o1[0].starts_with('%') => {
+            if o1[0] == o2[0] {
+                Some(Rewrite::DropBoth)
+            } else {
+                Some(Rewrite::Replace(format!("    movq    {}, {}", o1[0], o2[0])))
+            }
+        }
+        ("movq", "movq")
+            if o1.len() == 2 && o2.len() == 2 && o1[0] == o2[1] && o1[1] == o2[0]
+                && is_stable_operand(o1[0], o1[1]) && is_stable_operand(o1[1], o1[0]) =>
+        {
+            Some(Rewrite::DropSecond)
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites redundant instruction pairs; `#APP`/`#NO_APP` inline-asm regions are left as written.
+pub fn optimize(asm: &str) -> String {
+    let mut lines: Vec<String> = asm.lines().map(|l| l.to_string()).collect();
+
+    loop {
+        let mut changed = false;
+        let mut out: Vec<String> = Vec::with_capacity(lines.len());
+        let mut i = 0;
+
+        while i < lines.len() {
+            if lines[i].trim() == "#APP" {
+                while i < lines.len() {
+                    out.push(lines[i].clone());
+                    i += 1;
+                    if out.last().is_some_and(|l| l.trim() == "#NO_APP") {
+                        break;
+                    }
+                }
+                continue;
+            }
+            if i + 1 < lines.len() {
+                if let Some(rewrite) = rewrite_pair(&lines[i], &lines[i + 1]) {
+                    match rewrite {
+                        Rewrite::DropBoth => {}
+                        Rewrite::DropSecond => out.push(lines[i].clone()),
+                        Rewrite::Replace(line) => out.push(line),
+                    }
+                    changed = true;
+                    i += 2;
+                    continue;
+                }
+            }
+            out.push(lines[i].clone());
+            i += 1;
+        }
+
+        lines = out;
+        if !changed {
+            break;
+        }
+    }
+
+    let mut result = lines.join("\n");
+    if asm.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
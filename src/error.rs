@@ -8,16 +8,16 @@ pub struct CompileError {
     pub line: usize,
     pub column: usize,
     pub source_line: Option<String>,
+    /// Further errors from the same pass, each at its own location.
+    pub related: Vec<CompileError>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ErrorKind {
     LexerError,
     ParserError,
-    #[allow(dead_code)]
     TypeError,
     ModuleError,
-    #[allow(dead_code)]
     CodeGenError,
 }
 
@@ -30,6 +30,7 @@ impl CompileError {
             line,
             column,
             source_line: None,
+            related: Vec::new(),
         }
     }
 
@@ -58,6 +59,9 @@ impl CompileError {
                       kind_str);
         }
         eprintln!();
+        for error in &self.related {
+            error.display();
+        }
     }
 }
 
@@ -69,4 +73,28 @@ impl fmt::Display for CompileError {
 
 impl std::error::Error for CompileError {}
 
+/// A diagnostic that does not stop compilation unless `-Werror` is given.
+#[derive(Debug, Clone)]
+pub struct CompileWarning {
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    /// 0 when only the line is known.
+    pub column: usize,
+}
+
+impl CompileWarning {
+    pub fn display(&self) {
+        if self.column == 0 {
+            eprintln!("Warning: {} at {}:{}", self.message, self.file, self.line);
+        } else {
+            eprintln!("Warning: {} at {}:{}:{}", self.message, self.file, self.line, self.column);
+        }
+    }
+
+    pub fn into_error(self, kind: ErrorKind) -> CompileError {
+        CompileError::new(kind, self.message, self.file, self.line, self.column.max(1))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, CompileError>;
@@ -1,3 +1,5 @@
+use crate::error::{CompileError, CompileWarning, ErrorKind, Result};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Package,
@@ -9,6 +11,8 @@ pub enum Token {
     For,
     Return,
     Asm,
+    Enum,
+    Pub,
 
     Identifier(String),
     Number(i64),
@@ -39,6 +43,7 @@ pub enum Token {
     Comma,
     Semicolon,
     Colon,
+    ColonAssign,
     Dot,
     Arrow,
     Ampersand,
@@ -49,6 +54,61 @@ pub enum Token {
     Eof,
 }
 
+impl Token {
+    /// The token as the user wrote it, for diagnostics: `}` rather than `RightBrace`.
+    pub fn describe(&self) -> String {
+        let text = match self {
+            Token::Package => "package",
+            Token::Import => "import",
+            Token::Func => "fn",
+            Token::Var => "var",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::For => "for",
+            Token::Return => "return",
+            Token::Asm => "asm",
+            Token::Enum => "enum",
+            Token::Pub => "pub",
+            Token::Identifier(name) => return format!("`{}`", name),
+            Token::Number(n) => return format!("`{}`", n),
+            Token::String(s) => return format!("string {:?}", s),
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Assign => "=",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Not => "!",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::ColonAssign => ":=",
+            Token::Dot => ".",
+            Token::Arrow => "->",
+            Token::Ampersand => "&",
+            Token::DoublePlus => "++",
+            Token::Dollar => "$",
+            Token::Newline => return "end of line".to_string(),
+            Token::Eof => return "end of file".to_string(),
+        };
+        format!("`{}`", text)
+    }
+}
+
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
@@ -56,6 +116,13 @@ pub struct Lexer {
     line: usize,
     column: usize,
     file: String,
+    warnings: Vec<CompileWarning>,
+    /// First error found; `tokenize` stops and returns it.
+    error: Option<CompileError>,
+    /// Brace depth inside an `asm { }` block, where `;` starts a comment.
+    asm_depth: usize,
+    /// Source line and column of each token produced by `tokenize`.
+    token_positions: Vec<(usize, usize)>,
 }
 
 impl Lexer {
@@ -75,9 +142,21 @@ impl Lexer {
             line: 1,
             column: 1,
             file: file.to_string(),
+            warnings: Vec::new(),
+            error: None,
+            asm_depth: 0,
+            token_positions: Vec::new(),
         }
     }
 
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
+    }
+
+    pub fn token_positions(&self) -> &[(usize, usize)] {
+        &self.token_positions
+    }
+
     fn advance(&mut self) {
         if let Some(ch) = self.current_char {
             if ch == '\n' {
@@ -116,7 +195,8 @@ impl Lexer {
     }
 
     fn skip_comment(&mut self) {
-        if self.current_char == Some('/') && self.peek(1) == Some('/') {
+        let asm_comment = self.asm_depth > 0 && self.current_char == Some(';');
+        if asm_comment || (self.current_char == Some('/') && self.peek(1) == Some('/')) {
             while self.current_char.is_some() && self.current_char != Some('\n') {
                 self.advance();
             }
@@ -124,6 +204,14 @@ impl Lexer {
     }
 
     fn read_number(&mut self) -> Token {
+        let (line, column) = (self.line, self.column);
+        if self.current_char == Some('0')
+            && matches!(self.peek(1), Some('x') | Some('X'))
+            && self.peek(2).is_some_and(|c| c.is_ascii_hexdigit())
+        {
+            return self.read_hex_number();
+        }
+
         let mut num_str = String::new();
 
         while let Some(ch) = self.current_char {
@@ -138,7 +226,31 @@ impl Lexer {
         match num_str.parse::<i64>() {
             Ok(num) => Token::Number(num),
             Err(_) => {
-                eprintln!("Warning: Number '{}' is too large, using i64::MAX ({})", num_str, i64::MAX);
+                self.warn(format!("Number '{}' is too large, using i64::MAX ({})", num_str, i64::MAX), line, column);
+                Token::Number(i64::MAX)
+            }
+        }
+    }
+
+    fn read_hex_number(&mut self) -> Token {
+        let (line, column) = (self.line, self.column);
+        self.advance();
+        self.advance();
+
+        let mut hex_str = String::new();
+        while let Some(ch) = self.current_char {
+            if ch.is_ascii_hexdigit() {
+                hex_str.push(ch);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        match i64::from_str_radix(&hex_str, 16) {
+            Ok(num) => Token::Number(num),
+            Err(_) => {
+                self.warn(format!("Number '0x{}' is too large, using i64::MAX ({})", hex_str, i64::MAX), line, column);
                 Token::Number(i64::MAX)
             }
         }
@@ -171,51 +283,100 @@ impl Lexer {
             "loop" => Token::For,
             "return" => Token::Return,
             "asm" => Token::Asm,
-            "pub" => Token::Identifier(id),
+            "enum" => Token::Enum,
+            "pub" => Token::Pub,
             _ => Token::Identifier(id),
         }
     }
 
     fn read_string(&mut self) -> Token {
+        let (start_line, start_column) = (self.line, self.column);
         self.advance();
         let mut string = String::new();
 
-        while let Some(ch) = self.current_char {
-            if ch == '"' {
-                self.advance();
-                break;
-            } else if ch == '\\' {
-                self.advance();
-                if let Some(escape_ch) = self.current_char {
-                    match escape_ch {
-                        'n' => string.push('\n'),
-                        't' => string.push('\t'),
-                        'r' => string.push('\r'),
-                        '\\' => string.push('\\'),
-                        '"' => string.push('"'),
-                        _ => string.push(escape_ch),
+        loop {
+            match self.current_char {
+                None => {
+                    self.fail("unterminated string literal".to_string(), start_line, start_column);
+                    break;
+                }
+                Some('"') => {
+                    self.advance();
+                    break;
+                }
+                Some('\n') => {
+                    self.fail(
+                        "newline in string literal (close the string or escape the line break with `\\`)".to_string(),
+                        start_line,
+                        start_column,
+                    );
+                    break;
+                }
+                Some('\\') => {
+                    self.advance();
+                    match self.current_char {
+                        Some('\n') => {
+                            self.advance();
+                            while matches!(self.current_char, Some(' ') | Some('\t') | Some('\r')) {
+                                self.advance();
+                            }
+                        }
+                        Some(escape_ch) => {
+                            match escape_ch {
+                                'n' => string.push('\n'),
+                                't' => string.push('\t'),
+                                'r' => string.push('\r'),
+                                '\\' => string.push('\\'),
+                                '"' => string.push('"'),
+                                _ => {
+                                    self.warn(
+                                        format!("unknown escape sequence '\\{}', using '{}'", escape_ch, escape_ch),
+                                        self.line,
+                                        self.column - 1,
+                                    );
+                                    string.push(escape_ch);
+                                }
+                            }
+                            self.advance();
+                        }
+                        None => {
+                            self.fail("unterminated string literal".to_string(), start_line, start_column);
+                            break;
+                        }
                     }
+                }
+                Some(ch) => {
+                    string.push(ch);
                     self.advance();
                 }
-            } else {
-                string.push(ch);
-                self.advance();
             }
         }
 
         Token::String(string)
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    fn warn(&mut self, message: String, line: usize, column: usize) {
+        self.warnings.push(CompileWarning { message, file: self.file.clone(), line, column });
+    }
+
+    fn fail(&mut self, message: String, line: usize, column: usize) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(ErrorKind::LexerError, message, self.file.clone(), line, column));
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<Vec<Token>> {
         let mut tokens = Vec::new();
 
         loop {
             self.skip_whitespace();
             self.skip_comment();
+            let position = (self.line, self.column);
 
             match self.current_char {
                 None => {
                     tokens.push(Token::Eof);
+                    self.token_positions.push(position);
                     break;
                 }
                 Some('\n') => {
@@ -313,10 +474,14 @@ impl Lexer {
                     self.advance();
                 }
                 Some('{') => {
+                    if self.asm_depth > 0 || matches!(tokens.last(), Some(Token::Asm)) {
+                        self.asm_depth += 1;
+                    }
                     tokens.push(Token::LeftBrace);
                     self.advance();
                 }
                 Some('}') => {
+                    self.asm_depth = self.asm_depth.saturating_sub(1);
                     tokens.push(Token::RightBrace);
                     self.advance();
                 }
@@ -337,8 +502,13 @@ impl Lexer {
                     self.advance();
                 }
                 Some(':') => {
-                    tokens.push(Token::Colon);
                     self.advance();
+                    if self.current_char == Some('=') {
+                        tokens.push(Token::ColonAssign);
+                        self.advance();
+                    } else {
+                        tokens.push(Token::Colon);
+                    }
                 }
                 Some('.') => {
                     tokens.push(Token::Dot);
@@ -366,21 +536,14 @@ impl Lexer {
                 Some(ch) if ch.is_alphabetic() || ch == '_' => {
                     tokens.push(self.read_identifier());
                 }
-                Some(ch) => {
-                    use crate::error::{CompileError, ErrorKind};
-                    let err = CompileError::new(
-                        ErrorKind::LexerError,
-                        format!("unexpected character: '{}'", ch),
-                        self.file.clone(),
-                        self.line,
-                        self.column,
-                    );
-                    err.display();
-                    std::process::exit(1);
-                }
+                Some(ch) => self.fail(format!("unexpected character: '{}'", ch), self.line, self.column),
+            }
+            if let Some(err) = self.error.take() {
+                return Err(err);
             }
+            self.token_positions.resize(tokens.len(), position);
         }
 
-        tokens
+        Ok(tokens)
     }
 }
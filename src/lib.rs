@@ -0,0 +1,343 @@
+mod lexer;
+mod parser;
+mod ast;
+mod elf;
+mod pe;
+mod nvm;
+mod wasm;
+pub mod error;
+mod typechecker;
+mod reachability;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub use error::CompileError;
+pub use nvm::{disassemble, NVM_LATEST_VERSION};
+pub use pe::c_codegen::compile_c_code;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// GAS assembly for x86-64 Linux, ready to hand to `cc`.
+    Elf,
+    /// GAS assembly for AArch64 Linux, ready to hand to an ARM `cc`.
+    ElfArm64,
+    /// A complete PE32+ image from the machine-code backend.
+    Pe,
+    /// C source for the Windows `cl.exe` path.
+    PeC,
+    /// NVM assembly text.
+    NvmCode,
+    /// NVM bytecode for Novaria.
+    Novaria,
+    /// WebAssembly text format.
+    Wasm,
+}
+
+pub struct Compiler {
+    pub target: Target,
+    /// Name used in diagnostics; imports are resolved relative to its directory.
+    pub file: String,
+    pub entry: String,
+    pub lib: bool,
+    pub keep_exported: bool,
+    pub inline_threshold: Option<usize>,
+    pub nvm_version: u8,
+    pub max_array_size: usize,
+    pub warnings_as_errors: bool,
+    pub annotate: bool,
+    pub debug_info: bool,
+    /// Prefix user function labels with `<package>.` on the assembly targets.
+    pub mangle: bool,
+    /// Bytes of stack to run the program on instead of the default one (`--elf` only).
+    pub stack_size: Option<usize>,
+}
+
+pub struct Output {
+    pub code: Vec<u8>,
+    /// `location name` lines for `--emit-map`; empty on targets without one.
+    pub map: Vec<String>,
+    pub pruned: usize,
+}
+
+/// Compiles `source` for `target` with default options, resolving imports from the working directory.
+pub fn compile(source: &str, target: Target) -> Result<Vec<u8>, CompileError> {
+    Compiler::new(target).compile(source).map(|output| output.code)
+}
+
+impl Compiler {
+    pub fn new(target: Target) -> Self {
+        Compiler {
+            target,
+            file: "<source>".to_string(),
+            entry: "main".to_string(),
+            lib: false,
+            keep_exported: false,
+            inline_threshold: None,
+            nvm_version: 0,
+            max_array_size: parser::DEFAULT_MAX_ARRAY_SIZE,
+            warnings_as_errors: false,
+            annotate: false,
+            debug_info: false,
+            mangle: false,
+            stack_size: None,
+        }
+    }
+
+    pub fn compile(&self, source: &str) -> error::Result<Output> {
+        self.compile_files(&[(&self.file, source)])
+    }
+
+    /// Compiles `(file name, source)` pairs as one program. The files must declare the same
+    /// package; their functions and imports are merged as if written in a single file.
+    pub fn compile_files(&self, files: &[(&str, &str)]) -> error::Result<Output> {
+        let mut merged: Option<ast::Program> = None;
+        let mut loaded = HashSet::new();
+
+        for &(file, source) in files {
+            let mut program = self.parse(source, file)?;
+            let source_dir = Path::new(file).parent().unwrap_or(Path::new("."));
+            self.load_modules(&mut program, source_dir, &mut loaded)?;
+
+            let Some(ast) = merged.as_mut() else {
+                merged = Some(program);
+                continue;
+            };
+            if program.package != ast.package {
+                return Err(CompileError::new(
+                    error::ErrorKind::ModuleError,
+                    format!("{} declares package '{}', but the other files declare '{}'", file, program.package, ast.package),
+                    file.to_string(),
+                    1,
+                    1,
+                ));
+            }
+            ast.imports.extend(program.imports);
+            ast.functions.extend(program.functions);
+            ast.enums.extend(program.enums);
+            ast.modules.extend(program.modules);
+        }
+
+        let Some(mut ast) = merged else {
+            return Err(self.error(error::ErrorKind::ModuleError, "no source files given".to_string()));
+        };
+
+        let mut type_checker = typechecker::TypeChecker::new();
+        let checked = type_checker.check_program(&ast);
+        self.report_warnings(type_checker.warnings(), error::ErrorKind::TypeError)?;
+        if let Err(errors) = checked {
+            let mut errors = errors.into_iter().map(|e| {
+                let error = CompileError::new(
+                    error::ErrorKind::TypeError,
+                    format!("type error in {}: {}", e.location, e.message),
+                    e.file,
+                    e.line.max(1),
+                    1,
+                );
+                let source = files.iter().find(|(file, _)| *file == error.file).map(|(_, source)| *source);
+                match source.and_then(|source| source.lines().nth(error.line - 1)) {
+                    Some(line) => error.with_source_line(line.to_string()),
+                    None => error,
+                }
+            });
+            let mut first = errors.next().expect("type checking failed without errors");
+            first.related = errors.collect();
+            return Err(first);
+        }
+
+        if !self.lib && !ast.functions.iter().any(|f| f.name == self.entry) {
+            return Err(self.error(
+                error::ErrorKind::ModuleError,
+                format!("no `{}` function defined (use --lib to compile a file without an entry point)", self.entry),
+            ));
+        }
+
+        let pruned = if self.lib {
+            0
+        } else {
+            reachability::eliminate_dead_functions(&mut ast, &self.entry, self.keep_exported)
+        };
+
+        let mut output = self.generate(&ast).map_err(|e| {
+            if e.file.is_empty() {
+                CompileError { file: self.file.clone(), line: 1, ..e }
+            } else {
+                e
+            }
+        })?;
+        output.pruned = pruned;
+        Ok(output)
+    }
+
+    fn error(&self, kind: error::ErrorKind, message: String) -> CompileError {
+        CompileError::new(kind, message, self.file.clone(), 1, 1)
+    }
+
+    /// Prints `warnings`, or fails on the first one under `-Werror`.
+    fn report_warnings(&self, warnings: &[error::CompileWarning], kind: error::ErrorKind) -> error::Result<()> {
+        for warning in warnings {
+            if self.warnings_as_errors {
+                return Err(warning.clone().into_error(kind));
+            }
+            warning.display();
+        }
+        Ok(())
+    }
+
+    fn parse(&self, source: &str, file: &str) -> error::Result<ast::Program> {
+        let mut lexer = lexer::Lexer::new_with_file(source, file);
+        let with_source = |e: CompileError| match source.lines().nth(e.line.saturating_sub(1)) {
+            Some(line) => e.with_source_line(line.to_string()),
+            None => e,
+        };
+        let tokens = lexer.tokenize().map_err(with_source)?;
+        for warning in lexer.warnings() {
+            if self.warnings_as_errors {
+                return Err(with_source(warning.clone().into_error(error::ErrorKind::LexerError)));
+            }
+            warning.display();
+        }
+
+        let mut parser = parser::Parser::new(tokens, file);
+        parser.set_max_array_size(self.max_array_size);
+        parser.set_token_positions(lexer.token_positions());
+        let program = parser.parse().map_err(with_source)?;
+        for warning in parser.warnings() {
+            if self.warnings_as_errors {
+                return Err(with_source(warning.clone().into_error(error::ErrorKind::ParserError)));
+            }
+            warning.display();
+        }
+        Ok(program)
+    }
+
+    fn load_modules(&self, ast: &mut ast::Program, base_dir: &Path, loaded: &mut HashSet<String>) -> error::Result<()> {
+        let imports = ast.imports.clone();
+
+        for import in &imports {
+            // `import "net/http"` loads `net/http.per` as the module `net.http`.
+            let module_name = import.path.replace('/', ".");
+
+            if loaded.contains(&module_name) {
+                continue;
+            }
+
+            loaded.insert(module_name.clone());
+
+            let module_filename = format!("{}.per", import.path);
+
+            let mut module_file = base_dir.join(&module_filename);
+
+            if !module_file.exists() {
+                module_file = Path::new("stdlib").join(&module_filename);
+            }
+
+            if !module_file.exists() {
+                if let Ok(exe_path) = env::current_exe() {
+                    if let Some(exe_dir) = exe_path.parent() {
+                        module_file = exe_dir.join("stdlib").join(&module_filename);
+                    }
+                }
+            }
+
+            let module_source = match fs::read_to_string(&module_file) {
+                Ok(s) => s,
+                Err(_) => {
+                    return Err(CompileError::new(
+                        error::ErrorKind::ModuleError,
+                        format!("could not find module '{}'", module_name),
+                        module_file.to_string_lossy().to_string(),
+                        1,
+                        1,
+                    ).with_source_line(format!("import \"{}\"", import.path)));
+                }
+            };
+
+            let mut module_ast = self.parse(&module_source, &module_file.to_string_lossy())?;
+
+            self.load_modules(&mut module_ast, base_dir, loaded)?;
+
+            for (mod_name, module) in module_ast.modules {
+                ast.modules.insert(mod_name, module);
+            }
+
+            let module = ast::Module {
+                name: module_name.clone(),
+                functions: module_ast.functions,
+            };
+
+            ast.modules.insert(module_name, module);
+        }
+
+        Ok(())
+    }
+
+    fn generate(&self, ast: &ast::Program) -> error::Result<Output> {
+        let mut map = Vec::new();
+        let code = match self.target {
+            Target::Elf => {
+                let mut asm_gen = elf::AsmGenerator::new();
+                asm_gen.set_entry(&self.entry);
+                asm_gen.set_annotate(self.annotate);
+                asm_gen.set_debug_info(self.debug_info);
+                if self.mangle {
+                    asm_gen.set_symbol_prefix(&format!("{}.", ast.package), self.lib);
+                }
+                if let Some(size) = self.stack_size {
+                    asm_gen.set_stack_size(size);
+                }
+                let asm_code = asm_gen.generate(ast)?;
+                map = asm_gen.function_map().iter().map(|(label, name)| format!("{} {}", label, name)).collect();
+                asm_code.into_bytes()
+            }
+            Target::ElfArm64 => {
+                let mut asm_gen = elf::Arm64Generator::new();
+                asm_gen.set_entry(&self.entry);
+                if self.mangle {
+                    asm_gen.set_symbol_prefix(&format!("{}.", ast.package), self.lib);
+                }
+                asm_gen.generate(ast)?.into_bytes()
+            }
+            Target::Pe => {
+                let mut codegen = pe::CodeGen::new("pe-asm");
+                codegen.set_entry(&self.entry);
+                let machine_code = codegen.generate(ast)?;
+                pe::PEWriter::new().build(&machine_code)
+            }
+            Target::PeC => {
+                let mut c_gen = pe::c_codegen::CCodeGen::new();
+                c_gen.set_entry(&self.entry);
+                c_gen.generate(ast)
+                    .map_err(|e| self.error(error::ErrorKind::CodeGenError, e))?
+                    .into_bytes()
+            }
+            Target::NvmCode => {
+                let mut nvm_asm_gen = nvm::NVMAssemblyGenerator::new();
+                nvm_asm_gen.set_entry(&self.entry);
+                let asm_code = nvm_asm_gen.generate(ast)?;
+                self.report_warnings(nvm_asm_gen.warnings(), error::ErrorKind::CodeGenError)?;
+                asm_code.into_bytes()
+            }
+            Target::Novaria => {
+                let mut nvm_gen = nvm::NVMCodeGen::with_version(self.nvm_version);
+                nvm_gen.set_entry(&self.entry);
+                if let Some(threshold) = self.inline_threshold {
+                    nvm_gen.set_inline_threshold(threshold);
+                }
+                let bytecode = nvm_gen.generate(ast)?;
+                self.report_warnings(nvm_gen.warnings(), error::ErrorKind::CodeGenError)?;
+                map = nvm_gen.function_map().into_iter().map(|(offset, name)| format!("{:08x} {}", offset, name)).collect();
+                bytecode
+            }
+            Target::Wasm => {
+                let mut wat_gen = wasm::WatGenerator::new();
+                wat_gen.set_entry(&self.entry);
+                wat_gen.generate(ast)?.into_bytes()
+            }
+        };
+
+        Ok(Output { code, map, pruned: 0 })
+    }
+}
@@ -1,271 +1,430 @@
-mod lexer;
-mod parser;
-mod ast;
-mod elf;
-mod pe;
-mod nvm;
-mod error;
-mod typechecker;
+use perano_lang::{compile_c_code, disassemble, error, Compiler, Target, NVM_LATEST_VERSION};
 
 use std::fs;
 use std::env;
+use std::io::{self, Write};
 use std::process;
-use std::collections::HashSet;
 use std::path::Path;
 
+struct Linker {
+    cc: String,
+    pie: bool,
+    debug: bool,
+    flags: Vec<String>,
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
-        eprintln!("Usage: {} <source.per> [--elf|--nvm-code|--novaria|--pe-c]", args[0]);
+        eprintln!("Usage: {} <source.per>... [-o <output>] [--elf|--elf-arm64|--nvm-code|--novaria|--pe-c|--wasm] [--verbose] [--keep-exported] [--lib] [--mangle] [-Werror] [--entry <name>] [--pie] [--cc <path>] [--inline-threshold <n>] [--max-array-size <n>] [--stack-size <n>] [--nvm-version <n>] [--emit-map] [--annotate] [-g] [--stdout]", args[0]);
+        eprintln!("       {} --disasm <program.bin>", args[0]);
+        eprintln!("Run `{} --help` for a description of each flag", args[0]);
         process::exit(1);
     }
 
-    let source_file = &args[1];
-    let source = match fs::read_to_string(source_file) {
-        Ok(s) => s,
-        Err(e) => {
-            let err = error::CompileError::new(
-                error::ErrorKind::ModuleError,
-                format!("failed to read source file: {}", e),
-                source_file.to_string(),
-                1,
-                1,
-            );
-            err.display();
-            process::exit(1);
-        }
-    };
-
-    let mut lexer = lexer::Lexer::new_with_file(&source, source_file);
-    let tokens = lexer.tokenize();
+    if args[1] == "--disasm" {
+        disassemble_file(args.get(2));
+        return;
+    }
 
-    let mut parser = parser::Parser::new(tokens, source_file);
-    let mut ast = match parser.parse() {
-        Ok(ast) => ast,
-        Err(e) => {
-            e.display();
-            process::exit(1);
-        }
+    let mut source_files: Vec<String> = Vec::new();
+    let mut output_override: Option<String> = None;
+    let mut target_flag: Option<&str> = None;
+    let mut verbose = false;
+    let mut emit_map = false;
+    let mut to_stdout = false;
+    let mut compiler = Compiler::new(Target::Elf);
+    let mut linker = Linker {
+        cc: env::var("CC").ok().filter(|cc| !cc.is_empty()).unwrap_or_else(|| "gcc".to_string()),
+        pie: false,
+        debug: false,
+        flags: env::var("LDFLAGS").map(|f| f.split_whitespace().map(str::to_string).collect()).unwrap_or_default(),
     };
 
-    let source_dir = std::path::Path::new(source_file).parent().unwrap_or(std::path::Path::new("."));
-    if let Err(e) = load_modules(&mut ast, source_dir, &mut std::collections::HashSet::new()) {
-        e.display();
-        process::exit(1);
+    let mut flags = args[1..].iter();
+    while let Some(arg) = flags.next() {
+        match arg.as_str() {
+            "--verbose" => verbose = true,
+            "--keep-exported" => compiler.keep_exported = true,
+            "--lib" => compiler.lib = true,
+            "--mangle" => compiler.mangle = true,
+            "--emit-map" => emit_map = true,
+            "--stdout" => to_stdout = true,
+            "--annotate" => compiler.annotate = true,
+            "-Werror" | "--werror" => compiler.warnings_as_errors = true,
+            "--pie" => linker.pie = true,
+            "--version" | "-V" => {
+                print_version();
+                return;
+            }
+            "--help" | "-h" => {
+                print_help(&args[0]);
+                return;
+            }
+            "-g" | "--g" => {
+                compiler.debug_info = true;
+                linker.debug = true;
+            }
+            "--cc" => {
+                match flags.next() {
+                    Some(cc) => linker.cc = cc.clone(),
+                    None => {
+                        eprintln!("--cc expects a compiler command");
+                        process::exit(1);
+                    }
+                }
+            }
+            "-o" => {
+                match flags.next() {
+                    Some(path) => output_override = Some(path.clone()),
+                    None => {
+                        eprintln!("-o expects an output file");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--entry" => {
+                match flags.next() {
+                    Some(name) => compiler.entry = name.clone(),
+                    None => {
+                        eprintln!("--entry expects a function name");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--inline-threshold" => {
+                match flags.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => compiler.inline_threshold = Some(n),
+                    None => {
+                        eprintln!("--inline-threshold expects a statement count");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--max-array-size" => {
+                match flags.next().and_then(|n| n.parse().ok()) {
+                    Some(n) if n > 0 => compiler.max_array_size = n,
+                    _ => {
+                        eprintln!("--max-array-size expects a positive element count");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--stack-size" => {
+                match flags.next().and_then(|n| parse_size(n)) {
+                    Some(n) if n > 0 => compiler.stack_size = Some(n),
+                    _ => {
+                        eprintln!("--stack-size expects a size in bytes, optionally with a K, M or G suffix");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--nvm-version" => {
+                match flags.next().and_then(|n| n.parse().ok()) {
+                    Some(n) if n <= NVM_LATEST_VERSION => compiler.nvm_version = n,
+                    _ => {
+                        eprintln!("--nvm-version expects a version between 0 and {}", NVM_LATEST_VERSION);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--elf" => target_flag = Some("elf"),
+            "--elf-arm64" => target_flag = Some("elf-arm64"),
+            "--nvm-code" => target_flag = Some("nvm-code"),
+            "--novaria" => target_flag = Some("novaria"),
+            "--pe-asm" => target_flag = Some("pe-asm"),
+            "--wasm" => target_flag = Some("wasm"),
+            file if !file.starts_with('-') => source_files.push(file.to_string()),
+            _ => {
+                eprintln!("Unknown target: {}", arg);
+                eprintln!("Valid targets: --elf, --elf-arm64, --nvm-code, --novaria, --pe-asm, --wasm");
+                process::exit(1);
+            }
+        }
     }
 
-    let mut type_checker = typechecker::TypeChecker::new();
-    if let Err(errors) = type_checker.check_program(&ast) {
-        eprintln!("Type checking failed with {} error(s):", errors.len());
-        type_checker.print_errors();
+    let Some(source_file) = source_files.first() else {
+        eprintln!("No source file given");
         process::exit(1);
-    }
-
-    let target = if args.len() > 2 {
-        match args[2].as_str() {
-            "--elf" => "elf",
-            "--nvm-code" => "nvm-code",
-            "--novaria" => "novaria",
-            "--pe-asm" => "pe-asm",
-            _ => {
-                eprintln!("Unknown target: {}", args[2]);
-                eprintln!("Valid targets: --elf, --nvm-code, --novaria, --pe-asm");
+    };
+    compiler.file = source_file.clone();
+
+    let mut sources = Vec::new();
+    for file in &source_files {
+        match fs::read_to_string(file) {
+            Ok(s) => sources.push(s),
+            Err(e) => {
+                let err = error::CompileError::new(
+                    error::ErrorKind::ModuleError,
+                    format!("failed to read source file: {}", e),
+                    file.to_string(),
+                    1,
+                    1,
+                );
+                err.display();
                 process::exit(1);
             }
         }
+    }
+
+    let target = if let Some(t) = target_flag {
+        t
     } else if cfg!(target_os = "windows") {
         "pe"
     } else {
         "elf"
     };
 
-    let output_file = match target {
-        "nvm-code" => {
-            if source_file.ends_with(".per") {
-                source_file.replace(".per", ".asm")
-            } else {
-                format!("{}.asm", source_file)
+    compiler.target = match target {
+        "nvm-code" => Target::NvmCode,
+        "novaria" => Target::Novaria,
+        "elf" => Target::Elf,
+        "elf-arm64" => Target::ElfArm64,
+        "pe-asm" => Target::Pe,
+        "wasm" => Target::Wasm,
+        _ => Target::PeC,
+    };
+
+    let output_file = if let Some(path) = output_override {
+        path
+    } else {
+        match target {
+            "nvm-code" => {
+                if source_file.ends_with(".per") {
+                    source_file.replace(".per", ".asm")
+                } else {
+                    format!("{}.asm", source_file)
+                }
             }
-        }
-        "novaria" => {
-            if source_file.ends_with(".per") {
-                source_file.replace(".per", ".bin")
-            } else {
-                format!("{}.bin", source_file)
+            "novaria" => {
+                if source_file.ends_with(".per") {
+                    source_file.replace(".per", ".bin")
+                } else {
+                    format!("{}.bin", source_file)
+                }
             }
-        }
-        "elf" => {
-            if source_file.ends_with(".per") {
-                source_file[..source_file.len()-4].to_string()
-            } else if source_file.ends_with(".nl") {
-                source_file[..source_file.len()-4].to_string()
-            } else {
-                source_file.to_string()
+            "wasm" => {
+                if source_file.ends_with(".per") {
+                    source_file.replace(".per", ".wat")
+                } else {
+                    format!("{}.wat", source_file)
+                }
             }
-        }
-        _ => {
-            if source_file.ends_with(".per") {
-                source_file.replace(".per", ".exe")
-            } else {
-                source_file.replace(".go", ".exe")
+            "elf" | "elf-arm64" => {
+                if source_file.ends_with(".per") {
+                    source_file[..source_file.len()-4].to_string()
+                } else if source_file.ends_with(".nl") {
+                    source_file[..source_file.len()-4].to_string()
+                } else {
+                    source_file.to_string()
+                }
+            }
+            _ => {
+                if source_file.ends_with(".per") {
+                    source_file.replace(".per", ".exe")
+                } else {
+                    source_file.replace(".go", ".exe")
+                }
             }
         }
     };
 
-    match target {
-        "novaria" => {
-            compile_nvm(&ast, &output_file);
-        }
-        "nvm-code" => {
-            compile_nvm_asm(&ast, &output_file);
-        }
-        "elf" => {
-            compile_elf_proper(&ast, &output_file);
-        }
-        "pe-asm" => {
-            let mut codegen = pe::CodeGen::new(target);
-            let machine_code = codegen.generate(&ast);
-            let mut pe_writer = pe::PEWriter::new();
-            pe_writer.write(&output_file, &machine_code)
-                .expect("Failed to write executable");
-        }
-        _ => {
-            compile_pe_with_c(&ast, &output_file);
-        }
+    if emit_map && !matches!(target, "novaria" | "elf") {
+        eprintln!("Warning: --emit-map is only supported for --novaria and --elf, no map written");
+    }
+    if compiler.annotate && target != "elf" {
+        eprintln!("Warning: --annotate is only supported for --elf");
+    }
+    if compiler.debug_info && target != "elf" {
+        eprintln!("Warning: -g is only supported for --elf");
+    }
+    if compiler.stack_size.is_some() && target != "elf" {
+        eprintln!("Warning: --stack-size is only supported for --elf");
+    }
+    if compiler.mangle && !matches!(target, "elf" | "elf-arm64") {
+        eprintln!("Warning: --mangle is only supported for --elf and --elf-arm64");
     }
 
-    println!("Compilation successful: {}", output_file);
-}
-
-fn load_modules(ast: &mut ast::Program, base_dir: &Path, loaded: &mut HashSet<String>) -> error::Result<()> {
-    let imports = ast.imports.clone();
-
-    for import in &imports {
-        let module_name = import.path.clone();
+    let files: Vec<(&str, &str)> = source_files.iter().map(String::as_str).zip(sources.iter().map(String::as_str)).collect();
+    let output = match compiler.compile_files(&files) {
+        Ok(output) => output,
+        Err(e) => {
+            e.display();
+            process::exit(1);
+        }
+    };
 
-        if loaded.contains(&module_name) {
-            continue;
+    if verbose && !compiler.lib {
+        let note = format!("Dead code elimination: pruned {} unreachable function(s)", output.pruned);
+        if to_stdout {
+            eprintln!("{}", note);
+        } else {
+            println!("{}", note);
         }
+    }
 
-        loaded.insert(module_name.clone());
+    if emit_map && matches!(target, "novaria" | "elf") {
+        write_map(&output_file, &output.map);
+    }
 
-        
-        let module_filename = format!("{}.per", module_name);
-        
-        
-        let mut module_file = base_dir.join(&module_filename);
-        
-        
-        if !module_file.exists() {
-            module_file = Path::new("stdlib").join(&module_filename);
-        }
-        
-        
-        if !module_file.exists() {
-            if let Ok(exe_path) = env::current_exe() {
-                if let Some(exe_dir) = exe_path.parent() {
-                    module_file = exe_dir.join("stdlib").join(&module_filename);
-                }
-            }
+    if to_stdout {
+        let mut stdout = io::stdout();
+        if let Err(e) = stdout.write_all(&output.code).and_then(|_| stdout.flush()) {
+            eprintln!("Failed to write to stdout: {}", e);
+            process::exit(1);
         }
+        return;
+    }
 
-        let module_source = match fs::read_to_string(&module_file) {
-            Ok(s) => s,
-            Err(_) => {
-                return Err(error::CompileError::new(
-                    error::ErrorKind::ModuleError,
-                    format!("could not find module '{}'", module_name),
-                    module_file.to_string_lossy().to_string(),
-                    1,
-                    1,
-                ).with_source_line(format!("import \"{}\"", module_name)));
+    match compiler.target {
+        Target::Elf | Target::ElfArm64 => link_elf(&output.code, &output_file, &linker, compiler.annotate),
+        Target::PeC => compile_pe_with_c(&output.code, &output_file),
+        _ => {
+            if let Err(e) = fs::write(&output_file, &output.code) {
+                eprintln!("Failed to write {}: {}", output_file, e);
+                process::exit(1);
             }
-        };
-
-        let mut module_lexer = lexer::Lexer::new_with_file(&module_source, &module_file.to_string_lossy());
-        let module_tokens = module_lexer.tokenize();
-        let mut module_parser = parser::Parser::new(module_tokens, &module_file.to_string_lossy());
-        let mut module_ast = module_parser.parse()?;
-
-        load_modules(&mut module_ast, base_dir, loaded)?;
-
-        for (mod_name, module) in module_ast.modules {
-            ast.modules.insert(mod_name, module);
         }
-
-        let module = ast::Module {
-            name: module_name.clone(),
-            functions: module_ast.functions,
-        };
-
-        ast.modules.insert(module_name, module);
     }
 
-    Ok(())
+    println!("Compilation successful: {}", output_file);
 }
 
-fn compile_nvm(ast: &ast::Program, output_file: &str) {
-    use std::io::Write;
+const TARGETS: &[(&str, &str)] = &[
+    ("--elf", "x86-64 Linux executable, linked with cc (default on Linux)"),
+    ("--elf-arm64", "AArch64 Linux executable, linked with cc"),
+    ("--pe-asm", "Windows PE32+ executable from the machine-code backend"),
+    ("--novaria", "NVM bytecode for NovariaOS (.bin)"),
+    ("--nvm-code", "NVM assembly text (.asm)"),
+    ("--wasm", "WebAssembly text format (.wat)"),
+];
+
+fn print_version() {
+    println!("perano-lang {}", env!("CARGO_PKG_VERSION"));
+    let names: Vec<&str> = TARGETS.iter().map(|(flag, _)| &flag[2..]).collect();
+    println!("targets: {}", names.join(", "));
+}
 
-    let mut nvm_gen = nvm::NVMCodeGen::new();
-    let bytecode = nvm_gen.generate(ast);
+fn print_help(program: &str) {
+    println!("perano-lang {}", env!("CARGO_PKG_VERSION"));
+    println!();
+    println!("Usage: {} <source.per>... [options]", program);
+    println!("       {} --disasm <program.bin>", program);
+    println!();
+    println!("Targets:");
+    for (flag, description) in TARGETS {
+        println!("  {:<26}{}", flag, description);
+    }
+    println!();
+    println!("Options:");
+    let options = [
+        ("-o <output>", "Write the output to <output> instead of next to the first source"),
+        ("--stdout", "Write the generated code to stdout instead of a file"),
+        ("--entry <name>", "Start the program at <name> instead of main"),
+        ("--lib", "Compile without an entry point"),
+        ("--mangle", "Prefix function symbols with the package name (--elf, --elf-arm64)"),
+        ("--keep-exported", "Keep exported functions that are never called"),
+        ("--verbose", "Report how many unreachable functions were pruned"),
+        ("-Werror", "Treat warnings as errors"),
+        ("-g", "Emit DWARF line info (--elf)"),
+        ("--annotate", "Keep the assembly, commented with the source (--elf)"),
+        ("--emit-map", "Write a .map file of function locations (--elf, --novaria)"),
+        ("--pie", "Link a position-independent executable (--elf)"),
+        ("--cc <path>", "Compiler used to assemble and link (default: $CC or gcc)"),
+        ("--inline-threshold <n>", "Inline functions of at most <n> statements (--novaria)"),
+        ("--max-array-size <n>", "Largest array length accepted"),
+        ("--stack-size <n>", "Run on an <n>-byte stack, e.g. 64M (--elf)"),
+        ("--nvm-version <n>", "NVM bytecode version to emit (--novaria)"),
+        ("--disasm <file>", "Disassemble NVM bytecode"),
+        ("--version", "Print the version and supported targets"),
+        ("--help", "Print this help"),
+    ];
+    for (flag, description) in options {
+        println!("  {:<26}{}", flag, description);
+    }
+}
 
-    let mut file = fs::File::create(output_file).expect("Failed to create .nvm file");
-    file.write_all(&bytecode).expect("Failed to write NVM bytecode");
+/// Parses a byte count such as `65536`, `512K` or `64M`.
+fn parse_size(text: &str) -> Option<usize> {
+    let (digits, unit) = match text.char_indices().last()? {
+        (i, 'k' | 'K') => (&text[..i], 1 << 10),
+        (i, 'm' | 'M') => (&text[..i], 1 << 20),
+        (i, 'g' | 'G') => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
 }
 
-fn compile_nvm_asm(ast: &ast::Program, output_file: &str) {
-    use std::io::Write;
+/// Writes the `--emit-map` sidecar next to `output_file`: one `location name` line per function.
+fn write_map(output_file: &str, lines: &[String]) {
+    let map_file = Path::new(output_file).with_extension("map");
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    if let Err(e) = fs::write(&map_file, text) {
+        eprintln!("Failed to write {}: {}", map_file.display(), e);
+        process::exit(1);
+    }
+}
 
-    let mut nvm_asm_gen = nvm::NVMAssemblyGenerator::new();
-    let asm_code = nvm_asm_gen.generate(ast);
+fn disassemble_file(path: Option<&String>) {
+    let path = match path {
+        Some(p) => p,
+        None => {
+            eprintln!("--disasm expects a .bin file");
+            process::exit(1);
+        }
+    };
 
-    let mut file = fs::File::create(output_file).expect("Failed to create .asm file");
-    file.write_all(asm_code.as_bytes()).expect("Failed to write NVM assembly");
+    match fs::read(path) {
+        Ok(bytecode) => print!("{}", disassemble(&bytecode)),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            process::exit(1);
+        }
+    }
 }
 
-fn compile_pe_with_c(ast: &ast::Program, output_file: &str) {
-    let mut c_gen = pe::c_codegen::CCodeGen::new();
-    let c_code = c_gen.generate(ast).expect("Failed to generate C code");
-    
-    if let Err(e) = c_gen.compile_c_code(&c_code, output_file) {
+fn compile_pe_with_c(c_code: &[u8], output_file: &str) {
+    let c_code = String::from_utf8_lossy(c_code);
+    if let Err(e) = compile_c_code(&c_code, output_file) {
         eprintln!("Failed to compile C code: {}", e);
         eprintln!("Make sure cl.exe is available (run from Developer Command Prompt)");
         process::exit(1);
     }
 }
 
-fn compile_elf_proper(ast: &ast::Program, output_file: &str) {
-    use std::io::Write;
-
-    let mut asm_gen = elf::AsmGenerator::new();
-    let asm_code = asm_gen.generate(ast);
-
+fn link_elf(asm_code: &[u8], output_file: &str, linker: &Linker, annotate: bool) {
     let asm_file = format!("{}.s", output_file);
-    let mut file = fs::File::create(&asm_file).expect("Failed to create .s file");
-    file.write_all(asm_code.as_bytes()).expect("Failed to write assembly");
-
-    let status = process::Command::new("gcc")
-        .arg("-o")
-        .arg(output_file)
-        .arg(&asm_file)
-        .arg("-no-pie")
-        .status();
+    if let Err(e) = fs::write(&asm_file, asm_code) {
+        eprintln!("Failed to write {}: {}", asm_file, e);
+        process::exit(1);
+    }
+    let mut cc = process::Command::new(&linker.cc);
+    cc.arg("-o").arg(output_file).arg(&asm_file);
+    cc.arg(if linker.pie { "-pie" } else { "-no-pie" });
+    if linker.debug {
+        cc.arg("-g");
+    }
+    cc.args(&linker.flags);
+    let status = cc.status();
 
     match status {
         Ok(s) if s.success() => {
-            let _ = fs::remove_file(&asm_file);
+            if annotate {
+                println!("Annotated assembly kept at: {}", asm_file);
+            } else {
+                let _ = fs::remove_file(&asm_file);
+            }
             println!("Compilation successful: {}", output_file);
         }
         Ok(s) => {
-            eprintln!("GCC failed with exit code: {:?}", s.code());
+            eprintln!("{} failed with exit code: {:?}", linker.cc, s.code());
             eprintln!("Assembly file kept at: {}", asm_file);
             process::exit(1);
         }
         Err(e) => {
-            eprintln!("Failed to run gcc: {}", e);
-            eprintln!("Make sure gcc is installed");
+            eprintln!("Failed to run {}: {}", linker.cc, e);
+            eprintln!("Make sure {} is installed, or choose another compiler with --cc or CC", linker.cc);
             eprintln!("Assembly file kept at: {}", asm_file);
             process::exit(1);
         }
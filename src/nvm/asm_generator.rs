@@ -1,32 +1,59 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::error::{CompileError, CompileWarning, ErrorKind};
+use super::codegen::{NVMCodeGen, PrintfPiece, NOVARIA_SYSCALLS, PRINT_BIN_HELPER, PRINT_INT_HELPER, VGA_BASE, VGA_DEFAULT_ATTR, VGA_END, VGA_ROW_BYTES};
+use std::collections::{HashMap, HashSet};
 
 pub struct NVMAssemblyGenerator {
     output: String,
-    #[allow(dead_code)]
-    labels: HashMap<String, String>,
     label_counter: u32,
     local_vars: HashMap<String, u8>,
     next_local: u8,
     loop_stack: Vec<(String, String)>,
     current_function: String,
+    current_module: Option<String>,
+    return_label: Option<String>,
+    string_literals: Vec<(String, String)>,
+    compile_time_strings: HashMap<String, String>,
+    string_vars: HashSet<String>,
+    source: SourceCursor,
     vga_cursor: u32,
+    entry: String,
+    uses_print_bin: bool,
+    error: Option<CompileError>,
+    warnings: Vec<CompileWarning>,
 }
 
 impl NVMAssemblyGenerator {
     pub fn new() -> Self {
         Self {
             output: String::new(),
-            labels: HashMap::new(),
             label_counter: 0,
             local_vars: HashMap::new(),
             next_local: 0,
             loop_stack: Vec::new(),
             current_function: String::new(),
-            vga_cursor: 0xB8000 + (18 * 160),
+            current_module: None,
+            return_label: None,
+            string_literals: Vec::new(),
+            compile_time_strings: HashMap::new(),
+            string_vars: HashSet::new(),
+            source: SourceCursor::default(),
+            vga_cursor: VGA_BASE + (18 * VGA_ROW_BYTES),
+            entry: "main".to_string(),
+            uses_print_bin: false,
+            error: None,
+            warnings: Vec::new(),
         }
     }
-    
+
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
+    }
+
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
     fn has_return_or_exit(&self, stmts: &[Statement]) -> bool {
         for stmt in stmts {
             match stmt {
@@ -50,7 +77,7 @@ impl NVMAssemblyGenerator {
                         }
                     }
                 }
-                Statement::For { body, .. } => {
+                Statement::For { body, .. } | Statement::Block(body) => {
                     if self.has_return_or_exit(body) {
                         return true;
                     }
@@ -61,71 +88,107 @@ impl NVMAssemblyGenerator {
         false
     }
 
-    pub fn generate(&mut self, program: &Program) -> String {
-        
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    pub fn generate(&mut self, program: &Program) -> crate::error::Result<String> {
+
         self.output.push_str(".NVM0\n");
         self.output.push_str("; Generated by Perano Language Compiler\n\n");
 
-        
-        if let Some(main_func) = program.functions.iter().find(|f| f.name == "main") {
+
+        if let Some(main_func) = program.functions.iter().find(|f| f.name == self.entry) {
             self.generate_function(main_func, program);
         }
 
-        
+
         for func in &program.functions {
-            if func.name != "main" {
+            if func.name != self.entry {
                 self.generate_function(func, program);
             }
         }
 
-        
+
         for (module_name, module) in &program.modules {
-            if module_name == "stdio" {
+            if module_name == "stdio" || module_name == "vga" || module_name == "sys" || module_name == "time" {
                 continue;
             }
+            self.current_module = Some(module_name.clone());
             for func in &module.functions {
-                if func.is_exported {
-                    let full_name = format!("{}_{}", module.name, func.name);
-                    self.generate_module_function(func, &full_name, program);
-                }
+                let full_name = format!("{}_{}", module.name, func.name);
+                self.generate_module_function(func, &full_name, program);
             }
+            self.current_module = None;
+        }
+
+        if program.modules.contains_key("stdio") {
+            self.generate_print_int_helper();
+        }
+
+        if self.uses_print_bin {
+            self.generate_print_bin_helper();
+        }
+
+        if program.modules.contains_key("vga") {
+            self.generate_vga_scroll_helper();
         }
 
-        self.output.clone()
+        self.emit_string_literals();
+
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(self.output.clone()),
+        }
     }
 
     fn generate_function(&mut self, func: &Function, program: &Program) {
         self.current_function = func.name.clone();
         self.local_vars.clear();
+        self.compile_time_strings.clear();
+        self.string_vars = NVMCodeGen::string_params(func);
+        self.source = SourceCursor::new(func);
         self.next_local = 0;
 
         self.output.push_str(&format!("; Function: {}\n", func.name));
         self.output.push_str(&format!("fn_{}:\n", func.name));
 
-        
+
         for (i, param) in func.params.iter().enumerate() {
             self.local_vars.insert(param.name.clone(), i as u8);
             self.next_local = (i + 1) as u8;
             self.output.push_str(&format!("    ; param: {} -> local {}\n", param.name, i));
         }
 
-        
+
         for stmt in &func.body {
             self.generate_statement(stmt, program);
         }
 
-        if func.name == "main" && !self.has_return_or_exit(&func.body) {
+        if func.name == self.entry && !self.has_return_or_exit(&func.body) {
             self.output.push_str("    ; Main returns 0 by default\n");
             self.output.push_str("    push 0\n");
             self.output.push_str("    syscall exit\n");
+            self.output.push_str("    ret\n\n");
+        } else {
+            self.emit_fallthrough_return(func);
         }
-        
-        self.output.push_str("    ret\n\n");
     }
 
     fn generate_module_function(&mut self, func: &Function, full_name: &str, program: &Program) {
         self.current_function = full_name.to_string();
         self.local_vars.clear();
+        self.string_vars = NVMCodeGen::string_params(func);
+        self.source = SourceCursor::new(func);
         self.next_local = 0;
 
         self.output.push_str(&format!("; Module Function: {}\n", full_name));
@@ -137,118 +200,212 @@ impl NVMAssemblyGenerator {
             self.output.push_str(&format!("    ; param: {} -> local {}\n", param.name, i));
         }
 
-        for stmt in &func.body {
-            self.generate_statement(stmt, program);
+        let return_label = format!("fn_{}_return", full_name);
+        self.return_label = Some(return_label.clone());
+
+        match func.body.split_last() {
+            Some((Statement::Return(value), rest)) => {
+                for stmt in rest {
+                    self.generate_statement(stmt, program);
+                }
+                self.output.push_str("    ; return\n");
+                self.generate_return_value(value.as_ref(), program);
+            }
+            _ => {
+                for stmt in &func.body {
+                    self.generate_statement(stmt, program);
+                }
+                self.emit_fallthrough_value(func);
+            }
         }
 
+        self.return_label = None;
+        self.output.push_str(&format!("{}:\n", return_label));
         self.output.push_str("    ret\n\n");
     }
 
+    fn emit_fallthrough_return(&mut self, func: &Function) {
+        if !always_returns(&func.body) {
+            self.emit_fallthrough_value(func);
+            self.output.push_str("    ret\n");
+        }
+        self.output.push('\n');
+    }
+
+    fn emit_fallthrough_value(&mut self, func: &Function) {
+        if !always_returns(&func.body) {
+            self.warnings.extend(NVMCodeGen::missing_return_warning(func, &self.current_function));
+            self.output.push_str("    ; implicit return\n");
+            for _ in 0..NVMCodeGen::return_arity(func) {
+                self.output.push_str("    push 0\n");
+            }
+        }
+    }
+
+    fn generate_return_value(&mut self, value: Option<&Expression>, program: &Program) {
+        match value {
+            Some(expr) => self.generate_expression(expr, program),
+            None => self.output.push_str("    push 0\n"),
+        }
+    }
+
+    fn generate_block(&mut self, body: &[Statement], program: &Program) {
+        let saved_vars = self.local_vars.clone();
+        let saved_strings = self.compile_time_strings.clone();
+        let saved_string_vars = self.string_vars.clone();
+        for stmt in body {
+            self.generate_statement(stmt, program);
+        }
+        self.local_vars = saved_vars;
+        self.compile_time_strings = saved_strings;
+        self.string_vars = saved_string_vars;
+    }
+
     fn generate_statement(&mut self, stmt: &Statement, program: &Program) {
+        self.source.advance();
         match stmt {
             Statement::VarDecl { name, var_type, value } => {
-                self.output.push_str(&format!("    ; var {} {}\n", name, 
+                self.output.push_str(&format!("    ; var {} {}\n", name,
                     var_type.as_ref().map(|t| t.as_str()).unwrap_or("int")));
-                
+
+                let is_string = var_type.as_deref() == Some("string")
+                    || value.as_ref().is_some_and(|v| self.is_string_expression(v, program));
+                if is_string {
+                    self.string_vars.insert(name.clone());
+                } else {
+                    self.string_vars.remove(name);
+                }
                 if let Some(init_expr) = value {
+                    if let Expression::String(s) = init_expr {
+                        self.compile_time_strings.insert(name.clone(), s.clone());
+                    }
                     self.generate_expression(init_expr, program);
                 } else {
                     self.output.push_str("    push 0\n");
                 }
-                
+
                 let local_index = self.next_local;
                 self.local_vars.insert(name.clone(), local_index);
                 self.next_local += 1;
-                
+
                 self.output.push_str(&format!("    store {}\n", local_index));
             }
 
+            Statement::MultiVarDecl { names, value } => {
+                self.output.push_str(&format!("    ; var {}\n", names.join(", ")));
+                self.generate_expression(value, program);
+
+                let slots: Vec<u8> = names.iter().map(|name| {
+                    let local_index = self.next_local;
+                    self.local_vars.insert(name.clone(), local_index);
+                    self.next_local += 1;
+                    local_index
+                }).collect();
+
+                for local_index in slots.into_iter().rev() {
+                    self.output.push_str(&format!("    store {}\n", local_index));
+                }
+            }
+
             Statement::Assignment { name, value } => {
                 self.output.push_str(&format!("    ; {} = ...\n", name));
                 self.generate_expression(value, program);
-                
+
                 if let Some(&local_index) = self.local_vars.get(name) {
                     self.output.push_str(&format!("    store {}\n", local_index));
                 } else {
-                    self.output.push_str(&format!("    ; ERROR: Variable not found: {}\n", name));
+                    self.fail(format!("assignment to undeclared variable '{}'; declare it with `var {} = ...` first", name, name));
+                    self.output.push_str("    pop\n");
                 }
             }
 
             Statement::If { condition, then_body, else_body } => {
                 self.output.push_str("    ; if condition\n");
                 self.generate_expression(condition, program);
-                
+
                 let else_label = self.generate_label("else");
                 let end_label = self.generate_label("endif");
-                
-                self.output.push_str(&format!("    jz32 {}\n", else_label));
-                
+
+                self.output.push_str(&format!("    jz {}\n", else_label));
+
                 self.output.push_str("    ; then block\n");
-                for stmt in then_body {
-                    self.generate_statement(stmt, program);
-                }
-                
-                self.output.push_str(&format!("    jmp32 {}\n", end_label));
-                
+                self.generate_block(then_body, program);
+
+                self.output.push_str(&format!("    jmp {}\n", end_label));
+
                 self.output.push_str(&format!("{}:\n", else_label));
-                
+
                 if let Some(else_stmts) = else_body {
                     self.output.push_str("    ; else block\n");
-                    for stmt in else_stmts {
-                        self.generate_statement(stmt, program);
-                    }
+                    self.generate_block(else_stmts, program);
                 }
-                
+
                 self.output.push_str(&format!("{}:\n", end_label));
             }
 
+            Statement::Block(body) => self.generate_block(body, program),
+
             Statement::For { init, condition, post, body } => {
                 self.output.push_str("    ; for loop\n");
-                
+                let saved_vars = self.local_vars.clone();
+                let saved_strings = self.compile_time_strings.clone();
+                let saved_string_vars = self.string_vars.clone();
+
                 if let Some(init_stmt) = init {
                     self.output.push_str("    ; init\n");
                     self.generate_statement(init_stmt, program);
                 }
-                
+
                 let loop_start = self.generate_label("for_start");
                 let loop_end = self.generate_label("for_end");
                 let loop_continue = self.generate_label("for_continue");
-                
+
                 self.loop_stack.push((loop_end.clone(), loop_continue.clone()));
-                
+
                 self.output.push_str(&format!("{}:\n", loop_start));
-                
+
                 if let Some(cond) = condition {
                     self.output.push_str("    ; condition\n");
                     self.generate_expression(cond, program);
-                    self.output.push_str(&format!("    jz32 {}\n", loop_end));
+                    self.output.push_str(&format!("    jz {}\n", loop_end));
                 }
-                
+
                 self.output.push_str("    ; body\n");
-                for stmt in body {
-                    self.generate_statement(stmt, program);
-                }
-                
+                self.generate_block(body, program);
+
                 self.output.push_str(&format!("{}:\n", loop_continue));
-                
+
                 if let Some(post_stmt) = post {
                     self.output.push_str("    ; post\n");
                     self.generate_statement(post_stmt, program);
                 }
-                
-                self.output.push_str(&format!("    jmp32 {}\n", loop_start));
-                
+
+                self.output.push_str(&format!("    jmp {}\n", loop_start));
+
                 self.output.push_str(&format!("{}:\n", loop_end));
                 self.loop_stack.pop();
+                self.local_vars = saved_vars;
+                self.compile_time_strings = saved_strings;
+                self.string_vars = saved_string_vars;
             }
 
             Statement::Return(value) => {
-                if let Some(_expr) = value {
+                self.output.push_str("    ; return\n");
+                self.generate_return_value(value.as_ref(), program);
+                if self.current_function == self.entry {
+                    self.output.push_str("    syscall exit\n");
+                } else if let Some(label) = &self.return_label {
+                    self.output.push_str(&format!("    jmp {}\n", label));
+                } else {
+                    self.output.push_str("    ret\n");
                 }
             }
 
             Statement::Expression(expr) => {
                 self.generate_expression(expr, program);
+                for _ in 0..NVMCodeGen::values_left(expr) {
+                    self.output.push_str("    pop\n");
+                }
             }
 
             Statement::PointerAssignment { target, value } => {
@@ -260,23 +417,16 @@ impl NVMAssemblyGenerator {
 
             Statement::InlineAsm { parts } => {
                 use crate::ast::AsmPart;
-                
+
                 self.output.push_str("    ; inline asm\n");
-                
+
                 for part in parts {
                     match part {
-                        AsmPart::Literal(s) => {
-                            for line in s.lines() {
-                                let trimmed = line.trim();
-                                if !trimmed.is_empty() {
-                                    self.output.push_str("    ");
-                                    self.output.push_str(trimmed);
-                                    self.output.push_str("\n");
-                                }
-                            }
-                        }
+                        AsmPart::Literal(s) => self.emit_asm_lines(s),
                         AsmPart::Variable(var_name) => {
-                            if let Some(&local_index) = self.local_vars.get(var_name) {
+                            if let Some(string_value) = self.compile_time_strings.get(var_name).cloned() {
+                                self.emit_asm_lines(&string_value);
+                            } else if let Some(&local_index) = self.local_vars.get(var_name) {
                                 self.output.push_str(&format!("    load {}\n", local_index));
                             } else {
                                 self.output.push_str(&format!("    ; ERROR: Unknown variable: {}\n", var_name));
@@ -292,49 +442,125 @@ impl NVMAssemblyGenerator {
         }
     }
 
+    fn emit_asm_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.ends_with(':') {
+                let name = trimmed.trim_end_matches(':');
+                self.output.push_str(&format!("asm_{}_{}:\n", self.current_function, name));
+                continue;
+            }
+            let mut parts = trimmed.split_whitespace();
+            match (parts.next().map(str::to_lowercase).as_deref(), parts.next()) {
+                (Some(jump @ ("jmp" | "jz" | "jnz")), Some(target)) => {
+                    self.output.push_str(&format!("    {} asm_{}_{}\n", jump, self.current_function, target));
+                }
+                _ => {
+                    self.output.push_str("    ");
+                    self.output.push_str(trimmed);
+                    self.output.push('\n');
+                }
+            }
+        }
+    }
+
+    fn is_string_expression(&self, expr: &Expression, program: &Program) -> bool {
+        NVMCodeGen::string_typed(&self.string_vars, expr, program, self.current_module.as_deref())
+    }
+
     fn generate_expression(&mut self, expr: &Expression, program: &Program) {
         match expr {
             Expression::Number(n) => {
+                if i32::try_from(*n).is_err() {
+                    self.fail(format!(
+                        "integer literal {} in function '{}' does not fit in a 32-bit NVM word",
+                        n, self.current_function
+                    ));
+                }
                 self.output.push_str(&format!("    push {}\n", n));
             }
 
-            Expression::String(_s) => {
-                self.output.push_str("    push 0  ; string not supported\n");
+            Expression::String(s) => {
+                let string_label = self.generate_label("str");
+                self.string_literals.push((string_label.clone(), s.clone()));
+                self.output.push_str(&format!("    push {}\n", string_label));
             }
 
             Expression::TemplateString { parts } => {
                 use crate::ast::TemplateStringPart;
-                
+
                 self.output.push_str("    ; template string\n");
-                
+
                 for part in parts {
                     match part {
-                        TemplateStringPart::Literal(lit) => {
-                            for ch in lit.as_bytes() {
-                                self.emit_vga_char(*ch, 0x07);
+                        TemplateStringPart::Literal(lit) => self.emit_print_chars(lit),
+                        TemplateStringPart::Expression { expr, format } => {
+                            match NVMCodeGen::template_text(&self.compile_time_strings, expr, format.as_ref()) {
+                                Some(text) => self.emit_print_chars(&text),
+                                None => match NVMCodeGen::binary_spec(format.as_ref()) {
+                                    Some(Ok((width, padding))) => {
+                                        self.generate_expression(expr, program);
+                                        self.output.push_str(&format!("    push {}\n", width));
+                                        self.output.push_str(&format!("    push {}\n", padding as u32));
+                                        self.output.push_str("    call __print_bin\n");
+                                        self.uses_print_bin = true;
+                                    }
+                                    Some(Err(message)) => self.fail(message),
+                                    None => {
+                                        if let Some(message) = NVMCodeGen::runtime_format_error(format.as_ref(), "NVM") {
+                                            self.fail(message);
+                                        }
+                                        self.generate_expression(expr, program);
+                                        self.output.push_str("    call __print_int\n");
+                                    }
+                                },
                             }
                         }
-                        TemplateStringPart::Expression { expr, format: _ } => {
-                            self.generate_expression(expr, program);
-                            self.output.push_str("    call __print_int_vga\n");
-                        }
                     }
                 }
+                self.output.push_str("    push 0\n");
             }
 
             Expression::Identifier(name) => {
                 if let Some(&local_index) = self.local_vars.get(name) {
                     self.output.push_str(&format!("    load {}  ; {}\n", local_index, name));
                 } else {
-                    self.output.push_str(&format!("    ; ERROR: Variable not found: {}\n", name));
+                    self.fail(format!("Variable not found: {}", name));
                     self.output.push_str("    push 0\n");
                 }
             }
 
+            Expression::Binary { op: BinaryOp::Mul, left, .. } if self.is_string_expression(left, program) => {
+                self.fail("string repetition needs dynamic allocation, which NVM does not have; repeat a literal by a literal count instead".to_string());
+                self.output.push_str("    push 0\n");
+            }
+
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                let short_label = self.generate_label("short");
+                let end_label = self.generate_label("endbool");
+
+                self.generate_expression(left, program);
+                let jump = if *op == BinaryOp::And { "jz" } else { "jnz" };
+                self.output.push_str(&format!("    {} {}\n", jump, short_label));
+
+                self.generate_expression(right, program);
+                self.output.push_str("    push 0\n");
+                self.output.push_str("    neq\n");
+                self.output.push_str(&format!("    jmp {}\n", end_label));
+
+                self.output.push_str(&format!("{}:\n", short_label));
+                let short_value = if *op == BinaryOp::And { 0 } else { 1 };
+                self.output.push_str(&format!("    push {}\n", short_value));
+                self.output.push_str(&format!("{}:\n", end_label));
+            }
+
             Expression::Binary { op, left, right } => {
                 self.generate_expression(left, program);
                 self.generate_expression(right, program);
-                
+
                 match op {
                     BinaryOp::Add => self.output.push_str("    add\n"),
                     BinaryOp::Sub => self.output.push_str("    sub\n"),
@@ -363,7 +589,7 @@ impl NVMAssemblyGenerator {
 
             Expression::Unary { op, operand } => {
                 self.generate_expression(operand, program);
-                
+
                 match op {
                     UnaryOp::Neg => {
                         self.output.push_str("    push 0\n");
@@ -377,67 +603,175 @@ impl NVMAssemblyGenerator {
                 }
             }
 
+            Expression::Call { function, args }
+                if NVMCodeGen::is_int_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.generate_int_builtin(function, args, program);
+            }
+
+            Expression::Call { function, args }
+                if NVMCodeGen::is_fatal_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.generate_fatal_builtin(function, &args[0], program);
+            }
+
+            Expression::Call { function, .. }
+                if (function == "itoa" || function == "parseInt") && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.fail(format!("{} is not supported on the NVM target", function));
+            }
+
             Expression::Call { function, args } => {
                 self.output.push_str(&format!("    ; call {}\n", function));
-                
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg, program);
+
+                let is_variadic = program.functions.iter()
+                    .find(|f| &f.name == function)
+                    .is_some_and(|f| f.params.iter().any(|p| p.is_variadic));
+                if is_variadic && self.current_module.as_deref().and_then(|m| program.module_function(m, function)).is_none() {
+                    self.fail(format!("variadic function '{}' is not supported on the NVM target", function));
+                    return;
                 }
-                
-                for (i, _) in args.iter().enumerate() {
-                    let param_index = i as u8;
-                    self.output.push_str(&format!("    store {}\n", param_index));
+
+                self.generate_call_args(args, program);
+
+                match self.current_module.as_deref().filter(|m| program.module_function(m, function).is_some()) {
+                    Some(module) => self.output.push_str(&format!("    call fn_{}_{}\n", module, function)),
+                    None => self.output.push_str(&format!("    call fn_{}\n", function)),
                 }
-                
-                self.output.push_str(&format!("    call fn_{}\n", function));
             }
 
             Expression::ModuleCall { module, function, args } => {
-                if module == "stdio" {
-                    if function == "Print" || function == "Println" {
-                        self.output.push_str(&format!("    ; call {}.{}\n", module, function));
-                        if !args.is_empty() {
-                            if let Expression::String(s) = &args[0] {
-                                for ch in s.as_bytes() {
-                                    self.emit_vga_char(*ch, 0x07);
-                                }
-                                if function == "Println" {
-                                    self.emit_vga_newline();
-                                }
-                            } else if let Expression::TemplateString { .. } = &args[0] {
-                                self.generate_expression(&args[0], program);
-                                if function == "Println" {
-                                    self.emit_vga_newline();
-                                }
-                            } else {
-                                self.generate_expression(&args[0], program);
-                                self.output.push_str("    call __print_int_vga\n");
-                                if function == "Println" {
-                                    self.emit_vga_newline();
-                                }
+                self.output.push_str(&format!("    ; call {}.{}\n", module, function));
+
+                let print_name = NVMCodeGen::stdio_print_name(function, args);
+                if module == "stdio" && (print_name == "Print" || print_name == "Println") && !args.is_empty() {
+                    let newline = print_name == "Println";
+                    match &args[0] {
+                        Expression::String(s) => {
+                            self.emit_print_chars(s);
+                            if newline {
+                                self.emit_print_chars("\n");
                             }
+                            self.output.push_str("    push 0\n");
+                        }
+                        Expression::TemplateString { .. } => {
+                            self.generate_expression(&args[0], program);
+                            if newline {
+                                self.emit_print_chars("\n");
+                            }
+                        }
+                        arg => {
+                            self.generate_expression(arg, program);
+                            self.output.push_str("    call __print_int\n");
+                            if newline {
+                                self.emit_print_chars("\n");
+                            }
+                            self.output.push_str("    push 0\n");
                         }
-                        return;
                     }
+                    return;
                 }
 
-                self.output.push_str(&format!("    ; call {}.{}\n", module, function));
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg, program);
+                if module == "stdio" && function == "Printf" {
+                    self.generate_printf(args, program);
+                    self.output.push_str("    push 0\n");
+                    return;
+                }
+
+                if module == "stdio" && function == "PrintPtr" && args.len() == 1 {
+                    self.generate_expression(&args[0], program);
+                    self.generate_print_ptr();
+                    self.output.push_str("    push 0\n");
+                    return;
+                }
+
+                if module == "stdio" {
+                    self.fail(format!("stdio.{} is not supported on the NVM target", function));
+                    self.output.push_str("    push 0\n");
+                    return;
+                }
+
+                if module == "vga" {
+                    self.generate_vga_call(function, args, program);
+                    self.output.push_str("    push 0\n");
+                    return;
+                }
+
+                if module == "sys" {
+                    self.fail(format!("sys.{} is only supported on ELF targets", function));
+                    self.output.push_str("    push 0\n");
+                    return;
                 }
+
+                if module == "time" {
+                    self.fail(format!("time.{} is only supported on the x86-64 targets", function));
+                    self.output.push_str("    push 0\n");
+                    return;
+                }
+
+                if module == "novaria" {
+                    if function == "FileCreateStr" && args.len() >= 2 {
+                        if let (Expression::String(filename), Expression::String(content)) = (&args[0], &args[1]) {
+                            let skip_label = self.generate_label("skip_strings");
+                            self.output.push_str(&format!("    push {}\n", content.len()));
+                            self.output.push_str("    push 0\n");
+                            self.output.push_str("    push 0\n");
+                            self.output.push_str("    syscall create\n");
+                            self.output.push_str(&format!("    jmp {}\n", skip_label));
+                            self.output.push_str(&format!("    .string {:?}\n", filename));
+                            self.output.push_str(&format!("    .string {:?}\n", content));
+                            self.output.push_str(&format!("{}:\n", skip_label));
+                            self.output.push_str("    push 0\n");
+                            return;
+                        }
+                    }
+
+                    if let Some(&(_, mnemonic, _, arity)) = NOVARIA_SYSCALLS.iter().find(|(name, ..)| name == function) {
+                        if args.len() != arity {
+                            self.fail(format!(
+                                "novaria.{} takes {} argument(s), got {} in '{}'",
+                                function, arity, args.len(), self.current_function
+                            ));
+                            return;
+                        }
+                        self.generate_call_args(args, program);
+                        self.output.push_str(&format!("    syscall {}\n", mnemonic));
+                        return;
+                    }
+
+                    self.generate_call_args(args, program);
+
+                    let line = match function.as_str() {
+                        "CAP_FS_READ" => "push 1".to_string(),
+                        "CAP_FS_WRITE" => "push 2".to_string(),
+                        "CAP_FS_CREATE" => "push 4".to_string(),
+                        "CAP_FS_DELETE" => "push 8".to_string(),
+                        "CAP_DRV_ACCESS" => "push 16".to_string(),
+                        "CAP_CAPS_MGMT" => "push 32".to_string(),
+                        "CAP_ALL" => "push 65535".to_string(),
+                        _ => format!("call fn_{}_{}", module, function),
+                    };
+                    self.output.push_str(&format!("    {}\n", line));
+                    return;
+                }
+
+                self.generate_call_args(args, program);
                 self.output.push_str(&format!("    call fn_{}_{}\n", module, function));
             }
 
             Expression::AddressOf { operand } => {
                 if let Expression::Identifier(name) = operand.as_ref() {
                     if let Some(&local_index) = self.local_vars.get(name) {
-                        self.output.push_str(&format!("    load_addr {}  ; &{}\n", local_index, name));
+                        self.output.push_str(&format!("    push {}  ; &{}\n", local_index, name));
+                        self.output.push_str("    syscall get_local_addr\n");
+                    } else if program.functions.iter().any(|f| &f.name == name) {
+                        self.output.push_str(&format!("    push fn_{}\n", name));
                     } else {
-                        self.output.push_str(&format!("    ; ERROR: Variable not found: {}\n", name));
+                        self.fail(format!("Variable not found: {}", name));
                         self.output.push_str("    push 0\n");
                     }
                 } else {
-                    self.output.push_str("    ; ERROR: AddressOf only supports identifiers\n");
+                    self.fail("AddressOf only supports identifiers on the NVM target".to_string());
                     self.output.push_str("    push 0\n");
                 }
             }
@@ -445,7 +779,36 @@ impl NVMAssemblyGenerator {
             Expression::Deref { operand } => {
                 self.output.push_str("    ; *ptr\n");
                 self.generate_expression(operand, program);
-                self.output.push_str("    load_ptr\n");
+                self.output.push_str("    load_abs\n");
+            }
+
+            Expression::IndirectCall { callee, args } => {
+                self.output.push_str("    ; indirect call\n");
+                self.generate_call_args(args, program);
+                self.generate_expression(callee, program);
+                self.output.push_str("    call_ind\n");
+            }
+
+            Expression::Eval { instruction } => {
+                self.output.push_str("    ; eval\n");
+                match NVMCodeGen::eval_push_operand(instruction) {
+                    Some(value) => self.generate_expression(&value, program),
+                    None => {
+                        if let Expression::String(instr_str) = instruction.as_ref() {
+                            self.output.push_str(&format!("    {}\n", instr_str.trim()));
+                        }
+                    }
+                }
+            }
+
+            Expression::Tuple(values) => {
+                for value in values {
+                    self.generate_expression(value, program);
+                }
+            }
+
+            Expression::Slice { .. } => {
+                self.fail("string slices are not supported on the NVM target".to_string());
             }
 
             _ => {
@@ -460,19 +823,272 @@ impl NVMAssemblyGenerator {
         format!("{}_{}_{}", prefix, self.current_function, self.label_counter)
     }
 
+    fn generate_call_args(&mut self, args: &[Expression], program: &Program) {
+        if !NVMCodeGen::needs_spilled_args(args) {
+            for arg in args.iter().rev() {
+                self.generate_expression(arg, program);
+            }
+            return;
+        }
+
+        let slots: Vec<u8> = args.iter().map(|arg| {
+            self.generate_expression(arg, program);
+            let slot = self.next_local;
+            self.next_local += 1;
+            self.output.push_str(&format!("    store {}\n", slot));
+            slot
+        }).collect();
+        for slot in slots.into_iter().rev() {
+            self.output.push_str(&format!("    load {}\n", slot));
+        }
+    }
+
+    fn generate_int_builtin(&mut self, function: &str, args: &[Expression], program: &Program) {
+        self.output.push_str(&format!("    ; {}\n", function));
+        let slots: Vec<u8> = args.iter().map(|arg| {
+            self.generate_expression(arg, program);
+            let slot = self.next_local;
+            self.next_local += 1;
+            self.output.push_str(&format!("    store {}\n", slot));
+            slot
+        }).collect();
+
+        let other_label = self.generate_label("select");
+        let end_label = self.generate_label("endselect");
+
+        self.output.push_str(&format!("    load {}\n", slots[0]));
+        if function == "abs" {
+            self.output.push_str("    push 0\n");
+        } else {
+            self.output.push_str(&format!("    load {}\n", slots[1]));
+        }
+        self.output.push_str(if function == "max" { "    gt\n" } else { "    lt\n" });
+        self.output.push_str(&format!("    jz {}\n", other_label));
+
+        if function == "abs" {
+            self.output.push_str("    push 0\n");
+            self.output.push_str(&format!("    load {}\n", slots[0]));
+            self.output.push_str("    sub\n");
+        } else {
+            self.output.push_str(&format!("    load {}\n", slots[0]));
+        }
+        self.output.push_str(&format!("    jmp {}\n", end_label));
+
+        self.output.push_str(&format!("{}:\n", other_label));
+        self.output.push_str(&format!("    load {}\n", slots.last().unwrap_or(&0)));
+        self.output.push_str(&format!("{}:\n", end_label));
+    }
+
+    fn generate_fatal_builtin(&mut self, function: &str, arg: &Expression, program: &Program) {
+        let Some(message) = NVMCodeGen::fatal_message(&self.compile_time_strings, function, arg, &self.source) else {
+            self.fail("panic needs a string literal message on the NVM target".to_string());
+            self.output.push_str("    push 0\n");
+            return;
+        };
+
+        let ok_label = self.generate_label("assert_ok");
+        if function == "assert" {
+            self.generate_expression(arg, program);
+            self.output.push_str(&format!("    jnz {}\n", ok_label));
+        }
+        self.emit_print_chars(&message);
+        self.output.push_str("    push 1\n");
+        self.output.push_str("    syscall exit\n");
+        self.output.push_str(&format!("{}:\n", ok_label));
+        self.output.push_str("    push 0\n");
+    }
+
+    fn generate_printf(&mut self, args: &[Expression], program: &Program) {
+        let pieces = match args.first() {
+            Some(Expression::String(format)) => NVMCodeGen::parse_printf_format(format),
+            _ => Err("non-literal format".to_string()),
+        };
+        let pieces = match pieces {
+            Ok(pieces) => pieces,
+            Err(message) => {
+                self.output.push_str(&format!("    ; unsupported Printf: {}\n", message));
+                return;
+            }
+        };
+
+        let mut values = args[1..].iter();
+        for piece in pieces {
+            match piece {
+                PrintfPiece::Text(text) => self.emit_print_chars(&text),
+                PrintfPiece::Int => match values.next() {
+                    Some(value) => {
+                        self.generate_expression(value, program);
+                        self.output.push_str("    call __print_int\n");
+                    }
+                    None => self.output.push_str("    ; unsupported Printf argument\n"),
+                },
+                PrintfPiece::Str => match values.next() {
+                    Some(Expression::String(s)) => self.emit_print_chars(s),
+                    _ => self.output.push_str("    ; unsupported Printf argument\n"),
+                },
+            }
+        }
+    }
+
+    /// Mirrors `NVMCodeGen::generate_print_ptr`.
+    fn generate_print_ptr(&mut self) {
+        self.output.push_str("    store 250\n");
+        self.emit_print_chars("0x00000000");
+        self.emit_lines(&[
+            "load 250", "push 268435456", "mod", "push 268435456", "add", "push 268435456", "mod", "store 251",
+            "load 250", "load 251", "sub", "push 268435456", "div", "push 16", "add", "push 16", "mod",
+        ]);
+        self.emit_hex_digit();
+        for shift in (0..7).rev() {
+            self.output.push_str("    load 251\n");
+            self.output.push_str(&format!("    push {}\n", 1 << (4 * shift)));
+            self.emit_lines(&["div", "push 16", "mod"]);
+            self.emit_hex_digit();
+        }
+    }
+
+    fn emit_hex_digit(&mut self) {
+        self.emit_lines(&[
+            "store 252", "load 252", "push 48", "add",
+            "load 252", "push 9", "gt", "push 39", "mul", "add", "syscall print",
+        ]);
+    }
+
+    fn emit_print_chars(&mut self, text: &str) {
+        for ch in text.bytes() {
+            self.output.push_str(&format!("    push {}\n", ch));
+            self.output.push_str("    syscall print\n");
+        }
+    }
+
+    fn emit_string_literals(&mut self) {
+        if self.string_literals.is_empty() {
+            return;
+        }
+        self.output.push_str("; String literals\n");
+        for (label, content) in std::mem::take(&mut self.string_literals) {
+            self.output.push_str(&format!("{}:\n", label));
+            self.output.push_str(&format!("    .string {:?}\n", content));
+        }
+    }
+
+    fn generate_vga_call(&mut self, function: &str, args: &[Expression], program: &Program) {
+        match (function, args) {
+            ("PutChar", [ch, attr]) => {
+                self.vga_reserve_cell();
+                self.output.push_str(&format!("    push 0x{:X}\n", self.vga_cursor));
+                self.generate_expression(attr, program);
+                self.output.push_str("    push 256\n");
+                self.output.push_str("    mul\n");
+                self.generate_expression(ch, program);
+                self.output.push_str("    add\n");
+                self.output.push_str("    store_abs\n");
+                self.vga_cursor += 2;
+            }
+            ("PutStr", [text]) => {
+                let text = match text {
+                    Expression::String(s) => Some(s.clone()),
+                    Expression::Identifier(name) => self.compile_time_strings.get(name).cloned(),
+                    _ => None,
+                };
+                match text {
+                    Some(text) => {
+                        for ch in text.bytes() {
+                            if ch == b'\n' {
+                                self.emit_vga_newline();
+                            } else {
+                                self.emit_vga_char(ch, VGA_DEFAULT_ATTR);
+                            }
+                        }
+                    }
+                    None => self.fail(format!(
+                        "vga.PutStr in '{}' needs a string known at compile time",
+                        self.current_function
+                    )),
+                }
+            }
+            ("Newline", []) => self.emit_vga_newline(),
+            _ => self.fail(format!("unknown vga function '{}' with {} argument(s)", function, args.len())),
+        }
+    }
+
+    fn vga_reserve_cell(&mut self) {
+        if self.vga_cursor >= VGA_END {
+            self.output.push_str("    call __vga_scroll\n");
+            self.vga_cursor = VGA_END - VGA_ROW_BYTES;
+        }
+    }
+
     fn emit_vga_char(&mut self, ch: u8, attr: u8) {
+        self.vga_reserve_cell();
         let val = ((attr as u32) << 8) | (ch as u32);
         self.output.push_str(&format!("    push 0x{:X}\n", self.vga_cursor));
         self.output.push_str(&format!("    push 0x{:X}\n", val));
         self.output.push_str("    store_abs\n");
         self.vga_cursor += 2;
     }
-    
+
     fn emit_vga_newline(&mut self) {
-        self.vga_cursor = ((self.vga_cursor - 0xB8000) / 160 + 1) * 160 + 0xB8000;
-        self.vga_cursor += 160;
-        if self.vga_cursor >= 0xB8F00 {
-            self.vga_cursor = 0xB8000 + (18 * 160);
+        let row = (self.vga_cursor - VGA_BASE) / VGA_ROW_BYTES;
+        self.vga_cursor = VGA_BASE + (row + 1) * VGA_ROW_BYTES;
+        self.vga_reserve_cell();
+    }
+
+    fn emit_lines(&mut self, lines: &[&str]) {
+        for line in lines {
+            if line.ends_with(':') {
+                self.output.push_str(line);
+            } else {
+                self.output.push_str("    ");
+                self.output.push_str(line);
+            }
+            self.output.push('\n');
         }
     }
+
+    fn generate_print_int_helper(&mut self) {
+        self.generate_helper("print signed integer", "__print_int", PRINT_INT_HELPER);
+    }
+
+    fn generate_print_bin_helper(&mut self) {
+        self.generate_helper("print integer in binary", "__print_bin", PRINT_BIN_HELPER);
+    }
+
+    fn generate_helper(&mut self, description: &str, name: &str, listing: &[&str]) {
+        self.output.push_str(&format!("; Helper: {}\n", description));
+        self.output.push_str(&format!("{}:\n", name));
+        let caller = std::mem::replace(&mut self.current_function, name.to_string());
+        self.emit_asm_lines(&listing.join("\n"));
+        self.current_function = caller;
+        self.output.push('\n');
+    }
+
+    fn generate_vga_scroll_helper(&mut self) {
+        let last_row = format!("push {}", VGA_END - VGA_ROW_BYTES - VGA_BASE);
+        let screen = format!("push {}", VGA_END - VGA_BASE);
+        let base = format!("push 0x{:X}", VGA_BASE);
+        let second_row = format!("push 0x{:X}", VGA_BASE + VGA_ROW_BYTES);
+        let blank = format!("push 0x{:X}", (VGA_DEFAULT_ATTR as u32) << 8 | b' ' as u32);
+
+        self.output.push_str("; Helper: scroll VGA text buffer up one line\n");
+        self.emit_lines(&[
+            "__vga_scroll:",
+            "push 0", "store 250",
+            "__vga_scroll_copy:",
+            "load 250", &last_row, "lt", "jz __vga_scroll_clear",
+            &base, "load 250", "add",
+            &second_row, "load 250", "add", "load_abs",
+            "store_abs",
+            "load 250", "push 2", "add", "store 250",
+            "jmp __vga_scroll_copy",
+            "__vga_scroll_clear:",
+            "load 250", &screen, "lt", "jz __vga_scroll_done",
+            &base, "load 250", "add", &blank, "store_abs",
+            "load 250", "push 2", "add", "store 250",
+            "jmp __vga_scroll_clear",
+            "__vga_scroll_done:",
+            "ret",
+        ]);
+        self.output.push('\n');
+    }
 }
@@ -1,60 +1,176 @@
 use crate::ast::*;
-use std::collections::HashMap;
-const PUSH32: u8 = 0x02;
-const POP: u8 = 0x04;
-const SWAP: u8 = 0x06;
-
-const ADD: u8 = 0x10;
-const SUB: u8 = 0x11;
-const MUL: u8 = 0x12;
-const DIV: u8 = 0x13;
-const MOD: u8 = 0x14;
-
-const EQ: u8 = 0x21;
-const NEQ: u8 = 0x22;
-const GT: u8 = 0x23;
-const LT: u8 = 0x24;
-
-const JMP32: u8 = 0x30;
-const JZ32: u8 = 0x31;
-const JNZ32: u8 = 0x32;
-const CALL32: u8 = 0x33;
-const RET: u8 = 0x34;
-
-const LOAD: u8 = 0x40;
-const STORE: u8 = 0x41;
-const LOAD_ABS: u8 = 0x44;
-const STORE_ABS: u8 = 0x45;
-
-const SYSCALL: u8 = 0x50;
-
-const SYSCALL_EXIT: u8 = 0x00;
-const SYSCALL_PRINT: u8 = 0x0F;
-const SYSCALL_EXEC: u8 = 0x01;
-const SYSCALL_OPEN: u8 = 0x02;
-const SYSCALL_READ: u8 = 0x03;
-const SYSCALL_WRITE: u8 = 0x04;
-const SYSCALL_CREATE: u8 = 0x05;
-const SYSCALL_DELETE: u8 = 0x06;
-const SYSCALL_CAP_CHECK: u8 = 0x07;
-const SYSCALL_CAP_SPAWN: u8 = 0x08;
-const SYSCALL_MSG_SEND: u8 = 0x0A;
-const SYSCALL_MSG_RECEIVE: u8 = 0x0B;
-const SYSCALL_PORT_IN_BYTE: u8 = 0x0C;
-const SYSCALL_PORT_OUT_BYTE: u8 = 0x0D;
-const SYSCALL_GET_LOCAL_ADDR: u8 = 0x0E;
+use crate::error::{CompileError, CompileWarning, ErrorKind};
+use std::collections::{HashMap, HashSet};
+pub(super) const PUSH32: u8 = 0x02;
+pub(super) const POP: u8 = 0x04;
+pub(super) const SWAP: u8 = 0x06;
+
+pub(super) const ADD: u8 = 0x10;
+pub(super) const SUB: u8 = 0x11;
+pub(super) const MUL: u8 = 0x12;
+pub(super) const DIV: u8 = 0x13;
+pub(super) const MOD: u8 = 0x14;
+
+pub(super) const EQ: u8 = 0x21;
+pub(super) const NEQ: u8 = 0x22;
+pub(super) const GT: u8 = 0x23;
+pub(super) const LT: u8 = 0x24;
+
+pub(super) const JMP32: u8 = 0x30;
+pub(super) const JZ32: u8 = 0x31;
+pub(super) const JNZ32: u8 = 0x32;
+pub(super) const CALL32: u8 = 0x33;
+pub(super) const RET: u8 = 0x34;
+pub(super) const CALL_IND: u8 = 0x35;
+
+pub(super) const LOAD: u8 = 0x40;
+pub(super) const STORE: u8 = 0x41;
+pub(super) const LOAD_ABS: u8 = 0x44;
+pub(super) const STORE_ABS: u8 = 0x45;
+
+pub(super) const SYSCALL: u8 = 0x50;
+
+pub(super) const SYSCALL_EXIT: u8 = 0x00;
+pub(super) const SYSCALL_PRINT: u8 = 0x0F;
+pub(super) const SYSCALL_EXEC: u8 = 0x01;
+pub(super) const SYSCALL_OPEN: u8 = 0x02;
+pub(super) const SYSCALL_READ: u8 = 0x03;
+pub(super) const SYSCALL_WRITE: u8 = 0x04;
+pub(super) const SYSCALL_CREATE: u8 = 0x05;
+pub(super) const SYSCALL_DELETE: u8 = 0x06;
+pub(super) const SYSCALL_CAP_CHECK: u8 = 0x07;
+pub(super) const SYSCALL_CAP_SPAWN: u8 = 0x08;
+pub(super) const SYSCALL_MSG_SEND: u8 = 0x0A;
+pub(super) const SYSCALL_MSG_RECEIVE: u8 = 0x0B;
+pub(super) const SYSCALL_PORT_IN_BYTE: u8 = 0x0C;
+pub(super) const SYSCALL_PORT_OUT_BYTE: u8 = 0x0D;
+pub(super) const SYSCALL_GET_LOCAL_ADDR: u8 = 0x0E;
+
+/// `novaria` functions that compile straight to a syscall: name, mnemonic, syscall number
+/// and the number of arguments the syscall pops.
+pub(super) const NOVARIA_SYSCALLS: &[(&str, &str, u8, usize)] = &[
+    ("Exit", "exit", SYSCALL_EXIT, 1),
+    ("Exec", "exec", SYSCALL_EXEC, 1),
+    ("FileRead", "read", SYSCALL_READ, 3),
+    ("FileWrite", "write", SYSCALL_WRITE, 3),
+    ("FileCreate", "create", SYSCALL_CREATE, 3),
+    ("FileDelete", "delete", SYSCALL_DELETE, 1),
+    ("CapCheck", "cap_check", SYSCALL_CAP_CHECK, 2),
+    ("CapSpawn", "cap_spawn", SYSCALL_CAP_SPAWN, 3),
+    ("MsgSend", "msg_send", SYSCALL_MSG_SEND, 3),
+    ("MsgReceive", "msg_receive", SYSCALL_MSG_RECEIVE, 2),
+    ("PortInByte", "inb", SYSCALL_PORT_IN_BYTE, 1),
+    ("PortOutByte", "outb", SYSCALL_PORT_OUT_BYTE, 2),
+];
+
+pub(super) const VGA_BASE: u32 = 0xB8000;
+pub(super) const VGA_END: u32 = 0xB8FA0;
+pub(super) const VGA_ROW_BYTES: u32 = 160;
+pub(super) const VGA_DEFAULT_ATTR: u8 = 0x07;
+
+/// Body of `__print_int` in the asm-block dialect. Both NVM back ends assemble this one
+/// listing, so `--nvm-code` and `--novaria` cannot drift apart. Slot 255 holds the return
+/// address, 250 the value and 251 the current power of ten.
+pub(super) const PRINT_INT_HELPER: &[&str] = &[
+    "store 255", "store 250",
+    "load 250", "push 0", "lt", "jz not_negative",
+    "push 45", "syscall print",
+    "jmp negated",
+    "not_negative:",
+    "load 250", "push 0", "swap", "sub", "store 250",
+    "negated:",
+    "push 1", "store 251",
+    "find_power:",
+    "load 250", "load 251", "div", "push -10", "gt", "jnz find_power_done",
+    "load 251", "push 10", "mul", "store 251",
+    "jmp find_power",
+    "find_power_done:",
+    "digit_loop:",
+    "load 251", "push 0", "gt", "jz done",
+    "push 48", "load 250", "load 251", "div", "sub", "syscall print",
+    "load 250", "load 251", "mod", "store 250",
+    "load 251", "push 10", "div", "store 251",
+    "jmp digit_loop",
+    "done:",
+    "load 255", "ret",
+];
+
+/// Body of `__print_bin`, called with the value, the minimum width and the pad character
+/// pushed in that order. Bit 31 is printed first, then the value with it cleared is walked
+/// from 2^30 down. Once a 1 has been printed the width is raised to 32 and the pad set to
+/// `'0'`, so every later bit prints. Slots 250-255 as for `__print_int`; 254 is the bit index.
+pub(super) const PRINT_BIN_HELPER: &[&str] = &[
+    "store 255", "store 253", "store 252", "store 250",
+    "load 250", "push 0", "lt", "jz high_zero",
+    "push 49", "syscall print",
+    "load 250", "push 2147483647", "add", "push 1", "add", "store 250",
+    "push 32", "store 252", "push 48", "store 253",
+    "jmp low_bits",
+    "high_zero:",
+    "load 252", "push 31", "gt", "jz low_bits",
+    "load 253", "syscall print",
+    "low_bits:",
+    "push 30", "store 254",
+    "push 1073741824", "store 251",
+    "bit_loop:",
+    "load 250", "load 251", "div", "jz bit_zero",
+    "push 49", "syscall print",
+    "push 32", "store 252", "push 48", "store 253",
+    "jmp next_bit",
+    "bit_zero:",
+    "load 254", "push 0", "eq", "jnz last_zero",
+    "load 254", "load 252", "lt", "jz next_bit",
+    "load 253", "syscall print",
+    "jmp next_bit",
+    "last_zero:",
+    "push 48", "syscall print",
+    "next_bit:",
+    "load 250", "load 251", "mod", "store 250",
+    "load 254", "push 0", "eq", "jnz done",
+    "load 251", "push 2", "div", "store 251",
+    "load 254", "push 1", "sub", "store 254",
+    "jmp bit_loop",
+    "done:",
+    "load 255", "ret",
+];
+
+const NVM_MAGIC: &[u8; 3] = b"NVM";
+pub const NVM_LATEST_VERSION: u8 = 1;
+
+const MAX_USER_LOCALS: usize = 250;
+const DEFAULT_INLINE_THRESHOLD: usize = 4;
+
+pub(crate) enum PrintfPiece {
+    Text(String),
+    Int,
+    Str,
+}
 
 pub struct NVMCodeGen {
     bytecode: Vec<u8>,
     labels: HashMap<String, u32>,
+    /// Function entry labels with the source name each belongs to, for `--emit-map`.
+    function_labels: Vec<(String, String)>,
     label_patches: Vec<(u32, String)>,
+    label_counter: u32,
     local_vars: HashMap<String, u8>,
     next_local: u8,
     loop_stack: Vec<(String, String)>,
     current_function: String,
+    current_module: Option<String>,
+    return_label: Option<String>,
     string_literals: Vec<(String, String)>,
     compile_time_strings: HashMap<String, String>,
+    string_vars: HashSet<String>,
+    source: SourceCursor,
     vga_cursor: u32,
+    inline_threshold: usize,
+    error: Option<CompileError>,
+    warnings: Vec<CompileWarning>,
+    version: u8,
+    max_locals: u16,
+    entry: String,
+    uses_print_bin: bool,
 }
 
 impl NVMCodeGen {
@@ -62,17 +178,238 @@ impl NVMCodeGen {
         Self {
             bytecode: Vec::new(),
             labels: HashMap::new(),
+            function_labels: Vec::new(),
             label_patches: Vec::new(),
+            label_counter: 0,
             local_vars: HashMap::new(),
             next_local: 0,
             loop_stack: Vec::new(),
             current_function: String::new(),
+            current_module: None,
+            return_label: None,
             string_literals: Vec::new(),
             compile_time_strings: HashMap::new(),
-            vga_cursor: 0xB8000 + (18 * 160),
+            string_vars: HashSet::new(),
+            source: SourceCursor::default(),
+            vga_cursor: VGA_BASE + (18 * VGA_ROW_BYTES),
+            inline_threshold: DEFAULT_INLINE_THRESHOLD,
+            error: None,
+            warnings: Vec::new(),
+            version: 0,
+            max_locals: 0,
+            entry: "main".to_string(),
+            uses_print_bin: false,
+        }
+    }
+
+    pub fn with_version(version: u8) -> Self {
+        Self {
+            version,
+            ..Self::new()
+        }
+    }
+
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    fn alloc_local(&mut self) -> u8 {
+        if self.next_local as usize >= MAX_USER_LOCALS {
+            self.fail(format!(
+                "function '{}' needs more than {} local slots (slots {}-255 are reserved)",
+                self.current_function, MAX_USER_LOCALS, MAX_USER_LOCALS
+            ));
+            return 0;
         }
+
+        let slot = self.next_local;
+        self.next_local += 1;
+        self.max_locals = self.max_locals.max(self.next_local as u16);
+        slot
+    }
+
+    /// Start offset of every generated function, in bytecode order.
+    pub fn function_map(&self) -> Vec<(u32, String)> {
+        let mut map: Vec<(u32, String)> = self.function_labels.iter()
+            .filter_map(|(label, name)| self.labels.get(label).map(|&offset| (offset, name.clone())))
+            .collect();
+        map.sort();
+        map
+    }
+
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
+    }
+
+    pub fn set_inline_threshold(&mut self, threshold: usize) {
+        self.inline_threshold = threshold;
+    }
+
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
+    fn count_statements(stmts: &[Statement]) -> usize {
+        stmts.iter().map(|stmt| match stmt {
+            Statement::If { then_body, else_body, .. } => {
+                1 + Self::count_statements(then_body)
+                    + else_body.as_ref().map(|b| Self::count_statements(b)).unwrap_or(0)
+            }
+            Statement::For { init, post, body, .. } => {
+                1 + usize::from(init.is_some()) + usize::from(post.is_some()) + Self::count_statements(body)
+            }
+            Statement::Block(body) => Self::count_statements(body),
+            _ => 1,
+        }).sum()
+    }
+
+    fn count_var_decls(stmts: &[Statement]) -> usize {
+        stmts.iter().map(|stmt| match stmt {
+            Statement::VarDecl { .. } => 1,
+            Statement::MultiVarDecl { names, .. } => names.len(),
+            Statement::If { then_body, else_body, .. } => {
+                Self::count_var_decls(then_body)
+                    + else_body.as_ref().map(|b| Self::count_var_decls(b)).unwrap_or(0)
+            }
+            Statement::For { init, body, .. } => {
+                init.as_deref().map_or(0, |init| Self::count_var_decls(std::slice::from_ref(init)))
+                    + Self::count_var_decls(body)
+            }
+            Statement::Block(body) => Self::count_var_decls(body),
+            _ => 0,
+        }).sum()
+    }
+
+    fn is_leaf_statement(stmt: &Statement) -> bool {
+        match stmt {
+            Statement::VarDecl { value, .. } => value.as_ref().map(Self::is_leaf_expression).unwrap_or(true),
+            Statement::Assignment { value, .. } => Self::is_leaf_expression(value),
+            Statement::If { condition, then_body, else_body } => {
+                Self::is_leaf_expression(condition)
+                    && then_body.iter().all(Self::is_leaf_statement)
+                    && else_body.as_ref().map(|b| b.iter().all(Self::is_leaf_statement)).unwrap_or(true)
+            }
+            Statement::For { init, condition, post, body } => {
+                init.as_ref().map(|s| Self::is_leaf_statement(s)).unwrap_or(true)
+                    && condition.as_ref().map(Self::is_leaf_expression).unwrap_or(true)
+                    && post.as_ref().map(|s| Self::is_leaf_statement(s)).unwrap_or(true)
+                    && body.iter().all(Self::is_leaf_statement)
+            }
+            Statement::Return(value) => value.as_ref().map(Self::is_leaf_expression).unwrap_or(true),
+            Statement::Expression(expr) => Self::is_leaf_expression(expr),
+            Statement::PointerAssignment { target, value } => {
+                Self::is_leaf_expression(target) && Self::is_leaf_expression(value)
+            }
+            Statement::ArrayDecl { .. } | Statement::ArrayAssignment { .. } | Statement::InlineAsm { .. }
+            | Statement::MultiVarDecl { .. } | Statement::Block(_) => false,
+        }
+    }
+
+    fn is_leaf_expression(expr: &Expression) -> bool {
+        match expr {
+            Expression::Number(_) | Expression::Identifier(_) => true,
+            Expression::Binary { left, right, .. } => {
+                Self::is_leaf_expression(left) && Self::is_leaf_expression(right)
+            }
+            Expression::Unary { operand, .. } | Expression::Deref { operand } => Self::is_leaf_expression(operand),
+            _ => false,
+        }
+    }
+
+    /// Whether a call to `func` with `arg_count` arguments can be expanded in place. A call
+    /// whose argument count does not match the parameters is left to the ordinary call path,
+    /// since the inliner binds each parameter to the argument at the same position.
+    fn is_inlinable(&self, func: &Function, arg_count: usize) -> bool {
+        if self.inline_threshold == 0 || func.name == self.entry || arg_count != func.params.len() {
+            return false;
+        }
+
+        let (last, rest) = match func.body.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        if !matches!(last, Statement::Return(Some(_))) || self.has_return_or_exit(rest) {
+            return false;
+        }
+
+        let locals_needed = self.next_local as usize + func.params.len() + Self::count_var_decls(&func.body);
+
+        Self::count_statements(&func.body) <= self.inline_threshold
+            && locals_needed <= MAX_USER_LOCALS
+            && func.body.iter().all(Self::is_leaf_statement)
+    }
+
+    fn generate_inline_call(&mut self, func: &Function, args: &[Expression], program: &Program) {
+        let mut slots = Vec::new();
+        for arg in args {
+            self.generate_expression(arg, program);
+            let slot = self.alloc_local();
+            self.emit_byte(STORE);
+            self.emit_byte(slot);
+            slots.push(slot);
+        }
+
+        let saved_vars = std::mem::take(&mut self.local_vars);
+        let saved_strings = std::mem::take(&mut self.compile_time_strings);
+        let saved_string_vars = std::mem::replace(&mut self.string_vars, Self::string_params(func));
+        let saved_source = std::mem::replace(&mut self.source, SourceCursor::new(func));
+
+        for (param, slot) in func.params.iter().zip(slots) {
+            self.local_vars.insert(param.name.clone(), slot);
+        }
+
+        if let Some((Statement::Return(Some(value)), rest)) = func.body.split_last() {
+            for stmt in rest {
+                self.generate_statement(stmt, program);
+            }
+            self.generate_expression(value, program);
+        }
+
+        self.local_vars = saved_vars;
+        self.compile_time_strings = saved_strings;
+        self.string_vars = saved_string_vars;
+        self.source = saved_source;
     }
     
+    /// Pushes call arguments so the first one ends up on top of the stack. Arguments
+    /// are evaluated left to right: when that differs observably from pushing them in
+    /// reverse, each is spilled to a scratch local and reloaded in reverse.
+    fn generate_call_args(&mut self, args: &[Expression], program: &Program) {
+        if !Self::needs_spilled_args(args) {
+            for arg in args.iter().rev() {
+                self.generate_expression(arg, program);
+            }
+            return;
+        }
+
+        let mut slots = Vec::new();
+        for arg in args {
+            self.generate_expression(arg, program);
+            let slot = self.alloc_local();
+            self.emit_byte(STORE);
+            self.emit_byte(slot);
+            slots.push(slot);
+        }
+        for slot in slots.into_iter().rev() {
+            self.emit_byte(LOAD);
+            self.emit_byte(slot);
+        }
+    }
+
+    pub(super) fn needs_spilled_args(args: &[Expression]) -> bool {
+        args.len() > 1 && args.iter().any(Expression::has_side_effects)
+    }
+
     fn has_return_or_exit(&self, stmts: &[Statement]) -> bool {
         for stmt in stmts {
             match stmt {
@@ -96,7 +433,7 @@ impl NVMCodeGen {
                         }
                     }
                 }
-                Statement::For { body, .. } => {
+                Statement::For { body, .. } | Statement::Block(body) => {
                     if self.has_return_or_exit(body) {
                         return true;
                     }
@@ -107,91 +444,573 @@ impl NVMCodeGen {
         false
     }
 
-    pub fn generate(&mut self, program: &Program) -> Vec<u8> {
-        self.bytecode.extend_from_slice(&[b'N', b'V', b'M', b'0']);
+    pub fn generate(&mut self, program: &Program) -> crate::error::Result<Vec<u8>> {
+        self.bytecode.extend_from_slice(NVM_MAGIC);
+        self.bytecode.push(b'0' + self.version);
+        let locals_pos = self.bytecode.len() + 4;
+        if self.version >= 1 {
+            let entry_label = format!("func_{}", self.entry);
+            self.emit_label_ref(&entry_label);
+            self.bytecode.extend_from_slice(&[0, 0, 0, 0]);
+        }
 
-        if let Some(main_func) = program.functions.iter().find(|f| f.name == "main") {
+        if let Some(main_func) = program.functions.iter().find(|f| f.name == self.entry) {
             self.generate_function(main_func, program);
         }
 
         for func in &program.functions {
-            if func.name != "main" {
+            if func.name != self.entry {
                 self.generate_function(func, program);
             }
         }
 
         for (module_name, module) in &program.modules {
-            if module_name == "stdio" {
+            if module_name == "stdio" || module_name == "vga" || module_name == "sys" || module_name == "time" {
                 continue;
             }
+            self.current_module = Some(module_name.clone());
             for func in &module.functions {
-                if func.is_exported {
-                    let full_name = format!("{}_{}", module.name, func.name);
-                    self.generate_module_function(func, &full_name, program);
-                }
+                let full_name = format!("{}_{}", module.name, func.name);
+                self.generate_module_function(func, &full_name, program);
             }
+            self.current_module = None;
         }
 
         if program.modules.contains_key("stdio") {
             self.generate_print_int_vga_helper();
+            self.max_locals = 256;
+        }
+
+        if self.uses_print_bin {
+            self.generate_print_bin_helper();
+        }
+
+        if program.modules.contains_key("vga") {
+            self.generate_vga_scroll_helper();
+            self.max_locals = self.max_locals.max(MAX_USER_LOCALS as u16 + 1);
         }
 
         self.emit_string_literals();
         self.patch_labels();
 
-        self.bytecode.clone()
+        if self.version >= 1 {
+            self.bytecode[locals_pos..locals_pos + 2].copy_from_slice(&self.max_locals.to_be_bytes());
+        }
+
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(self.bytecode.clone()),
+        }
     }
 
     fn generate_function(&mut self, func: &Function, program: &Program) {
         self.current_function = func.name.clone();
         self.local_vars.clear();
         self.compile_time_strings.clear();
+        self.string_vars = Self::string_params(func);
+        self.source = SourceCursor::new(func);
         self.next_local = 0;
 
         let func_label = format!("func_{}", func.name);
         self.add_label(&func_label);
+        self.function_labels.push((func_label, func.name.clone()));
 
         for param in &func.params {
-            self.local_vars.insert(param.name.clone(), self.next_local);
-            self.next_local += 1;
+            let slot = self.alloc_local();
+            self.local_vars.insert(param.name.clone(), slot);
         }
 
         for stmt in &func.body {
             self.generate_statement(stmt, program);
         }
 
-        if func.name == "main" && !self.has_return_or_exit(&func.body) {
+        if func.name == self.entry && !self.has_return_or_exit(&func.body) {
             self.emit_push32(0);
             self.emit_byte(SYSCALL);
             self.emit_byte(SYSCALL_EXIT);
+            self.emit_byte(RET);
+        } else {
+            self.emit_fallthrough_return(func);
         }
-        
-        self.emit_byte(RET);
     }
 
     fn generate_module_function(&mut self, func: &Function, full_name: &str, program: &Program) {
         self.current_function = full_name.to_string();
         self.local_vars.clear();
+        self.string_vars = Self::string_params(func);
+        self.source = SourceCursor::new(func);
         self.next_local = 0;
 
         let func_label = format!("func_{}", full_name);
         self.add_label(&func_label);
+        let module = self.current_module.clone().unwrap_or_default();
+        self.function_labels.push((func_label.clone(), format!("{}.{}", module, func.name)));
 
         for param in &func.params {
-            self.local_vars.insert(param.name.clone(), self.next_local);
-            self.next_local += 1;
+            let slot = self.alloc_local();
+            self.local_vars.insert(param.name.clone(), slot);
         }
 
-        for stmt in &func.body {
-            self.generate_statement(stmt, program);
+        let return_label = format!("{}_return", func_label);
+        self.return_label = Some(return_label.clone());
+
+        match func.body.split_last() {
+            Some((Statement::Return(value), rest)) => {
+                for stmt in rest {
+                    self.generate_statement(stmt, program);
+                }
+                self.generate_return_value(value.as_ref(), program);
+            }
+            _ => {
+                for stmt in &func.body {
+                    self.generate_statement(stmt, program);
+                }
+                self.emit_fallthrough_value(func);
+            }
         }
 
+        self.return_label = None;
+        self.add_label(&return_label);
         self.emit_byte(RET);
     }
 
+    /// Splits a `stdio.Printf` format into literal text and argument slots.
+    /// Only `%d`, `%ld`, `%s` and `%%` are understood.
+    pub(crate) fn parse_printf_format(format: &str) -> std::result::Result<Vec<PrintfPiece>, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                text.push(ch);
+                continue;
+            }
+            let spec = match chars.next() {
+                Some('%') => {
+                    text.push('%');
+                    continue;
+                }
+                Some('l') if chars.clone().next() == Some('d') => {
+                    chars.next();
+                    PrintfPiece::Int
+                }
+                Some('d') => PrintfPiece::Int,
+                Some('s') => PrintfPiece::Str,
+                Some(other) => return Err(format!("unsupported Printf conversion '%{}'", other)),
+                None => return Err("Printf format ends with a lone '%'".to_string()),
+            };
+            if !text.is_empty() {
+                pieces.push(PrintfPiece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(spec);
+        }
+        if !text.is_empty() {
+            pieces.push(PrintfPiece::Text(text));
+        }
+        Ok(pieces)
+    }
+
+    fn generate_printf(&mut self, args: &[Expression], program: &Program) {
+        let pieces = match args.first() {
+            Some(Expression::String(format)) => Self::parse_printf_format(format),
+            _ => Err("stdio.Printf needs a string literal format on the NVM target".to_string()),
+        };
+        let pieces = match pieces {
+            Ok(pieces) => pieces,
+            Err(message) => {
+                self.fail(message);
+                return;
+            }
+        };
+
+        let mut values = args[1..].iter();
+        for piece in pieces {
+            match piece {
+                PrintfPiece::Text(text) => {
+                    for ch in text.as_bytes() {
+                        self.emit_push32(*ch as i32);
+                        self.emit_byte(SYSCALL);
+                        self.emit_byte(SYSCALL_PRINT);
+                    }
+                }
+                PrintfPiece::Int => match values.next() {
+                    Some(value) => {
+                        self.generate_expression(value, program);
+                        self.emit_byte(CALL32);
+                        self.emit_label_ref("__print_int");
+                    }
+                    None => self.fail("stdio.Printf has more conversions than arguments".to_string()),
+                },
+                PrintfPiece::Str => match values.next() {
+                    Some(Expression::String(s)) => {
+                        for ch in s.as_bytes() {
+                            self.emit_push32(*ch as i32);
+                            self.emit_byte(SYSCALL);
+                            self.emit_byte(SYSCALL_PRINT);
+                        }
+                    }
+                    Some(_) => self.fail("stdio.Printf '%s' needs a string literal on the NVM target".to_string()),
+                    None => self.fail("stdio.Printf has more conversions than arguments".to_string()),
+                },
+            }
+        }
+    }
+
+    /// Prints the word on the stack as `0x` and 16 hex digits. Words are 32 bits, so the first
+    /// eight digits are always zero. Uses the reserved slots 250-252 like `__print_int`.
+    fn generate_print_ptr(&mut self) {
+        const NIBBLES_7: i32 = 1 << 28;
+        self.emit_byte(STORE);
+        self.emit_byte(250);
+        for ch in b"0x00000000" {
+            self.emit_push32(*ch as i32);
+            self.emit_byte(SYSCALL);
+            self.emit_byte(SYSCALL_PRINT);
+        }
+
+        // The low seven digits, made non-negative so signed DIV/MOD extract them.
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        for op in [MOD, ADD, MOD] {
+            self.emit_push32(NIBBLES_7);
+            self.emit_byte(op);
+        }
+        self.emit_byte(STORE);
+        self.emit_byte(251);
+
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_byte(LOAD);
+        self.emit_byte(251);
+        self.emit_byte(SUB);
+        self.emit_push32(NIBBLES_7);
+        self.emit_byte(DIV);
+        self.emit_push32(16);
+        self.emit_byte(ADD);
+        self.emit_push32(16);
+        self.emit_byte(MOD);
+        self.emit_hex_digit();
+
+        for shift in (0..7).rev() {
+            self.emit_byte(LOAD);
+            self.emit_byte(251);
+            self.emit_push32(1 << (4 * shift));
+            self.emit_byte(DIV);
+            self.emit_push32(16);
+            self.emit_byte(MOD);
+            self.emit_hex_digit();
+        }
+    }
+
+    /// Prints the value 0-15 on the stack as a lowercase hex digit.
+    fn emit_hex_digit(&mut self) {
+        self.emit_byte(STORE);
+        self.emit_byte(252);
+        self.emit_byte(LOAD);
+        self.emit_byte(252);
+        self.emit_push32('0' as i32);
+        self.emit_byte(ADD);
+        self.emit_byte(LOAD);
+        self.emit_byte(252);
+        self.emit_push32(9);
+        self.emit_byte(GT);
+        self.emit_push32(('a' as i32) - ('9' as i32) - 1);
+        self.emit_byte(MUL);
+        self.emit_byte(ADD);
+        self.emit_byte(SYSCALL);
+        self.emit_byte(SYSCALL_PRINT);
+    }
+
+    pub(crate) fn is_int_builtin(function: &str, arg_count: usize) -> bool {
+        matches!((function, arg_count), ("min", 2) | ("max", 2) | ("abs", 1))
+    }
+
+    pub(crate) fn is_fatal_builtin(function: &str, arg_count: usize) -> bool {
+        matches!((function, arg_count), ("assert", 1) | ("panic", 1))
+    }
+
+    /// The line `assert`/`panic` prints before exiting, or `None` when a `panic` message is not
+    /// known at compile time.
+    pub(crate) fn fatal_message(
+        strings: &HashMap<String, String>,
+        function: &str,
+        arg: &Expression,
+        source: &SourceCursor,
+    ) -> Option<String> {
+        if function == "assert" {
+            return Some(format!("{}: assertion failed: {}\n", source.location(), arg));
+        }
+        Self::template_text(strings, arg, None).map(|text| format!("panic: {}\n", text))
+    }
+
+    /// How many values `expr` leaves on the stack once generated. Every lowering leaves one
+    /// (calls their result, prints and `vga` calls a 0), except:
+    /// - `novaria.Exit` and `novaria.PortOutByte`, whose syscalls push nothing;
+    /// - a raw `eval` instruction other than `push`, `load` or `dup`, which runs for its effect;
+    /// - a tuple, which leaves one value per element.
+    pub(crate) fn values_left(expr: &Expression) -> usize {
+        match expr {
+            Expression::ModuleCall { module, function, .. } if module == "novaria" => {
+                usize::from(!matches!(function.as_str(), "Exit" | "PortOutByte"))
+            }
+            Expression::Eval { instruction } if Self::eval_push_operand(instruction).is_none() => {
+                match instruction.as_ref() {
+                    Expression::String(text) => {
+                        let mnemonic = text.split_whitespace().next().unwrap_or("").to_lowercase();
+                        usize::from(matches!(mnemonic.as_str(), "push" | "push32" | "load" | "dup"))
+                    }
+                    _ => 1,
+                }
+            }
+            Expression::Tuple(values) => values.len(),
+            _ => 1,
+        }
+    }
+
+    /// The operand of an `eval` that pushes a runtime value: `eval("push x")` reads the local `x`
+    /// and `eval("push $(expr)")` pushes `expr`.
+    pub(super) fn eval_push_operand(instruction: &Expression) -> Option<Expression> {
+        match instruction {
+            Expression::String(text) => {
+                let words: Vec<&str> = text.split_whitespace().collect();
+                match words.as_slice() {
+                    ["push" | "push32", name]
+                        if name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                            && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                    {
+                        Some(Expression::Identifier(name.to_string()))
+                    }
+                    _ => None,
+                }
+            }
+            Expression::TemplateString { parts } => match parts.as_slice() {
+                [TemplateStringPart::Literal(op), TemplateStringPart::Expression { expr, .. }]
+                    if matches!(op.trim(), "push" | "push32") => Some(expr.as_ref().clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The padded text of a template interpolation whose string value is known at compile time.
+    pub(crate) fn template_text(
+        strings: &HashMap<String, String>,
+        expr: &Expression,
+        format: Option<&FormatSpec>,
+    ) -> Option<String> {
+        let text = match expr {
+            Expression::String(s) => s.clone(),
+            Expression::Identifier(name) => strings.get(name)?.clone(),
+            _ => return None,
+        };
+        Some(format.map(|spec| spec.pad(&text)).unwrap_or(text))
+    }
+
+    /// Minimum width and pad character of a `b` spec, which NVM prints at run time through
+    /// `__print_bin`; `None` for other formats.
+    pub(crate) fn binary_spec(format: Option<&FormatSpec>) -> Option<std::result::Result<(usize, char), String>> {
+        let spec = format.filter(|spec| matches!(spec.format_type, FormatType::Binary))?;
+        if spec.left_align || spec.precision.is_some() {
+            return Some(Err("left alignment and precision are not supported for the `b` format on the NVM target".to_string()));
+        }
+        Some(Ok((spec.width.unwrap_or(0).min(32), spec.padding)))
+    }
+
+    /// Why a template interpolation printed at run time cannot honour `format`: the print
+    /// helper only writes plain decimal.
+    pub(crate) fn runtime_format_error(format: Option<&FormatSpec>, target: &str) -> Option<String> {
+        let spec = format?;
+        let letter = match spec.format_type {
+            FormatType::Hex => Some('x'),
+            FormatType::HexUpper => Some('X'),
+            FormatType::Binary => Some('b'),
+            FormatType::String => Some('s'),
+            FormatType::Decimal | FormatType::Auto => None,
+        };
+        match letter {
+            Some(letter) => Some(format!("the `{}` template format is not supported on the {} target", letter, target)),
+            None if spec.width.is_some() || spec.precision.is_some() => Some(format!(
+                "template width and precision are only supported for compile-time strings on the {} target",
+                target
+            )),
+            None => None,
+        }
+    }
+
+    /// `PrintStr`/`PrintlnStr` of a literal or template string print exactly like `Print`/`Println`.
+    pub(crate) fn stdio_print_name<'f>(function: &'f str, args: &[Expression]) -> &'f str {
+        let is_text = matches!(args.first(), Some(Expression::String(_) | Expression::TemplateString { .. }));
+        match function {
+            "PrintStr" if is_text => "Print",
+            "PrintlnStr" if is_text => "Println",
+            _ => function,
+        }
+    }
+
+    fn generate_int_builtin(&mut self, function: &str, args: &[Expression], program: &Program) {
+        let slots: Vec<u8> = args.iter().map(|arg| {
+            self.generate_expression(arg, program);
+            let slot = self.alloc_local();
+            self.emit_byte(STORE);
+            self.emit_byte(slot);
+            slot
+        }).collect();
+
+        let other_label = self.generate_label("select");
+        let end_label = self.generate_label("endselect");
+
+        self.emit_byte(LOAD);
+        self.emit_byte(slots[0]);
+        match function {
+            "abs" => self.emit_push32(0),
+            _ => {
+                self.emit_byte(LOAD);
+                self.emit_byte(slots[1]);
+            }
+        }
+        self.emit_byte(if function == "max" { GT } else { LT });
+        self.emit_byte(JZ32);
+        self.emit_label_ref(&other_label);
+
+        if function == "abs" {
+            self.emit_push32(0);
+            self.emit_byte(LOAD);
+            self.emit_byte(slots[0]);
+            self.emit_byte(SUB);
+        } else {
+            self.emit_byte(LOAD);
+            self.emit_byte(slots[0]);
+        }
+        self.emit_byte(JMP32);
+        self.emit_label_ref(&end_label);
+
+        self.add_label(&other_label);
+        self.emit_byte(LOAD);
+        self.emit_byte(*slots.last().unwrap_or(&0));
+        self.add_label(&end_label);
+    }
+
+    fn generate_fatal_builtin(&mut self, function: &str, arg: &Expression, program: &Program) {
+        let Some(message) = Self::fatal_message(&self.compile_time_strings, function, arg, &self.source) else {
+            self.fail("panic needs a string literal message on the NVM target".to_string());
+            return;
+        };
+
+        let ok_label = self.generate_label("assert_ok");
+        if function == "assert" {
+            self.generate_expression(arg, program);
+            self.emit_byte(JNZ32);
+            self.emit_label_ref(&ok_label);
+        }
+        for ch in message.bytes() {
+            self.emit_push32(ch as i32);
+            self.emit_byte(SYSCALL);
+            self.emit_byte(SYSCALL_PRINT);
+        }
+        self.emit_push32(1);
+        self.emit_byte(SYSCALL);
+        self.emit_byte(SYSCALL_EXIT);
+        self.add_label(&ok_label);
+        self.emit_push32(0);
+    }
+
+    fn emit_fallthrough_return(&mut self, func: &Function) {
+        if !always_returns(&func.body) {
+            self.emit_fallthrough_value(func);
+            self.emit_byte(RET);
+        }
+    }
+
+    fn emit_fallthrough_value(&mut self, func: &Function) {
+        if always_returns(&func.body) {
+            return;
+        }
+
+        self.warnings.extend(Self::missing_return_warning(func, &self.current_function));
+
+        for _ in 0..Self::return_arity(func) {
+            self.emit_push32(0);
+        }
+    }
+
+    fn generate_return_value(&mut self, value: Option<&Expression>, program: &Program) {
+        match value {
+            Some(expr) => self.generate_expression(expr, program),
+            None => self.emit_push32(0),
+        }
+    }
+
+    /// The warning for a function with a return type whose end is reachable, where 0 is returned.
+    pub(crate) fn missing_return_warning(func: &Function, name: &str) -> Option<CompileWarning> {
+        func.return_type.as_ref()?;
+        Some(CompileWarning {
+            message: format!("function '{}' can reach its end without returning a value, returning 0", name),
+            file: func.file.clone(),
+            line: func.line,
+            column: 0,
+        })
+    }
+
+    pub(crate) fn string_params(func: &Function) -> HashSet<String> {
+        func.params.iter().filter(|p| p.param_type == "string").map(|p| p.name.clone()).collect()
+    }
+
+    /// Whether `expr` has type `string`, judged from literals and from the declared types
+    /// of variables, parameters and called functions.
+    pub(crate) fn string_typed(
+        string_vars: &HashSet<String>,
+        expr: &Expression,
+        program: &Program,
+        module: Option<&str>,
+    ) -> bool {
+        let returns_string = |func: Option<&Function>| func.is_some_and(|f| f.return_type.as_deref() == Some("string"));
+        match expr {
+            Expression::String(_) | Expression::TemplateString { .. } | Expression::Slice { .. } => true,
+            Expression::Identifier(name) => string_vars.contains(name),
+            Expression::Call { function, .. } => returns_string(
+                module.and_then(|m| program.module_function(m, function))
+                    .or_else(|| program.functions.iter().find(|f| &f.name == function)),
+            ),
+            Expression::ModuleCall { module, function, .. } => returns_string(program.module_function(module, function)),
+            Expression::Binary { op: BinaryOp::Mul, left, .. } => Self::string_typed(string_vars, left, program, module),
+            _ => false,
+        }
+    }
+
+    fn is_string_expression(&self, expr: &Expression, program: &Program) -> bool {
+        Self::string_typed(&self.string_vars, expr, program, self.current_module.as_deref())
+    }
+
+    pub(crate) fn return_arity(func: &Function) -> usize {
+        match func.return_type.as_deref() {
+            Some(ty) if ty.starts_with('(') => ty.split(',').count(),
+            _ => 1,
+        }
+    }
+
+    fn generate_block(&mut self, body: &[Statement], program: &Program) {
+        let saved_vars = self.local_vars.clone();
+        let saved_strings = self.compile_time_strings.clone();
+        let saved_string_vars = self.string_vars.clone();
+        for stmt in body {
+            self.generate_statement(stmt, program);
+        }
+        self.local_vars = saved_vars;
+        self.compile_time_strings = saved_strings;
+        self.string_vars = saved_string_vars;
+    }
+
     fn generate_statement(&mut self, stmt: &Statement, program: &Program) {
+        self.source.advance();
         match stmt {
-            Statement::VarDecl { name, var_type: _, value } => {
+            Statement::VarDecl { name, var_type, value } => {
+                let is_string = var_type.as_deref() == Some("string")
+                    || value.as_ref().is_some_and(|v| self.is_string_expression(v, program));
+                if is_string {
+                    self.string_vars.insert(name.clone());
+                } else {
+                    self.string_vars.remove(name);
+                }
                 if let Some(init_expr) = value {
                     if let Expression::String(s) = init_expr {
                         self.compile_time_strings.insert(name.clone(), s.clone());
@@ -201,14 +1020,28 @@ impl NVMCodeGen {
                     self.emit_push32(0);
                 }
                 
-                let local_index = self.next_local;
+                let local_index = self.alloc_local();
                 self.local_vars.insert(name.clone(), local_index);
-                self.next_local += 1;
                 
                 self.emit_byte(STORE);
                 self.emit_byte(local_index);
             }
 
+            Statement::MultiVarDecl { names, value } => {
+                self.generate_expression(value, program);
+
+                let slots: Vec<u8> = names.iter().map(|name| {
+                    let local_index = self.alloc_local();
+                    self.local_vars.insert(name.clone(), local_index);
+                    local_index
+                }).collect();
+
+                for local_index in slots.into_iter().rev() {
+                    self.emit_byte(STORE);
+                    self.emit_byte(local_index);
+                }
+            }
+
             Statement::Assignment { name, value } => {
                 self.generate_expression(value, program);
                 
@@ -216,8 +1049,8 @@ impl NVMCodeGen {
                     self.emit_byte(STORE);
                     self.emit_byte(local_index);
                 } else {
-                    
-                    panic!("Variable not found: {}", name);
+                    self.fail(format!("assignment to undeclared variable '{}'; declare it with `var {} = ...` first", name, name));
+                    self.emit_byte(POP);
                 }
             }
 
@@ -230,9 +1063,7 @@ impl NVMCodeGen {
                 self.emit_byte(JZ32);
                 self.emit_label_ref(&else_label);
                 
-                for stmt in then_body {
-                    self.generate_statement(stmt, program);
-                }
+                self.generate_block(then_body, program);
                 
                 self.emit_byte(JMP32);
                 self.emit_label_ref(&end_label);
@@ -240,16 +1071,18 @@ impl NVMCodeGen {
                 self.add_label(&else_label);
                 
                 if let Some(else_stmts) = else_body {
-                    for stmt in else_stmts {
-                        self.generate_statement(stmt, program);
-                    }
+                    self.generate_block(else_stmts, program);
                 }
                 
                 self.add_label(&end_label);
             }
 
+            Statement::Block(body) => self.generate_block(body, program),
+
             Statement::For { init, condition, post, body } => {
-                
+                let saved_vars = self.local_vars.clone();
+                let saved_strings = self.compile_time_strings.clone();
+                let saved_string_vars = self.string_vars.clone();
                 if let Some(init_stmt) = init {
                     self.generate_statement(init_stmt, program);
                 }
@@ -270,9 +1103,7 @@ impl NVMCodeGen {
                 }
                 
                 
-                for stmt in body {
-                    self.generate_statement(stmt, program);
-                }
+                self.generate_block(body, program);
                 
                 self.add_label(&loop_continue);
                 
@@ -286,18 +1117,29 @@ impl NVMCodeGen {
                 
                 self.add_label(&loop_end);
                 self.loop_stack.pop();
+                self.local_vars = saved_vars;
+                self.compile_time_strings = saved_strings;
+                self.string_vars = saved_string_vars;
             }
 
             Statement::Return(value) => {
-                if let Some(expr) = value {
-                    self.generate_expression(expr, program);
+                self.generate_return_value(value.as_ref(), program);
+                if self.current_function == self.entry {
+                    self.emit_byte(SYSCALL);
+                    self.emit_byte(SYSCALL_EXIT);
+                } else if let Some(label) = self.return_label.clone() {
+                    self.emit_byte(JMP32);
+                    self.emit_label_ref(&label);
+                } else {
+                    self.emit_byte(RET);
                 }
-                self.emit_byte(RET);
             }
 
             Statement::Expression(expr) => {
                 self.generate_expression(expr, program);
-                self.emit_byte(POP);
+                for _ in 0..Self::values_left(expr) {
+                    self.emit_byte(POP);
+                }
             }
 
             Statement::InlineAsm { parts } => {
@@ -351,7 +1193,7 @@ impl NVMCodeGen {
     fn generate_expression(&mut self, expr: &Expression, program: &Program) {
         match expr {
             Expression::Number(n) => {
-                self.emit_push32(*n as i32);
+                self.emit_push_literal(*n);
             }
 
             Expression::String(s) => {
@@ -374,10 +1216,35 @@ impl NVMCodeGen {
                                 self.emit_byte(SYSCALL_PRINT);
                             }
                         }
-                        TemplateStringPart::Expression { expr, format: _ } => {
-                            self.generate_expression(expr, program);
-                            self.emit_byte(CALL32);
-                            self.emit_label_ref("__print_int");
+                        TemplateStringPart::Expression { expr, format } => {
+                            match Self::template_text(&self.compile_time_strings, expr, format.as_ref()) {
+                                Some(text) => {
+                                    for ch in text.as_bytes() {
+                                        self.emit_push32(*ch as i32);
+                                        self.emit_byte(SYSCALL);
+                                        self.emit_byte(SYSCALL_PRINT);
+                                    }
+                                }
+                                None => match Self::binary_spec(format.as_ref()) {
+                                    Some(Ok((width, padding))) => {
+                                        self.generate_expression(expr, program);
+                                        self.emit_push32(width as i32);
+                                        self.emit_push32(padding as i32);
+                                        self.emit_byte(CALL32);
+                                        self.emit_label_ref("__print_bin");
+                                        self.uses_print_bin = true;
+                                    }
+                                    Some(Err(message)) => self.fail(message),
+                                    None => {
+                                        if let Some(message) = Self::runtime_format_error(format.as_ref(), "NVM") {
+                                            self.fail(message);
+                                        }
+                                        self.generate_expression(expr, program);
+                                        self.emit_byte(CALL32);
+                                        self.emit_label_ref("__print_int");
+                                    }
+                                },
+                            }
                         }
                     }
                 }
@@ -389,10 +1256,35 @@ impl NVMCodeGen {
                     self.emit_byte(LOAD);
                     self.emit_byte(local_index);
                 } else {
-                    panic!("Variable not found: {}", name);
+                    self.fail(format!("Variable not found: {}", name));
+                    self.emit_push32(0);
                 }
             }
 
+            Expression::Binary { op: BinaryOp::Mul, left, .. } if self.is_string_expression(left, program) => {
+                self.fail("string repetition needs dynamic allocation, which NVM does not have; repeat a literal by a literal count instead".to_string());
+                self.emit_push32(0);
+            }
+
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                let short_label = self.generate_label("short");
+                let end_label = self.generate_label("endbool");
+
+                self.generate_expression(left, program);
+                self.emit_byte(if *op == BinaryOp::And { JZ32 } else { JNZ32 });
+                self.emit_label_ref(&short_label);
+
+                self.generate_expression(right, program);
+                self.emit_push32(0);
+                self.emit_byte(NEQ);
+                self.emit_byte(JMP32);
+                self.emit_label_ref(&end_label);
+
+                self.add_label(&short_label);
+                self.emit_push32(if *op == BinaryOp::And { 0 } else { 1 });
+                self.add_label(&end_label);
+            }
+
             Expression::Binary { op, left, right } => {
                 self.generate_expression(left, program);
                 self.generate_expression(right, program);
@@ -437,10 +1329,44 @@ impl NVMCodeGen {
                 }
             }
 
+            Expression::Call { function, .. }
+                if (function == "itoa" || function == "parseInt") && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.fail(format!("{} is not supported on the NVM target", function));
+            }
+
+            Expression::Call { function, args }
+                if Self::is_int_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.generate_int_builtin(function, args, program);
+            }
+
+            Expression::Call { function, args }
+                if Self::is_fatal_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.generate_fatal_builtin(function, &args[0], program);
+            }
+
             Expression::Call { function, args } => {
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg, program);
+                if let Some(module) = self.current_module.clone().filter(|m| program.module_function(m, function).is_some()) {
+                    self.generate_call_args(args, program);
+                    self.emit_byte(CALL32);
+                    self.emit_label_ref(&format!("func_{}_{}", module, function));
+                    return;
+                }
+
+                if let Some(func) = program.functions.iter().find(|f| &f.name == function) {
+                    if func.params.iter().any(|p| p.is_variadic) {
+                        self.fail(format!("variadic function '{}' is not supported on the NVM target", function));
+                        return;
+                    }
+                    if self.is_inlinable(func, args.len()) {
+                        self.generate_inline_call(func, args, program);
+                        return;
+                    }
                 }
+
+                self.generate_call_args(args, program);
                 
                 let func_label = format!("func_{}", function);
                 self.emit_byte(CALL32);
@@ -449,7 +1375,7 @@ impl NVMCodeGen {
 
             Expression::ModuleCall { module, function, args } => {
                 if module == "stdio" {
-                    match function.as_str() {
+                    match Self::stdio_print_name(function, args) {
                         "Print" => {
                             if !args.is_empty() {
                                 if let Expression::String(s) = &args[0] {
@@ -500,10 +1426,43 @@ impl NVMCodeGen {
                                 }
                             }
                         }
-                        _ => {}
+                        "Printf" => {
+                            self.generate_printf(args, program);
+                            self.emit_push32(0);
+                            return;
+                        }
+                        "PrintPtr" if args.len() == 1 => {
+                            self.generate_expression(&args[0], program);
+                            self.generate_print_ptr();
+                            self.emit_push32(0);
+                            return;
+                        }
+                        _ => {
+                            self.fail(format!("stdio.{} is not supported on the NVM target", function));
+                            self.emit_push32(0);
+                            return;
+                        }
                     }
                 }
 
+                if module == "vga" {
+                    self.generate_vga_call(function, args, program);
+                    self.emit_push32(0);
+                    return;
+                }
+
+                if module == "sys" {
+                    self.fail(format!("sys.{} is only supported on ELF targets", function));
+                    self.emit_push32(0);
+                    return;
+                }
+
+                if module == "time" {
+                    self.fail(format!("time.{} is only supported on the x86-64 targets", function));
+                    self.emit_push32(0);
+                    return;
+                }
+
                 if module == "novaria" {
                     match function.as_str() {
                         "FileCreateStr" => {
@@ -542,58 +1501,22 @@ impl NVMCodeGen {
                         _ => {}
                     }
                     
-                    for arg in args.iter().rev() {
-                        self.generate_expression(arg, program);
+                    if let Some(&(_, _, syscall, arity)) = NOVARIA_SYSCALLS.iter().find(|(name, ..)| name == function) {
+                        if args.len() != arity {
+                            self.fail(format!(
+                                "novaria.{} takes {} argument(s), got {} in '{}'",
+                                function, arity, args.len(), self.current_function
+                            ));
+                            return;
+                        }
+                        self.generate_call_args(args, program);
+                        self.emit_byte(SYSCALL);
+                        self.emit_byte(syscall);
+                        return;
                     }
+
+                    self.generate_call_args(args, program);
                     match function.as_str() {
-                        "Exit" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_EXIT);
-                        }
-                        "Exec" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_EXEC);
-                        }
-                        "FileRead" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_READ);
-                        }
-                        "FileWrite" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_WRITE);
-                        }
-                        "FileCreate" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_CREATE);
-                        }
-                        "FileDelete" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_DELETE);
-                        }
-                        "CapCheck" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_CAP_CHECK);
-                        }
-                        "CapSpawn" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_CAP_SPAWN);
-                        }
-                        "MsgSend" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_MSG_SEND);
-                        }
-                        "MsgReceive" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_MSG_RECEIVE);
-                        }
-                        "PortInByte" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_PORT_IN_BYTE);
-                        }
-                        "PortOutByte" => {
-                            self.emit_byte(SYSCALL);
-                            self.emit_byte(SYSCALL_PORT_OUT_BYTE);
-                        }
                         "CAP_FS_READ" => {
                             self.emit_push32(1);
                         }
@@ -624,9 +1547,7 @@ impl NVMCodeGen {
                     return;
                 }
 
-                for arg in args.iter().rev() {
-                    self.generate_expression(arg, program);
-                }
+                self.generate_call_args(args, program);
 
                 let func_label = format!("func_{}_{}", module, function);
                 self.emit_byte(CALL32);
@@ -639,11 +1560,16 @@ impl NVMCodeGen {
                         self.emit_push32(local_index as i32);
                         self.emit_byte(SYSCALL);
                         self.emit_byte(SYSCALL_GET_LOCAL_ADDR);
+                    } else if program.functions.iter().any(|f| &f.name == name) {
+                        self.emit_byte(PUSH32);
+                        self.emit_label_ref(&format!("func_{}", name));
                     } else {
-                        panic!("Variable not found: {}", name);
+                        self.fail(format!("Variable not found: {}", name));
+                        self.emit_push32(0);
                     }
                 } else {
-                    panic!("AddressOf only supports identifiers");
+                    self.fail("AddressOf only supports identifiers on the NVM target".to_string());
+                    self.emit_push32(0);
                 }
             }
 
@@ -652,16 +1578,36 @@ impl NVMCodeGen {
                 self.emit_byte(LOAD_ABS);
             }
 
-            Expression::Eval { instruction } => {
-                self.generate_expression(instruction, program);
-                
-                if let Expression::String(instr_str) = instruction.as_ref() {
-                    self.emit_asm_instruction(instr_str.trim());
-                } else {
-                    eprintln!("Warning: eval() with non-literal string not fully supported yet");
+            Expression::IndirectCall { callee, args } => {
+                self.generate_call_args(args, program);
+                self.generate_expression(callee, program);
+                self.emit_byte(CALL_IND);
+            }
+
+            Expression::Eval { instruction } => match Self::eval_push_operand(instruction) {
+                Some(Expression::Identifier(name)) if !self.local_vars.contains_key(&name) => {
+                    self.fail(format!("eval(): unknown variable '{}' in '{}'", name, self.current_function));
+                }
+                Some(value) => self.generate_expression(&value, program),
+                None => match instruction.as_ref() {
+                    Expression::String(instr_str) => self.emit_asm_instruction(instr_str.trim()),
+                    _ => self.fail(format!(
+                        "eval() in '{}' needs a literal instruction string, `\"push <var>\"` or `\"push $(expr)\"`",
+                        self.current_function
+                    )),
+                },
+            },
+
+            Expression::Tuple(values) => {
+                for value in values {
+                    self.generate_expression(value, program);
                 }
             }
 
+            Expression::Slice { .. } => {
+                self.fail("string slices are not supported on the NVM target".to_string());
+            }
+
             _ => {
                 self.emit_push32(0);
             }
@@ -672,13 +1618,75 @@ impl NVMCodeGen {
         self.bytecode.push(byte);
     }
 
+    fn emit_push_literal(&mut self, value: i64) {
+        match i32::try_from(value) {
+            Ok(v) => self.emit_push32(v),
+            Err(_) => {
+                self.fail(format!(
+                    "integer literal {} in function '{}' does not fit in a 32-bit NVM word",
+                    value, self.current_function
+                ));
+                self.emit_push32(0);
+            }
+        }
+    }
+
     fn emit_push32(&mut self, value: i32) {
         self.emit_byte(PUSH32);
         let bytes = value.to_be_bytes();
         self.bytecode.extend_from_slice(&bytes);
     }
     
+    fn generate_vga_call(&mut self, function: &str, args: &[Expression], program: &Program) {
+        match (function, args) {
+            ("PutChar", [ch, attr]) => {
+                self.vga_reserve_cell();
+                self.emit_push32(self.vga_cursor as i32);
+                self.generate_expression(attr, program);
+                self.emit_push32(256);
+                self.emit_byte(MUL);
+                self.generate_expression(ch, program);
+                self.emit_byte(ADD);
+                self.emit_byte(STORE_ABS);
+                self.vga_cursor += 2;
+            }
+            ("PutStr", [text]) => {
+                let text = match text {
+                    Expression::String(s) => Some(s.clone()),
+                    Expression::Identifier(name) => self.compile_time_strings.get(name).cloned(),
+                    _ => None,
+                };
+                match text {
+                    Some(text) => {
+                        for ch in text.bytes() {
+                            if ch == b'\n' {
+                                self.vga_newline();
+                            } else {
+                                self.emit_vga_char(ch, VGA_DEFAULT_ATTR);
+                            }
+                        }
+                    }
+                    None => self.fail(format!(
+                        "vga.PutStr in '{}' needs a string known at compile time",
+                        self.current_function
+                    )),
+                }
+            }
+            ("Newline", []) => self.vga_newline(),
+            _ => self.fail(format!("unknown vga function '{}' with {} argument(s)", function, args.len())),
+        }
+    }
+
+    fn vga_reserve_cell(&mut self) {
+        if self.vga_cursor >= VGA_END {
+            self.emit_byte(CALL32);
+            self.emit_label_ref("__vga_scroll");
+            self.vga_cursor = VGA_END - VGA_ROW_BYTES;
+        }
+    }
+
     fn emit_vga_char(&mut self, ch: u8, attr: u8) {
+        self.vga_reserve_cell();
         self.emit_push32(self.vga_cursor as i32);
         self.emit_push32(((attr as u32) << 8 | ch as u32) as i32);
         self.emit_byte(STORE_ABS);
@@ -686,15 +1694,122 @@ impl NVMCodeGen {
     }
     
     fn vga_newline(&mut self) {
-        self.vga_cursor = ((self.vga_cursor - 0xB8000) / 160 + 1) * 160 + 0xB8000;
-        self.vga_cursor += 160;
-        if self.vga_cursor >= 0xB8FA0 {
-            self.vga_cursor = 0xB8000 + (18 * 160);
+        let row = (self.vga_cursor - VGA_BASE) / VGA_ROW_BYTES;
+        self.vga_cursor = VGA_BASE + (row + 1) * VGA_ROW_BYTES;
+        self.vga_reserve_cell();
+    }
+
+    fn generate_vga_scroll_helper(&mut self) {
+        self.add_label("__vga_scroll");
+
+        let last_row = VGA_END - VGA_ROW_BYTES - VGA_BASE;
+
+        self.emit_push32(0);
+        self.emit_byte(STORE);
+        self.emit_byte(250);
+
+        let copy_label = self.generate_label("vga_copy");
+        let clear_label = self.generate_label("vga_clear");
+        let done_label = self.generate_label("vga_done");
+
+        self.add_label(&copy_label);
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_push32(last_row as i32);
+        self.emit_byte(LT);
+        self.emit_byte(JZ32);
+        self.emit_label_ref(&clear_label);
+
+        self.emit_push32(VGA_BASE as i32);
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_byte(ADD);
+        self.emit_push32((VGA_BASE + VGA_ROW_BYTES) as i32);
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_byte(ADD);
+        self.emit_byte(LOAD_ABS);
+        self.emit_byte(STORE_ABS);
+
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_push32(2);
+        self.emit_byte(ADD);
+        self.emit_byte(STORE);
+        self.emit_byte(250);
+        self.emit_byte(JMP32);
+        self.emit_label_ref(&copy_label);
+
+        self.add_label(&clear_label);
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_push32((VGA_END - VGA_BASE) as i32);
+        self.emit_byte(LT);
+        self.emit_byte(JZ32);
+        self.emit_label_ref(&done_label);
+
+        self.emit_push32(VGA_BASE as i32);
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_byte(ADD);
+        self.emit_push32(((VGA_DEFAULT_ATTR as u32) << 8 | b' ' as u32) as i32);
+        self.emit_byte(STORE_ABS);
+
+        self.emit_byte(LOAD);
+        self.emit_byte(250);
+        self.emit_push32(2);
+        self.emit_byte(ADD);
+        self.emit_byte(STORE);
+        self.emit_byte(250);
+        self.emit_byte(JMP32);
+        self.emit_label_ref(&clear_label);
+
+        self.add_label(&done_label);
+        self.emit_byte(RET);
+    }
+
+    fn asm_label(&self, name: &str) -> String {
+        format!("asm_{}_{}", self.current_function, name)
+    }
+
+    fn emit_asm_slot(&mut self, opcode: u8, operand: Option<&str>) {
+        match operand.map(|op| op.parse::<u8>()) {
+            Some(Ok(slot)) => {
+                self.emit_byte(opcode);
+                self.emit_byte(slot);
+            }
+            Some(Err(_)) => self.fail(format!(
+                "local slot '{}' in asm block of '{}' must be between 0 and 255",
+                operand.unwrap_or_default(), self.current_function
+            )),
+            None => self.fail(format!("load/store without a local slot in asm block of '{}'", self.current_function)),
+        }
+    }
+
+    fn emit_asm_jump(&mut self, opcode: u8, target: Option<&str>) {
+        match target {
+            Some(target) => {
+                let label = self.asm_label(target);
+                self.emit_byte(opcode);
+                self.emit_label_ref(&label);
+            }
+            None => self.fail(format!("jump without a target label in asm block of '{}'", self.current_function)),
         }
     }
 
     fn emit_asm_instruction(&mut self, line: &str) {
-        let line = line.trim();
+        let mut line = line.trim();
+        if let Some(colon) = line.find(':') {
+            let name = line[..colon].trim();
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                let label = self.asm_label(name);
+                if self.labels.contains_key(&label) {
+                    self.fail(format!("asm label '{}' is defined more than once in '{}'", name, self.current_function));
+                }
+                self.add_label(&label);
+                line = line[colon + 1..].trim();
+            }
+        }
         if line.is_empty() {
             return;
         }
@@ -708,17 +1823,26 @@ impl NVMCodeGen {
         match instr.as_str() {
             "push32" | "push" => {
                 if parts.len() > 1 {
-                    if let Ok(value) = parts[1].parse::<i32>() {
-                        self.emit_push32(value);
+                    if let Ok(value) = parts[1].parse::<i64>() {
+                        self.emit_push_literal(value);
                     }
                 }
             }
             "pop" => self.emit_byte(POP),
+            "swap" => self.emit_byte(SWAP),
+            "load" => self.emit_asm_slot(LOAD, parts.get(1).copied()),
+            "store" => self.emit_asm_slot(STORE, parts.get(1).copied()),
+            "load_abs" => self.emit_byte(LOAD_ABS),
+            "store_abs" => self.emit_byte(STORE_ABS),
             "add" => self.emit_byte(ADD),
             "sub" => self.emit_byte(SUB),
             "mul" => self.emit_byte(MUL),
             "div" => self.emit_byte(DIV),
             "mod" => self.emit_byte(MOD),
+            "eq" => self.emit_byte(EQ),
+            "neq" => self.emit_byte(NEQ),
+            "gt" => self.emit_byte(GT),
+            "lt" => self.emit_byte(LT),
             "syscall" => {
                 self.emit_byte(SYSCALL);
                 if parts.len() > 1 {
@@ -728,6 +1852,7 @@ impl NVMCodeGen {
                     } else {
                         let syscall_num = match syscall_arg.to_lowercase().as_str() {
                             "exit" => SYSCALL_EXIT,
+                            "print" => SYSCALL_PRINT,
                             "exec" => SYSCALL_EXEC,
                             "read" => SYSCALL_READ,
                             "write" => SYSCALL_WRITE,
@@ -754,6 +1879,23 @@ impl NVMCodeGen {
                 }
             }
             "ret" => self.emit_byte(RET),
+            "jmp" => self.emit_asm_jump(JMP32, parts.get(1).copied()),
+            "jz" => self.emit_asm_jump(JZ32, parts.get(1).copied()),
+            "jnz" => self.emit_asm_jump(JNZ32, parts.get(1).copied()),
+            "call_ind" => self.emit_byte(CALL_IND),
+            "call" => {
+                if let Some(target) = parts.get(1) {
+                    let label = if target.starts_with("func_") {
+                        target.to_string()
+                    } else {
+                        format!("func_{}", target)
+                    };
+                    self.emit_byte(CALL32);
+                    self.emit_label_ref(&label);
+                } else {
+                    self.fail(format!("call without a target in asm block of '{}'", self.current_function));
+                }
+            }
             _ => {}
         }
     }
@@ -769,21 +1911,29 @@ impl NVMCodeGen {
         self.labels.insert(label.to_string(), pos);
     }
 
-    fn generate_label(&self, prefix: &str) -> String {
-        use std::sync::atomic::{AtomicU32, Ordering};
-        static COUNTER: AtomicU32 = AtomicU32::new(0);
-        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
-        format!("{}_{}_{}", prefix, self.current_function, count)
+    fn generate_label(&mut self, prefix: &str) -> String {
+        self.label_counter += 1;
+        format!("{}_{}_{}", prefix, self.current_function, self.label_counter)
     }
 
+    /// Fills in every label reference. Offsets are 32-bit, so positions recorded as `u32`
+    /// are only exact while the bytecode stays below 4 GiB; that is checked first.
     fn patch_labels(&mut self) {
-        for (pos, label) in &self.label_patches {
-            if let Some(&target) = self.labels.get(label) {
-                let bytes = target.to_be_bytes();
-                let pos = *pos as usize;
-                self.bytecode[pos..pos + 4].copy_from_slice(&bytes);
-            } else {
-                eprintln!("Warning: Unresolved label: {}", label);
+        if u32::try_from(self.bytecode.len()).is_err() {
+            self.fail(format!(
+                "bytecode is {} bytes, more than the 32-bit label offsets can address",
+                self.bytecode.len()
+            ));
+            return;
+        }
+        for (pos, label) in std::mem::take(&mut self.label_patches) {
+            let pos = pos as usize;
+            match self.labels.get(&label) {
+                Some(&target) if pos + 4 <= self.bytecode.len() => {
+                    self.bytecode[pos..pos + 4].copy_from_slice(&target.to_be_bytes());
+                }
+                Some(_) => self.fail(format!("reference to label '{}' at offset {} is past the end of the bytecode", label, pos)),
+                None => self.fail(format!("unresolved label '{}'", label)),
             }
         }
     }
@@ -800,134 +1950,20 @@ impl NVMCodeGen {
     }
 
     fn generate_print_int_vga_helper(&mut self) {
-        self.add_label("__print_int");
-        
-        self.emit_byte(STORE);
-        self.emit_byte(255);
-        
-        self.emit_byte(STORE);
-        self.emit_byte(250);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_push32(0);
-        self.emit_byte(LT);
-        
-        let not_negative_label = self.generate_label("not_negative");
-        self.emit_byte(JZ32);
-        self.emit_label_ref(&not_negative_label);
-        
-        self.emit_push32('-' as i32);
-        self.emit_byte(SYSCALL);
-        self.emit_byte(SYSCALL_PRINT);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_push32(0);
-        self.emit_byte(SWAP);
-        self.emit_byte(SUB);
-        self.emit_byte(STORE);
-        self.emit_byte(250);
-        
-        self.add_label(&not_negative_label);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_push32(0);
-        self.emit_byte(EQ);
-        
-        let not_zero = self.generate_label("not_zero");
-        self.emit_byte(JZ32);
-        self.emit_label_ref(&not_zero);
-        
-        self.emit_push32('0' as i32);
-        self.emit_byte(SYSCALL);
-        self.emit_byte(SYSCALL_PRINT);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(255);
-        self.emit_byte(RET);
-        
-        self.add_label(&not_zero);
-        
-        self.emit_push32(1);
-        self.emit_byte(STORE);
-        self.emit_byte(251);
-        
-        let find_power_loop = self.generate_label("find_power");
-        let find_power_done = self.generate_label("find_power_done");
-        
-        self.add_label(&find_power_loop);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_push32(10);
-        self.emit_byte(MUL);
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_byte(GT);
-        
-        self.emit_byte(JNZ32);
-        self.emit_label_ref(&find_power_done);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_push32(10);
-        self.emit_byte(MUL);
-        self.emit_byte(STORE);
-        self.emit_byte(251);
-        
-        self.emit_byte(JMP32);
-        self.emit_label_ref(&find_power_loop);
-        
-        self.add_label(&find_power_done);
-        
-        let print_loop = self.generate_label("print_digit_loop");
-        let print_done = self.generate_label("print_done");
-        
-        self.add_label(&print_loop);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_push32(0);
-        self.emit_byte(GT);
-        
-        self.emit_byte(JZ32);
-        self.emit_label_ref(&print_done);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_byte(DIV);
-        
-        self.emit_push32('0' as i32);
-        self.emit_byte(ADD);
-        self.emit_byte(SYSCALL);
-        self.emit_byte(SYSCALL_PRINT);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(250);
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_byte(MOD);
-        self.emit_byte(STORE);
-        self.emit_byte(250);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(251);
-        self.emit_push32(10);
-        self.emit_byte(DIV);
-        self.emit_byte(STORE);
-        self.emit_byte(251);
-        
-        self.emit_byte(JMP32);
-        self.emit_label_ref(&print_loop);
-        
-        self.add_label(&print_done);
-        
-        self.emit_byte(LOAD);
-        self.emit_byte(255);
-        self.emit_byte(RET);
+        self.generate_helper("__print_int", PRINT_INT_HELPER);
+    }
+
+    fn generate_print_bin_helper(&mut self) {
+        self.generate_helper("__print_bin", PRINT_BIN_HELPER);
+    }
+
+    /// Assembles a shared helper listing under `name`; its labels are local to the helper.
+    fn generate_helper(&mut self, name: &str, listing: &[&str]) {
+        self.add_label(name);
+        let caller = std::mem::replace(&mut self.current_function, name.to_string());
+        for line in listing {
+            self.emit_asm_instruction(line);
+        }
+        self.current_function = caller;
     }
 }
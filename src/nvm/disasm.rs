@@ -0,0 +1,115 @@
+use super::codegen::*;
+
+fn syscall_name(code: u8) -> Option<&'static str> {
+    match code {
+        SYSCALL_EXIT => Some("exit"),
+        SYSCALL_EXEC => Some("exec"),
+        SYSCALL_OPEN => Some("open"),
+        SYSCALL_READ => Some("read"),
+        SYSCALL_WRITE => Some("write"),
+        SYSCALL_CREATE => Some("create"),
+        SYSCALL_DELETE => Some("delete"),
+        SYSCALL_CAP_CHECK => Some("cap_check"),
+        SYSCALL_CAP_SPAWN => Some("cap_spawn"),
+        SYSCALL_MSG_SEND => Some("msg_send"),
+        SYSCALL_MSG_RECEIVE => Some("msg_receive"),
+        SYSCALL_PORT_IN_BYTE => Some("inb"),
+        SYSCALL_PORT_OUT_BYTE => Some("outb"),
+        SYSCALL_GET_LOCAL_ADDR => Some("get_local_addr"),
+        SYSCALL_PRINT => Some("print"),
+        _ => None,
+    }
+}
+
+fn simple_name(opcode: u8) -> Option<&'static str> {
+    match opcode {
+        POP => Some("pop"),
+        SWAP => Some("swap"),
+        ADD => Some("add"),
+        SUB => Some("sub"),
+        MUL => Some("mul"),
+        DIV => Some("div"),
+        MOD => Some("mod"),
+        EQ => Some("eq"),
+        NEQ => Some("neq"),
+        GT => Some("gt"),
+        LT => Some("lt"),
+        RET => Some("ret"),
+        CALL_IND => Some("call_ind"),
+        LOAD_ABS => Some("load_abs"),
+        STORE_ABS => Some("store_abs"),
+        _ => None,
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    bytes.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn disassemble(bytecode: &[u8]) -> String {
+    let mut out = String::new();
+
+    if bytecode.len() < 4 || &bytecode[..3] != b"NVM" || !bytecode[3].is_ascii_digit() {
+        out.push_str("; not NVM bytecode (missing NVM magic)\n");
+        return out;
+    }
+
+    let version = bytecode[3] - b'0';
+    out.push_str(&format!("; NVM bytecode, version {}\n", version));
+
+    let mut pos = 4;
+    if version >= 1 {
+        if let (Some(entry), Some(locals)) = (read_u32(bytecode, 4), bytecode.get(8..10)) {
+            out.push_str(&format!("; entry: 0x{:08x}\n", entry));
+            out.push_str(&format!("; locals: {}\n", u16::from_be_bytes([locals[0], locals[1]])));
+        }
+        pos = 12;
+    }
+    out.push('\n');
+
+    while pos < bytecode.len() {
+        let opcode = bytecode[pos];
+        let (text, size) = match opcode {
+            PUSH32 => match read_u32(bytecode, pos + 1) {
+                Some(v) => (format!("push32 {}", v as i32), 5),
+                None => (format!(".byte 0x{:02x}", opcode), 1),
+            },
+            JMP32 | JZ32 | JNZ32 | CALL32 => {
+                let name = match opcode {
+                    JMP32 => "jmp",
+                    JZ32 => "jz",
+                    JNZ32 => "jnz",
+                    _ => "call",
+                };
+                match read_u32(bytecode, pos + 1) {
+                    Some(target) => (format!("{} 0x{:08x}", name, target), 5),
+                    None => (format!(".byte 0x{:02x}", opcode), 1),
+                }
+            }
+            LOAD | STORE => {
+                let name = if opcode == LOAD { "load" } else { "store" };
+                match bytecode.get(pos + 1) {
+                    Some(slot) => (format!("{} {}", name, slot), 2),
+                    None => (format!(".byte 0x{:02x}", opcode), 1),
+                }
+            }
+            SYSCALL => match bytecode.get(pos + 1) {
+                Some(&code) => match syscall_name(code) {
+                    Some(name) => (format!("syscall {}", name), 2),
+                    None => (format!("syscall {}", code), 2),
+                },
+                None => (format!(".byte 0x{:02x}", opcode), 1),
+            },
+            _ => match simple_name(opcode) {
+                Some(name) => (name.to_string(), 1),
+                None => (format!(".byte 0x{:02x}", opcode), 1),
+            },
+        };
+
+        let raw: Vec<String> = bytecode[pos..pos + size].iter().map(|b| format!("{:02x}", b)).collect();
+        out.push_str(&format!("{:08x}:  {:<16} {}\n", pos, raw.join(" "), text));
+        pos += size;
+    }
+
+    out
+}
@@ -1,5 +1,7 @@
 pub mod codegen;
 pub mod asm_generator;
+pub mod disasm;
 
-pub use codegen::NVMCodeGen;
+pub use codegen::{NVMCodeGen, NVM_LATEST_VERSION};
 pub use asm_generator::NVMAssemblyGenerator;
+pub use disasm::disassemble;
@@ -1,10 +1,18 @@
 use crate::lexer::Token;
 use crate::ast::*;
 
+pub const DEFAULT_MAX_ARRAY_SIZE: usize = 65536;
+
 pub struct Parser {
     tokens: Vec<Token>,
     position: usize,
     file: String,
+    enums: std::collections::HashMap<String, Vec<(String, i64)>>,
+    deferred_error: Option<crate::error::CompileError>,
+    max_array_size: usize,
+    token_positions: Vec<(usize, usize)>,
+    statement_lines: Vec<usize>,
+    warnings: Vec<crate::error::CompileWarning>,
 }
 
 impl Parser {
@@ -13,9 +21,36 @@ impl Parser {
             tokens,
             position: 0,
             file: file.to_string(),
+            enums: std::collections::HashMap::new(),
+            deferred_error: None,
+            max_array_size: DEFAULT_MAX_ARRAY_SIZE,
+            token_positions: Vec::new(),
+            statement_lines: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
+    pub fn warnings(&self) -> &[crate::error::CompileWarning] {
+        &self.warnings
+    }
+
+    pub fn set_max_array_size(&mut self, size: usize) {
+        self.max_array_size = size;
+    }
+
+    /// Line and column of every token, as recorded by the lexer.
+    pub fn set_token_positions(&mut self, positions: &[(usize, usize)]) {
+        self.token_positions = positions.to_vec();
+    }
+
+    fn current_position(&self) -> (usize, usize) {
+        self.token_positions.get(self.position).or(self.token_positions.last()).copied().unwrap_or((1, 1))
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_position().0
+    }
+
     fn current_token(&self) -> &Token {
         if self.position < self.tokens.len() {
             &self.tokens[self.position]
@@ -34,31 +69,60 @@ impl Parser {
         }
     }
 
+    /// Skips the newlines and semicolons that separate statements.
+    fn skip_separators(&mut self) {
+        while matches!(self.current_token(), Token::Newline | Token::Semicolon) {
+            self.advance();
+        }
+    }
+
     fn expect(&mut self, expected: Token) -> crate::error::Result<()> {
         if self.current_token() != &expected {
-            return Err(crate::error::CompileError::new(
-                crate::error::ErrorKind::ParserError,
-                format!("expected {:?}, found {:?}", expected, self.current_token()),
-                self.file.clone(),
-                1,
-                1,
-            ));
+            return Err(self.expected(&expected.describe()));
         }
         self.advance();
         Ok(())
     }
 
     fn error(&self, message: String) -> crate::error::CompileError {
+        let (line, column) = self.current_position();
         crate::error::CompileError::new(
             crate::error::ErrorKind::ParserError,
             message,
             self.file.clone(),
-            1,
-            1,
+            line,
+            column,
         )
     }
 
+    /// An "expected X, found Y" error at the current token.
+    fn expected(&self, what: &str) -> crate::error::CompileError {
+        self.error(format!("expected {}, found {}", what, self.current_token().describe()))
+    }
+
+    /// Expression parsing does not return `Result`: its first error is kept and
+    /// reported in place of whatever goes wrong after it.
+    fn defer_error(&mut self, error: crate::error::CompileError) {
+        if self.deferred_error.is_none() {
+            self.deferred_error = Some(error);
+        }
+    }
+
+    fn expect_in_expression(&mut self, expected: Token) {
+        if let Err(e) = self.expect(expected) {
+            self.defer_error(e);
+        }
+    }
+
     pub fn parse(&mut self) -> crate::error::Result<Program> {
+        let result = self.parse_program();
+        match self.deferred_error.take() {
+            Some(err) => Err(err),
+            None => result,
+        }
+    }
+
+    fn parse_program(&mut self) -> crate::error::Result<Program> {
         self.skip_newlines();
 
         self.expect(Token::Package)?;
@@ -67,7 +131,7 @@ impl Parser {
             self.advance();
             p
         } else {
-            return Err(self.error("expected package name".to_string()));
+            return Err(self.expected("package name"));
         };
 
         self.skip_newlines();
@@ -83,17 +147,24 @@ impl Parser {
                 imports.push(import);
                 self.advance();
             } else {
-                return Err(self.error("expected import path string".to_string()));
+                return Err(self.expected("import path string"));
             }
             self.skip_newlines();
         }
 
+        self.collect_enums()?;
+
         let mut functions = Vec::new();
+        let mut enums = Vec::new();
         while !matches!(self.current_token(), Token::Eof) {
             self.skip_newlines();
             if matches!(self.current_token(), Token::Eof) {
                 break;
             }
+            if matches!(self.current_token(), Token::Enum) {
+                enums.push(self.parse_enum()?);
+                continue;
+            }
             functions.push(self.parse_function()?);
         }
 
@@ -101,22 +172,103 @@ impl Parser {
             package,
             imports,
             functions,
-            modules: std::collections::HashMap::new(),
+            enums,
+            modules: std::collections::BTreeMap::new(),
         })
     }
 
-    fn parse_function(&mut self) -> crate::error::Result<Function> {
-        let is_pub = if let Token::Identifier(id) = self.current_token() {
-            if id == "pub" {
-                self.advance();
-                true
-            } else {
-                false
+    /// Enums may be used before their definition, so their variants are
+    /// resolved up front and `Name.Variant` folds to a number while parsing.
+    fn collect_enums(&mut self) -> crate::error::Result<()> {
+        let start = self.position;
+        let mut depth = 0;
+
+        while !matches!(self.current_token(), Token::Eof) {
+            match self.current_token() {
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace => depth -= 1,
+                Token::Enum if depth == 0 => {
+                    let def = self.parse_enum()?;
+                    if self.enums.insert(def.name.clone(), def.variants).is_some() {
+                        return Err(self.error(format!("enum '{}' is defined more than once", def.name)));
+                    }
+                    continue;
+                }
+                _ => {}
             }
+            self.advance();
+        }
+
+        self.position = start;
+        Ok(())
+    }
+
+    fn parse_enum(&mut self) -> crate::error::Result<EnumDef> {
+        self.expect(Token::Enum)?;
+
+        let name = if let Token::Identifier(n) = self.current_token() {
+            let name = n.clone();
+            self.advance();
+            name
         } else {
-            false
+            return Err(self.expected("enum name"));
         };
 
+        self.skip_newlines();
+        self.expect(Token::LeftBrace)?;
+        self.skip_newlines();
+
+        let mut variants: Vec<(String, i64)> = Vec::new();
+        let mut next_value = 0;
+        while !matches!(self.current_token(), Token::RightBrace) {
+            let variant = if let Token::Identifier(v) = self.current_token() {
+                let variant = v.clone();
+                self.advance();
+                variant
+            } else {
+                return Err(self.expected(&format!("variant name in enum '{}'", name)));
+            };
+
+            if variants.iter().any(|(v, _)| v == &variant) {
+                return Err(self.error(format!("duplicate variant '{}' in enum '{}'", variant, name)));
+            }
+
+            if matches!(self.current_token(), Token::Assign) {
+                self.advance();
+                let negative = matches!(self.current_token(), Token::Minus);
+                if negative {
+                    self.advance();
+                }
+                if let Token::Number(n) = self.current_token() {
+                    next_value = if negative { -*n } else { *n };
+                    self.advance();
+                } else {
+                    return Err(self.expected(&format!("integer value for '{}.{}'", name, variant)));
+                }
+            }
+
+            variants.push((variant, next_value));
+            next_value += 1;
+
+            if matches!(self.current_token(), Token::Comma) {
+                self.advance();
+            }
+            self.skip_newlines();
+        }
+
+        self.expect(Token::RightBrace)?;
+        self.skip_newlines();
+
+        Ok(EnumDef { name, variants })
+    }
+
+    fn parse_function(&mut self) -> crate::error::Result<Function> {
+        let is_pub = matches!(self.current_token(), Token::Pub);
+        if is_pub {
+            self.advance();
+        }
+
+        let line = self.current_line();
         self.expect(Token::Func)?;
 
         let name = if let Token::Identifier(n) = self.current_token() {
@@ -124,73 +276,75 @@ impl Parser {
             self.advance();
             name
         } else {
-            return Err(self.error("expected function name".to_string()));
+            return Err(self.expected("function name"));
         };
 
         self.expect(Token::LeftParen)?;
         let mut params = Vec::new();
 
+        self.skip_newlines();
         while !matches!(self.current_token(), Token::RightParen) {
+            if params.last().map(|p: &Parameter| p.is_variadic).unwrap_or(false) {
+                return Err(self.error("variadic parameter must be the last parameter".to_string()));
+            }
+
             let param_name = if let Token::Identifier(n) = self.current_token() {
                 let name = n.clone();
                 self.advance();
                 name
             } else {
-                return Err(self.error("expected parameter name".to_string()));
+                return Err(self.expected("parameter name"));
             };
 
             if matches!(self.current_token(), Token::Colon) {
                 self.advance();
             }
 
-            let param_type = if let Token::Identifier(t) = self.current_token() {
-                let ty = t.clone();
-                self.advance();
-                ty
-            } else {
-                return Err(self.error("expected parameter type".to_string()));
+            let is_variadic = matches!(self.current_token(), Token::Dot);
+            if is_variadic {
+                for _ in 0..3 {
+                    self.expect(Token::Dot)?;
+                }
+            }
+
+            let param_type = match self.parse_type_name() {
+                Some(ty) => ty,
+                None => return Err(self.expected("parameter type")),
             };
 
             params.push(Parameter {
                 name: param_name,
                 param_type,
+                is_variadic,
             });
 
             if matches!(self.current_token(), Token::Comma) {
                 self.advance();
             }
+            self.skip_newlines();
         }
 
         self.expect(Token::RightParen)?;
 
         let return_type = if matches!(self.current_token(), Token::Arrow) {
             self.advance();
-            if let Token::Identifier(t) = self.current_token() {
-                let ty = t.clone();
-                self.advance();
-                Some(ty)
+            if matches!(self.current_token(), Token::LeftParen) {
+                Some(self.parse_return_type_list()?)
             } else {
-                None
+                self.parse_type_name()
             }
-        } else if let Token::Identifier(t) = self.current_token() {
-            let ty = t.clone();
-            self.advance();
-            Some(ty)
+        } else if matches!(self.current_token(), Token::LeftParen) {
+            Some(self.parse_return_type_list()?)
         } else {
-            None
+            self.parse_type_name()
         };
 
         self.skip_newlines();
         self.expect(Token::LeftBrace)?;
         self.skip_newlines();
 
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::RightBrace) {
-            body.push(self.parse_statement()?);
-            self.skip_newlines();
-        }
-
-        self.expect(Token::RightBrace)?;
+        self.statement_lines.clear();
+        let body = self.parse_body(&format!("body of function '{}'", name))?;
         self.skip_newlines();
 
         let is_exported = is_pub || name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false);
@@ -201,16 +355,62 @@ impl Parser {
             return_type,
             body,
             is_exported,
+            file: self.file.clone(),
+            line,
+            statement_lines: std::mem::take(&mut self.statement_lines),
         })
     }
 
+    /// Parses a type name such as `i64` or `*u8`; leaves the position alone when there is none.
+    fn parse_type_name(&mut self) -> Option<String> {
+        let start = self.position;
+        let mut stars = String::new();
+        while matches!(self.current_token(), Token::Star) {
+            stars.push('*');
+            self.advance();
+        }
+        if let Token::Identifier(t) = self.current_token() {
+            let ty = format!("{}{}", stars, t);
+            self.advance();
+            Some(ty)
+        } else {
+            self.position = start;
+            None
+        }
+    }
+
+    fn parse_return_type_list(&mut self) -> crate::error::Result<String> {
+        self.expect(Token::LeftParen)?;
+
+        let mut types = Vec::new();
+        while !matches!(self.current_token(), Token::RightParen) {
+            if let Token::Identifier(t) = self.current_token() {
+                types.push(t.clone());
+                self.advance();
+            } else {
+                return Err(self.expected("return type"));
+            }
+
+            if matches!(self.current_token(), Token::Comma) {
+                self.advance();
+            }
+        }
+
+        self.expect(Token::RightParen)?;
+        Ok(format!("({})", types.join(", ")))
+    }
+
     fn parse_statement(&mut self) -> crate::error::Result<Statement> {
         match self.current_token() {
-            Token::Var => self.parse_var_decl(),
             Token::If => self.parse_if(),
             Token::For => self.parse_for(),
             Token::Return => self.parse_return(),
             Token::Asm => self.parse_asm(),
+            Token::LeftBrace => {
+                self.advance();
+                self.skip_newlines();
+                Ok(Statement::Block(self.parse_body("block")?))
+            }
             Token::Star => {
                 let next_pos = self.position + 1;
                 let mut check_pos = next_pos;
@@ -219,17 +419,17 @@ impl Parser {
                         Token::Assign => {
                             return self.parse_pointer_assignment();
                         }
-                        Token::Identifier(_) | Token::LeftParen | Token::RightParen => {
-                            check_pos += 1;
-                        }
-                        _ => break,
+                        Token::Newline | Token::Semicolon | Token::RightBrace | Token::Eof => break,
+                        _ => check_pos += 1,
                     }
                 }
                 Ok(Statement::Expression(self.parse_expression()))
             }
             Token::Identifier(_) => {
                 let next_pos = self.position + 1;
-                if next_pos < self.tokens.len() && (matches!(self.tokens[next_pos], Token::Assign) || matches!(self.tokens[next_pos], Token::LBracket)) {
+                if matches!(self.tokens.get(next_pos), Some(Token::ColonAssign)) {
+                    self.parse_short_var_decl()
+                } else if next_pos < self.tokens.len() && (matches!(self.tokens[next_pos], Token::Assign) || matches!(self.tokens[next_pos], Token::LBracket)) {
                     self.parse_assignment()
                 } else {
                     Ok(Statement::Expression(self.parse_expression()))
@@ -239,7 +439,8 @@ impl Parser {
         }
     }
 
-    fn parse_var_decl(&mut self) -> crate::error::Result<Statement> {
+    /// A `var` declaration. A list of names with one value per name becomes one `VarDecl` each.
+    fn parse_var_decl(&mut self) -> crate::error::Result<Vec<Statement>> {
         self.expect(Token::Var)?;
 
         let name = if let Token::Identifier(n) = self.current_token() {
@@ -247,9 +448,50 @@ impl Parser {
             self.advance();
             name
         } else {
-            return Err(self.error("expected variable name".to_string()));
+            return Err(self.expected("variable name"));
         };
 
+        if matches!(self.current_token(), Token::Comma) {
+            let mut names = vec![name];
+            while matches!(self.current_token(), Token::Comma) {
+                self.advance();
+                if let Token::Identifier(n) = self.current_token() {
+                    names.push(n.clone());
+                    self.advance();
+                } else {
+                    return Err(self.expected("variable name"));
+                }
+            }
+            if matches!(self.current_token(), Token::Colon) {
+                self.advance();
+            }
+            let var_type = self.parse_type_name();
+            if !matches!(self.current_token(), Token::Assign) {
+                return Ok(names.into_iter()
+                    .map(|name| Statement::VarDecl { name, var_type: var_type.clone(), value: None })
+                    .collect());
+            }
+            self.advance();
+
+            let mut values = vec![self.parse_expression()];
+            while matches!(self.current_token(), Token::Comma) {
+                self.advance();
+                values.push(self.parse_expression());
+            }
+            if values.len() == 1 && var_type.is_none() {
+                return Ok(vec![Statement::MultiVarDecl { names, value: values.remove(0) }]);
+            }
+            if values.len() != names.len() {
+                return Err(self.error(format!(
+                    "assignment mismatch: {} variables but {} values",
+                    names.len(), values.len()
+                )));
+            }
+            return Ok(names.into_iter().zip(values)
+                .map(|(name, value)| Statement::VarDecl { name, var_type: var_type.clone(), value: Some(value) })
+                .collect());
+        }
+
         if matches!(self.current_token(), Token::Colon) {
             self.advance();
         }
@@ -257,12 +499,25 @@ impl Parser {
         if matches!(self.current_token(), Token::LBracket) {
             self.advance();
 
+            let negative = matches!(self.current_token(), Token::Minus);
+            if negative {
+                self.advance();
+            }
             let size = if let Token::Number(n) = self.current_token() {
-                let s = *n as usize;
+                let n = if negative { -*n } else { *n };
+                if n <= 0 {
+                    return Err(self.error(format!("array '{}' must have a positive size, found {}", name, n)));
+                }
+                if n as u64 > self.max_array_size as u64 {
+                    return Err(self.error(format!(
+                        "array '{}' has {} elements, more than the limit of {} (raise it with --max-array-size)",
+                        name, n, self.max_array_size
+                    )));
+                }
                 self.advance();
-                s
+                n as usize
             } else {
-                return Err(self.error("expected array size".to_string()));
+                return Err(self.expected("array size"));
             };
 
             self.expect(Token::RBracket)?;
@@ -272,28 +527,37 @@ impl Parser {
                 self.advance();
                 ty
             } else {
-                return Err(self.error("expected array element type".to_string()));
+                return Err(self.expected("array element type"));
             };
 
-            return Ok(Statement::ArrayDecl { name, element_type, size });
+            return Ok(vec![Statement::ArrayDecl { name, element_type, size }]);
         }
 
-        let var_type = if let Token::Identifier(t) = self.current_token() {
-            let ty = t.clone();
+        let var_type = self.parse_type_name();
+
+        let value = if matches!(self.current_token(), Token::Assign) {
             self.advance();
-            Some(ty)
+            Some(self.parse_expression())
         } else {
             None
         };
 
-        let value = if matches!(self.current_token(), Token::Assign) {
+        Ok(vec![Statement::VarDecl { name, var_type, value }])
+    }
+
+    /// `name := value`, shorthand for `var name = value`.
+    fn parse_short_var_decl(&mut self) -> crate::error::Result<Statement> {
+        let name = if let Token::Identifier(n) = self.current_token() {
+            let name = n.clone();
             self.advance();
-            Some(self.parse_expression())
+            name
         } else {
-            None
+            return Err(self.expected("variable name"));
         };
+        self.expect(Token::ColonAssign)?;
+        let value = self.parse_expression();
 
-        Ok(Statement::VarDecl { name, var_type, value })
+        Ok(Statement::VarDecl { name, var_type: None, value: Some(value) })
     }
 
     fn parse_assignment(&mut self) -> crate::error::Result<Statement> {
@@ -302,7 +566,7 @@ impl Parser {
             self.advance();
             name
         } else {
-            return Err(self.error("expected variable name".to_string()));
+            return Err(self.expected("variable name"));
         };
 
         if matches!(self.current_token(), Token::LBracket) {
@@ -330,6 +594,30 @@ impl Parser {
         Ok(Statement::PointerAssignment { target, value })
     }
 
+    /// Statements up to and including the closing `}` of a block whose `{` was already consumed.
+    fn parse_body(&mut self, what: &str) -> crate::error::Result<Vec<Statement>> {
+        let mut body = Vec::new();
+        self.skip_separators();
+        while !matches!(self.current_token(), Token::RightBrace) {
+            if matches!(self.current_token(), Token::Eof) {
+                return Err(self.error(format!("unterminated {} (missing `}}`)", what)));
+            }
+            let line = self.current_line();
+            if matches!(self.current_token(), Token::Var) {
+                let decls = self.parse_var_decl()?;
+                self.statement_lines.extend(std::iter::repeat_n(line, decls.len()));
+                body.extend(decls);
+            } else {
+                self.statement_lines.push(line);
+                body.push(self.parse_statement()?);
+            }
+            self.skip_separators();
+        }
+
+        self.expect(Token::RightBrace)?;
+        Ok(body)
+    }
+
     fn parse_if(&mut self) -> crate::error::Result<Statement> {
         self.expect(Token::If)?;
 
@@ -339,13 +627,7 @@ impl Parser {
         self.expect(Token::LeftBrace)?;
         self.skip_newlines();
 
-        let mut then_body = Vec::new();
-        while !matches!(self.current_token(), Token::RightBrace) {
-            then_body.push(self.parse_statement()?);
-            self.skip_newlines();
-        }
-
-        self.expect(Token::RightBrace)?;
+        let then_body = self.parse_body("`if` block")?;
         self.skip_newlines();
 
         let else_body = if matches!(self.current_token(), Token::Else) {
@@ -354,14 +636,7 @@ impl Parser {
             self.expect(Token::LeftBrace)?;
             self.skip_newlines();
 
-            let mut body = Vec::new();
-            while !matches!(self.current_token(), Token::RightBrace) {
-                body.push(self.parse_statement()?);
-                self.skip_newlines();
-            }
-
-            self.expect(Token::RightBrace)?;
-            Some(body)
+            Some(self.parse_body("`else` block")?)
         } else {
             None
         };
@@ -376,44 +651,131 @@ impl Parser {
     fn parse_for(&mut self) -> crate::error::Result<Statement> {
         self.expect(Token::For)?;
 
-        let condition = if matches!(self.current_token(), Token::LeftBrace) {
+        if !self.for_has_clauses() {
+            let condition = if matches!(self.current_token(), Token::LeftBrace) {
+                None
+            } else {
+                Some(self.parse_expression())
+            };
+
+            self.skip_newlines();
+            self.expect(Token::LeftBrace)?;
+            self.skip_newlines();
+
+            let body = self.parse_body("`for` loop")?;
+
+            return Ok(Statement::For {
+                init: None,
+                condition,
+                post: None,
+                body,
+            });
+        }
+
+        let init = if matches!(self.current_token(), Token::Semicolon) {
+            None
+        } else {
+            self.statement_lines.push(self.current_line());
+            let init = if matches!(self.current_token(), Token::Var) {
+                let mut decls = self.parse_var_decl()?;
+                if decls.len() != 1 {
+                    return Err(self.error("a `for` initializer declares a single variable".to_string()));
+                }
+                decls.remove(0)
+            } else {
+                self.parse_statement()?
+            };
+            Some(Box::new(init))
+        };
+        self.expect(Token::Semicolon)?;
+
+        let condition = if matches!(self.current_token(), Token::Semicolon) {
             None
         } else {
             Some(self.parse_expression())
         };
+        self.expect(Token::Semicolon)?;
+
+        let post_line = self.current_line();
+        let post = if matches!(self.current_token(), Token::LeftBrace) {
+            None
+        } else {
+            Some(Box::new(self.parse_statement()?))
+        };
 
         self.skip_newlines();
         self.expect(Token::LeftBrace)?;
         self.skip_newlines();
 
-        let mut body = Vec::new();
-        while !matches!(self.current_token(), Token::RightBrace) {
-            body.push(self.parse_statement()?);
-            self.skip_newlines();
+        let body = self.parse_body("`for` loop")?;
+        // The post statement runs after the body, so its line follows the body's.
+        if post.is_some() {
+            self.statement_lines.push(post_line);
         }
 
-        self.expect(Token::RightBrace)?;
-
         Ok(Statement::For {
-            init: None,
+            init,
             condition,
-            post: None,
+            post,
             body,
         })
     }
 
+    /// Whether a `;` before the loop's `{` makes this the `for init; condition; post` form.
+    fn for_has_clauses(&self) -> bool {
+        let mut depth = 0usize;
+        for token in &self.tokens[self.position.min(self.tokens.len())..] {
+            match token {
+                Token::LeftParen | Token::LBracket => depth += 1,
+                Token::RightParen | Token::RBracket => depth = depth.saturating_sub(1),
+                Token::Semicolon if depth == 0 => return true,
+                Token::LeftBrace if depth == 0 => return false,
+                Token::Newline | Token::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
     fn parse_return(&mut self) -> crate::error::Result<Statement> {
         self.expect(Token::Return)?;
 
-        let value = if matches!(self.current_token(), Token::Newline | Token::RightBrace) {
+        let value = if matches!(self.current_token(), Token::Newline | Token::Semicolon | Token::RightBrace) {
             None
         } else {
-            Some(self.parse_expression())
+            let first = self.parse_expression();
+            if matches!(self.current_token(), Token::Comma) {
+                let mut values = vec![first];
+                while matches!(self.current_token(), Token::Comma) {
+                    self.advance();
+                    values.push(self.parse_expression());
+                }
+                Some(Expression::Tuple(values))
+            } else {
+                Some(first)
+            }
         };
 
         Ok(Statement::Return(value))
     }
 
+    /// Arguments up to (not including) the closing `)`; a trailing comma and line breaks are allowed.
+    fn parse_call_args(&mut self) -> Vec<Expression> {
+        let mut args = Vec::new();
+
+        self.skip_newlines();
+        while !matches!(self.current_token(), Token::RightParen | Token::Eof) {
+            args.push(self.parse_expression());
+
+            if matches!(self.current_token(), Token::Comma) {
+                self.advance();
+            }
+            self.skip_newlines();
+        }
+
+        args
+    }
+
     fn parse_asm(&mut self) -> crate::error::Result<Statement> {
         use crate::ast::AsmPart;
         
@@ -434,6 +796,9 @@ impl Parser {
             
             while !matches!(self.current_token(), Token::RightBrace) {
                 match self.current_token() {
+                    Token::Eof => {
+                        return Err(self.error("unterminated `asm` block (missing `}`)".to_string()));
+                    }
                     Token::Dollar => {
                         let line_before_var = current_line.trim().to_string();
                         current_line.clear();
@@ -452,53 +817,22 @@ impl Parser {
                         }
                     }
                     Token::Identifier(instr) => {
-                        if instr == ";" || instr.starts_with(';') {
-                            while !matches!(self.current_token(), Token::Newline | Token::RightBrace | Token::Eof) {
-                                self.advance();
-                            }
-                        } else {
-                            if !current_line.is_empty() {
-                                current_line.push(' ');
-                            }
-                            current_line.push_str(instr);
+                        if !current_line.is_empty() {
+                            current_line.push(' ');
                         }
+                        current_line.push_str(instr);
                         self.advance();
                     }
                     Token::Number(n) => {
                         if !current_line.is_empty() {
                             current_line.push(' ');
                         }
-                        let n_val = *n;
-                        let num_str = if n_val == 0 {
-                            let next_pos = self.position + 1;
-                            if next_pos < self.tokens.len() {
-                                if let Token::Identifier(id) = &self.tokens[next_pos] {
-                                    if id.starts_with('x') || id.starts_with('X') {
-                                        let hex_str = format!("0{}", id);
-                                        self.advance();
-                                        self.advance();
-                                        hex_str
-                                    } else {
-                                        n_val.to_string()
-                                    }
-                                } else {
-                                    n_val.to_string()
-                                }
-                            } else {
-                                n_val.to_string()
-                            }
-                        } else {
-                            n_val.to_string()
-                        };
-                        current_line.push_str(&num_str);
-                        if !num_str.starts_with("0x") && !num_str.starts_with("0X") {
-                            self.advance();
-                        }
+                        current_line.push_str(&n.to_string());
+                        self.advance();
                     }
-                    Token::Semicolon => {
-                        while !matches!(self.current_token(), Token::Newline | Token::RightBrace | Token::Eof) {
-                            self.advance();
-                        }
+                    Token::Colon => {
+                        current_line.push(':');
+                        self.advance();
                     }
                     Token::Newline => {
                         if !current_line.is_empty() {
@@ -521,7 +855,7 @@ impl Parser {
             self.expect(Token::RightBrace)?;
             Ok(Statement::InlineAsm { parts })
         } else {
-            Err(self.error("expected assembly code string or block after 'asm'".to_string()))
+            Err(self.expected("assembly code string or block after 'asm'"))
         }
     }
     
@@ -533,7 +867,12 @@ impl Parser {
         let mut chars = code.chars().peekable();
         
         while let Some(ch) = chars.next() {
-            if ch == '$' && chars.peek() == Some(&'(') {
+            if ch == ';' {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            } else if ch == '$' && chars.peek() == Some(&'$') {
+                chars.next();
+                current_literal.push('$');
+            } else if ch == '$' && chars.peek() == Some(&'(') {
                 chars.next();
                 
                 if !current_literal.is_empty() {
@@ -600,6 +939,7 @@ impl Parser {
 
     fn parse_equality(&mut self) -> Expression {
         let mut left = self.parse_comparison();
+        let mut chained = false;
 
         loop {
             let op = match self.current_token() {
@@ -608,8 +948,13 @@ impl Parser {
                 _ => break,
             };
 
+            let position = self.current_position();
             self.advance();
             let right = self.parse_comparison();
+            if chained {
+                self.warn_chained_comparison(position, &left, &op, &right);
+            }
+            chained = true;
             left = Expression::Binary {
                 op,
                 left: Box::new(left),
@@ -622,6 +967,7 @@ impl Parser {
 
     fn parse_comparison(&mut self) -> Expression {
         let mut left = self.parse_additive();
+        let mut chained = false;
 
         loop {
             let op = match self.current_token() {
@@ -632,8 +978,13 @@ impl Parser {
                 _ => break,
             };
 
+            let position = self.current_position();
             self.advance();
             let right = self.parse_additive();
+            if chained {
+                self.warn_chained_comparison(position, &left, &op, &right);
+            }
+            chained = true;
             left = Expression::Binary {
                 op,
                 left: Box::new(left),
@@ -644,6 +995,23 @@ impl Parser {
         left
     }
 
+    /// `a < b < c` parses as `(a < b) < c`, comparing the 0/1 result of `a < b` with `c`.
+    /// Parenthesizing the first comparison marks it as deliberate and silences the warning.
+    fn warn_chained_comparison(&mut self, (line, column): (usize, usize), left: &Expression, op: &BinaryOp, right: &Expression) {
+        let Expression::Binary { right: middle, .. } = left else {
+            return;
+        };
+        self.warnings.push(crate::error::CompileWarning {
+            message: format!(
+                "`{} {} {}` compares the 0/1 result of `{}` with `{}`; write `{} && {} {} {}` to test both",
+                left, op.symbol(), right, left, right, left, middle, op.symbol(), right
+            ),
+            file: self.file.clone(),
+            line,
+            column,
+        });
+    }
+
     fn parse_additive(&mut self) -> Expression {
         let mut left = self.parse_multiplicative();
 
@@ -680,10 +1048,15 @@ impl Parser {
 
             self.advance();
             let right = self.parse_unary();
-            left = Expression::Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
+            left = match (&left, right.constant_value()) {
+                (Expression::String(s), Some(count)) if op == BinaryOp::Mul => {
+                    Expression::String(s.repeat(count.max(0) as usize))
+                }
+                _ => Expression::Binary {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
             };
         }
 
@@ -734,7 +1107,10 @@ impl Parser {
         let mut chars = s.chars().peekable();
         
         while let Some(ch) = chars.next() {
-            if ch == '$' && chars.peek() == Some(&'(') {
+            if ch == '$' && chars.peek() == Some(&'$') {
+                chars.next();
+                current_literal.push('$');
+            } else if ch == '$' && chars.peek() == Some(&'(') {
                 chars.next();
                 
                 if !current_literal.is_empty() {
@@ -763,7 +1139,13 @@ impl Parser {
                 let (expr_str, format_spec) = self.parse_format_spec(&expr_str);
                 
                 let mut lexer = crate::lexer::Lexer::new(&expr_str);
-                let tokens = lexer.tokenize();
+                let tokens = match lexer.tokenize() {
+                    Ok(tokens) => tokens,
+                    Err(e) => {
+                        self.defer_error(e);
+                        vec![Token::Eof]
+                    }
+                };
                 let mut parser = Parser::new(tokens, &self.file);
                 let expr = parser.parse_expression();
                 
@@ -776,6 +1158,10 @@ impl Parser {
             }
         }
         
+        if parts.is_empty() {
+            return Expression::String(current_literal);
+        }
+        
         if !current_literal.is_empty() {
             parts.push(TemplateStringPart::Literal(current_literal));
         }
@@ -786,7 +1172,18 @@ impl Parser {
     fn parse_format_spec(&self, expr_str: &str) -> (String, Option<crate::ast::FormatSpec>) {
         use crate::ast::{FormatSpec, FormatType};
         
-        if let Some(colon_pos) = expr_str.rfind(':') {
+        let mut depth = 0;
+        let mut separator = None;
+        for (i, ch) in expr_str.char_indices() {
+            match ch {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                ':' if depth == 0 => separator = Some(i),
+                _ => {}
+            }
+        }
+        
+        if let Some(colon_pos) = separator {
             let expr_part = expr_str[..colon_pos].trim();
             let format_part = expr_str[colon_pos + 1..].trim();
             
@@ -797,6 +1194,11 @@ impl Parser {
                 
                 let mut format_chars = format_part.chars().peekable();
                 
+                let left_align = format_chars.peek() == Some(&'-');
+                if left_align {
+                    format_chars.next();
+                }
+                
                 if format_chars.peek() == Some(&'0') {
                     padding = '0';
                     format_chars.next();
@@ -816,21 +1218,42 @@ impl Parser {
                     width = width_str.parse().ok();
                 }
                 
+                let mut precision = None;
+                if format_chars.peek() == Some(&'.') {
+                    format_chars.next();
+                    let mut precision_str = String::new();
+                    while let Some(&ch) = format_chars.peek() {
+                        if ch.is_ascii_digit() {
+                            precision_str.push(ch);
+                            format_chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    precision = Some(precision_str.parse().unwrap_or(0));
+                }
+                
                 if let Some(ch) = format_chars.next() {
                     format_type = match ch {
                         'd' => FormatType::Decimal,
                         'x' => FormatType::Hex,
                         'X' => FormatType::HexUpper,
+                        'b' => FormatType::Binary,
                         's' => FormatType::String,
-                        _ => FormatType::Auto,
+                        _ => return (expr_str.to_string(), None),
                     };
                 }
                 
+                if format_chars.next().is_some() {
+                    return (expr_str.to_string(), None);
+                }
+                
                 return (expr_part.to_string(), Some(FormatSpec {
                     width,
-                    precision: None,
+                    precision,
                     format_type,
                     padding,
+                    left_align,
                 }));
             }
         }
@@ -838,6 +1261,22 @@ impl Parser {
         (expr_str.to_string(), None)
     }
 
+    fn parse_slice(&mut self, base: Expression, start: Option<Expression>) -> Expression {
+        self.advance();
+        let end = if matches!(self.current_token(), Token::RBracket) {
+            None
+        } else {
+            Some(self.parse_expression())
+        };
+        self.expect_in_expression(Token::RBracket);
+
+        Expression::Slice {
+            base: Box::new(base),
+            start: start.map(Box::new),
+            end: end.map(Box::new),
+        }
+    }
+
     fn parse_primary(&mut self) -> Expression {
         match self.current_token().clone() {
             Token::Number(n) => {
@@ -849,10 +1288,14 @@ impl Parser {
 
                 if matches!(self.current_token(), Token::LBracket) {
                     self.advance();
+                    if matches!(self.current_token(), Token::Colon) {
+                        return self.parse_slice(Expression::String(s), None);
+                    }
                     let index = self.parse_expression();
-                    if let Err(_) = self.expect(Token::RBracket) {
-                        panic!("Expected closing bracket in string index");
+                    if matches!(self.current_token(), Token::Colon) {
+                        return self.parse_slice(Expression::String(s), Some(index));
                     }
+                    self.expect_in_expression(Token::RBracket);
 
                     return Expression::StringIndex {
                         string: Box::new(Expression::String(s)),
@@ -860,7 +1303,7 @@ impl Parser {
                     };
                 }
 
-                if s.contains("$(") {
+                if s.contains('$') {
                     self.parse_template_string(s)
                 } else {
                     Expression::String(s)
@@ -869,53 +1312,63 @@ impl Parser {
             Token::Identifier(name) => {
                 self.advance();
 
+                if let Some(variants) = self.enums.get(&name).cloned() {
+                    if matches!(self.current_token(), Token::Dot) {
+                        self.advance();
+                        let value = match self.current_token() {
+                            Token::Identifier(variant) => {
+                                variants.iter().find(|(v, _)| v == variant).map(|(_, value)| *value)
+                                    .ok_or_else(|| format!("enum '{}' has no variant '{}'", name, variant))
+                            }
+                            _ => Err(format!("expected variant name after '{}.'", name)),
+                        };
+                        self.advance();
+                        return match value {
+                            Ok(n) => Expression::Number(n),
+                            Err(message) => {
+                                self.defer_error(self.error(message));
+                                Expression::Number(0)
+                            }
+                        };
+                    }
+                }
+
                 if matches!(self.current_token(), Token::Dot) {
                     self.advance();
+                    // Every part but the last names a submodule: `net.http.Get` calls `Get` in `net.http`.
+                    let mut module = name;
+                    while matches!(self.tokens.get(self.position + 1), Some(Token::Dot)) {
+                        let Token::Identifier(part) = self.current_token() else { break };
+                        module = format!("{}.{}", module, part);
+                        self.advance();
+                        self.advance();
+                    }
                     if let Token::Identifier(func_name) = self.current_token() {
                         let func_name = func_name.clone();
                         self.advance();
 
                         if matches!(self.current_token(), Token::LeftParen) {
                             self.advance();
-                            let mut args = Vec::new();
-
-                            while !matches!(self.current_token(), Token::RightParen) {
-                                args.push(self.parse_expression());
+                            let args = self.parse_call_args();
 
-                                if matches!(self.current_token(), Token::Comma) {
-                                    self.advance();
-                                }
-                            }
-
-                            if let Err(_) = self.expect(Token::RightParen) {
-                                panic!("Expected closing parenthesis in module call");
-                            }
+                            self.expect_in_expression(Token::RightParen);
 
                             return Expression::ModuleCall {
-                                module: name,
+                                module,
                                 function: func_name,
                                 args,
                             };
                         }
                     }
-                    panic!("Expected function name after module.");
+                    self.defer_error(self.expected(&format!("a call of a function in `{}`", module)));
+                    return Expression::Number(0);
                 }
 
                 if matches!(self.current_token(), Token::LeftParen) {
                     self.advance();
-                    let mut args = Vec::new();
-
-                    while !matches!(self.current_token(), Token::RightParen) {
-                        args.push(self.parse_expression());
-
-                        if matches!(self.current_token(), Token::Comma) {
-                            self.advance();
-                        }
-                    }
+                    let args = self.parse_call_args();
 
-                    if let Err(_) = self.expect(Token::RightParen) {
-                        panic!("Expected closing parenthesis in function call");
-                    }
+                    self.expect_in_expression(Token::RightParen);
 
                     if name == "eval" && args.len() == 1 {
                         return Expression::Eval {
@@ -929,10 +1382,14 @@ impl Parser {
                     }
                 } else if matches!(self.current_token(), Token::LBracket) {
                     self.advance();
+                    if matches!(self.current_token(), Token::Colon) {
+                        return self.parse_slice(Expression::Identifier(name), None);
+                    }
                     let index = self.parse_expression();
-                    if let Err(_) = self.expect(Token::RBracket) {
-                        panic!("Expected closing bracket in array access");
+                    if matches!(self.current_token(), Token::Colon) {
+                        return self.parse_slice(Expression::Identifier(name), Some(index));
                     }
+                    self.expect_in_expression(Token::RBracket);
 
                     Expression::ArrayAccess {
                         name,
@@ -945,12 +1402,29 @@ impl Parser {
             Token::LeftParen => {
                 self.advance();
                 let expr = self.parse_expression();
-                if let Err(_) = self.expect(Token::RightParen) {
-                    panic!("Expected closing parenthesis");
+                self.expect_in_expression(Token::RightParen);
+
+                if matches!(self.current_token(), Token::LeftParen) {
+                    self.advance();
+                    let args = self.parse_call_args();
+
+                    self.expect_in_expression(Token::RightParen);
+
+                    return Expression::IndirectCall {
+                        callee: Box::new(expr),
+                        args,
+                    };
                 }
+
                 expr
             }
-            _ => panic!("Unexpected token: {:?}", self.current_token()),
+            _ => {
+                self.defer_error(self.expected("an expression"));
+                if !matches!(self.current_token(), Token::Eof) {
+                    self.advance();
+                }
+                Expression::Number(0)
+            }
         }
     }
 }
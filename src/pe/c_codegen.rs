@@ -8,6 +8,10 @@ pub struct CCodeGen {
     output: String,
     var_types: HashMap<String, bool>,
     temp_counter: usize,
+    entry: String,
+    /// Set while generating a C `main` for a function without a return value, whose
+    /// bare `return` must still yield an exit status.
+    in_void_main: bool,
 }
 
 impl CCodeGen {
@@ -16,9 +20,15 @@ impl CCodeGen {
             output: String::new(),
             var_types: HashMap::new(),
             temp_counter: 0,
+            entry: "main".to_string(),
+            in_void_main: false,
         }
     }
 
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
     pub fn generate(&mut self, program: &Program) -> Result<String, String> {
         self.output.push_str("#include <stdio.h>\n");
         self.output.push_str("#include <stdlib.h>\n");
@@ -28,12 +38,46 @@ impl CCodeGen {
             self.generate_function(func)?;
         }
 
+        if self.entry != "main" {
+            let returns_value = program.functions.iter()
+                .find(|f| f.name == self.entry)
+                .is_some_and(Self::returns_value);
+            if returns_value {
+                self.output.push_str(&format!("int main(void) {{\n    return (int){}();\n}}\n", self.entry));
+            } else {
+                self.output.push_str(&format!("int main(void) {{\n    {}();\n    return 0;\n}}\n", self.entry));
+            }
+        }
+
         Ok(self.output.clone())
     }
 
+    fn returns_value(func: &Function) -> bool {
+        !matches!(func.return_type.as_deref(), None | Some("void"))
+    }
+
+    /// C name of a function; with another entry, a user `main` is renamed so it does not
+    /// clash with the `main` wrapper.
+    fn c_symbol(&self, name: &str) -> String {
+        if name == "main" && self.entry != "main" {
+            "main_user".to_string()
+        } else {
+            name.to_string()
+        }
+    }
+
     fn generate_function(&mut self, func: &Function) -> Result<(), String> {
-        self.output.push_str("void ");
-        self.output.push_str(&func.name);
+        let symbol = self.c_symbol(&func.name);
+        let return_type = if symbol == "main" {
+            "int"
+        } else if !Self::returns_value(func) {
+            "void"
+        } else {
+            "long long"
+        };
+        self.output.push_str(return_type);
+        self.output.push(' ');
+        self.output.push_str(&symbol);
         self.output.push_str("(");
         
         for (i, param) in func.params.iter().enumerate() {
@@ -46,9 +90,13 @@ impl CCodeGen {
         
         self.output.push_str(") {\n");
         
+        self.in_void_main = symbol == "main" && !Self::returns_value(func);
         for stmt in &func.body {
             self.generate_statement(stmt)?;
         }
+        if self.in_void_main {
+            self.output.push_str("    return 0;\n");
+        }
         
         self.output.push_str("}\n\n");
         Ok(())
@@ -131,9 +179,18 @@ impl CCodeGen {
                 self.output.push_str("    return ");
                 if let Some(e) = expr {
                     self.generate_expression(e)?;
+                } else if self.in_void_main {
+                    self.output.push('0');
                 }
                 self.output.push_str(";\n");
             }
+            Statement::Block(body) => {
+                self.output.push_str("    {\n");
+                for stmt in body {
+                    self.generate_statement(stmt)?;
+                }
+                self.output.push_str("    }\n");
+            }
             _ => {}
         }
         Ok(())
@@ -157,6 +214,12 @@ impl CCodeGen {
                 self.output.push_str(name);
             }
             Expression::ModuleCall { module, function, args } => {
+                if module == "sys" {
+                    return Err(format!("sys.{} is only supported on ELF targets", function));
+                }
+                if module == "time" {
+                    return Err(format!("time.{} is only supported by the machine-code backends", function));
+                }
                 if module == "stdio" {
                     match function.as_str() {
                         "PrintlnStr" => {
@@ -180,10 +243,33 @@ impl CCodeGen {
                             }
                             self.output.push_str(")");
                         }
+                        "PrintPtr" => {
+                            self.output.push_str("printf(\"0x%016llx\", (unsigned long long)");
+                            if !args.is_empty() {
+                                self.generate_expression(&args[0])?;
+                            }
+                            self.output.push(')');
+                        }
+                        "Printf" => {
+                            self.output.push_str("printf(");
+                            for (i, arg) in args.iter().enumerate() {
+                                if i > 0 {
+                                    self.output.push_str(", (long long)");
+                                }
+                                self.generate_expression(arg)?;
+                            }
+                            self.output.push(')');
+                        }
                         _ => return Err(format!("Unknown stdio function: {}", function)),
                     }
                 }
             }
+            Expression::Binary { op: crate::ast::BinaryOp::Mul, left, .. }
+                if matches!(**left, Expression::String(_) | Expression::TemplateString { .. })
+                    || matches!(&**left, Expression::Identifier(name) if self.var_types.get(name).copied().unwrap_or(false)) =>
+            {
+                return Err("string repetition is only supported on the ELF target".to_string());
+            }
             Expression::Binary { op, left, right } => {
                 use crate::ast::BinaryOp;
                 let op_str = match op {
@@ -226,68 +312,68 @@ impl CCodeGen {
         Ok(())
     }
 
+}
 
-    pub fn compile_c_code(&self, c_code: &str, output_path: &str) -> Result<(), String> {
-        fs::create_dir_all("build").map_err(|e| e.to_string())?;
-        
-        let temp_c = "build/temp_perano.c";
-        fs::write(temp_c, c_code).map_err(|e| e.to_string())?;
+pub fn compile_c_code(c_code: &str, output_path: &str) -> Result<(), String> {
+    fs::create_dir_all("build").map_err(|e| e.to_string())?;
+    
+    let temp_c = "build/temp_perano.c";
+    fs::write(temp_c, c_code).map_err(|e| e.to_string())?;
 
-        let result = if let Ok(output) = Command::new("cl.exe")
-            .args(&["/nologo", "/O2", temp_c, &format!("/Fe:{}", output_path)])
-            .current_dir(".")
-            .output()
-        {
-            let _ = fs::remove_file("build/temp_perano.obj");
-            
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                if stderr.contains("is not recognized") || stderr.contains("cannot open") {
-                    None
-                } else {
-                    Some(Err(stderr.to_string()))
-                }
+    let result = if let Ok(output) = Command::new("cl.exe")
+        .args(&["/nologo", "/O2", temp_c, &format!("/Fe:{}", output_path)])
+        .current_dir(".")
+        .output()
+    {
+        let _ = fs::remove_file("build/temp_perano.obj");
+        
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("is not recognized") || stderr.contains("cannot open") {
+                None
             } else {
-                Some(Ok(()))
+                Some(Err(stderr.to_string()))
             }
         } else {
-            None
-        };
+            Some(Ok(()))
+        }
+    } else {
+        None
+    };
+    
+    let result = if let Some(r) = result {
+        r
+    } else if Command::new("gcc").arg("--version").output().is_ok() {
+        let output = Command::new("gcc")
+            .args(&["-O2", temp_c, "-o", output_path])
+            .output()
+            .map_err(|e| format!("Failed to run gcc: {}", e))?;
         
-        let result = if let Some(r) = result {
-            r
-        } else if Command::new("gcc").arg("--version").output().is_ok() {
-            let output = Command::new("gcc")
-                .args(&["-O2", temp_c, "-o", output_path])
-                .output()
-                .map_err(|e| format!("Failed to run gcc: {}", e))?;
-            
-            if !output.status.success() {
-                Err(String::from_utf8_lossy(&output.stderr).to_string())
-            } else {
-                Ok(())
-            }
-        } else if Command::new("wsl").arg("gcc").arg("--version").output().is_ok() {
-            let wsl_temp_c = temp_c.replace("\\", "/").replace("E:", "/mnt/e");
-            let wsl_output = output_path.replace("\\", "/").replace("E:", "/mnt/e");
-            
-            let output = Command::new("wsl")
-                .args(&["gcc", "-O2", &wsl_temp_c, "-o", &wsl_output])
-                .output()
-                .map_err(|e| format!("Failed to run WSL gcc: {}", e))?;
-            
-            if !output.status.success() {
-                Err(String::from_utf8_lossy(&output.stderr).to_string())
-            } else {
-                Ok(())
-            }
+        if !output.status.success() {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
         } else {
-            Err("No compiler found. Install Visual Studio (cl.exe), MinGW (gcc), or WSL with gcc".to_string())
-        };
-
-        if result.is_ok() {
-            println!("Compilation successful: {}", output_path);
+            Ok(())
         }
-        result
+    } else if Command::new("wsl").arg("gcc").arg("--version").output().is_ok() {
+        let wsl_temp_c = temp_c.replace("\\", "/").replace("E:", "/mnt/e");
+        let wsl_output = output_path.replace("\\", "/").replace("E:", "/mnt/e");
+        
+        let output = Command::new("wsl")
+            .args(&["gcc", "-O2", &wsl_temp_c, "-o", &wsl_output])
+            .output()
+            .map_err(|e| format!("Failed to run WSL gcc: {}", e))?;
+        
+        if !output.status.success() {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        } else {
+            Ok(())
+        }
+    } else {
+        Err("No compiler found. Install Visual Studio (cl.exe), MinGW (gcc), or WSL with gcc".to_string())
+    };
+
+    if result.is_ok() {
+        println!("Compilation successful: {}", output_path);
     }
+    result
 }
@@ -1,16 +1,29 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::error::{CompileError, ErrorKind};
+use std::collections::{HashMap, HashSet};
 
 pub struct CodeGen<'a> {
     code: Vec<u8>,
     data: Vec<u8>,
     variables: HashMap<String, i32>,
+    string_vars: HashSet<String>,
+    /// Functions declared to return `string`, as named by `Program::string_functions`.
+    string_functions: HashSet<String>,
+    /// Stack arrays in scope: name -> (length, element size).
+    arrays: HashMap<String, (usize, usize)>,
+    pointer_vars: HashSet<String>,
     stack_offset: i32,
+    /// Deepest `stack_offset` reached in the current frame, inlined calls included.
+    frame_size: i32,
     #[allow(dead_code)]
     string_literals: Vec<(usize, String)>,
     target: String,
     program: Option<&'a Program>,
     in_main: bool,
+    current_module: Option<String>,
+    source: SourceCursor,
+    entry: String,
+    error: Option<CompileError>,
 }
 
 impl<'a> CodeGen<'a> {
@@ -19,85 +32,226 @@ impl<'a> CodeGen<'a> {
             code: Vec::new(),
             data: Vec::new(),
             variables: HashMap::new(),
+            string_vars: HashSet::new(),
+            string_functions: HashSet::new(),
+            arrays: HashMap::new(),
+            pointer_vars: HashSet::new(),
             stack_offset: 0,
+            frame_size: 0,
             string_literals: Vec::new(),
             target: target.to_string(),
             program: None,
             in_main: false,
+            current_module: None,
+            source: SourceCursor::default(),
+            entry: "main".to_string(),
+            error: None,
         }
     }
 
-    pub fn generate(&mut self, program: &'a Program) -> MachineCode {
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    pub fn generate(&mut self, program: &'a Program) -> crate::error::Result<MachineCode> {
         self.program = Some(program);
+        self.string_functions = program.string_functions();
         self.in_main = true;
 
-        let main_func = program.functions.iter()
-            .find(|f| f.name == "main")
-            .expect("No main function found");
+        let main_func = program.functions.iter().find(|f| f.name == self.entry);
+        let main_body = main_func.map(|f| f.body.as_slice()).unwrap_or(&[]);
+        if let Some(func) = main_func {
+            self.source = SourceCursor::new(func);
+        }
 
-        if self.target == "elf" {
-            self.emit(&[0x55]);
-            self.emit(&[0x48, 0x89, 0xE5]);
+        self.emit(&[0x55]);
+        self.emit(&[0x48, 0x89, 0xE5]);
+        let frame_pos = self.begin_frame();
 
-            for stmt in &main_func.body {
-                self.generate_statement(stmt);
-            }
-            self.emit_exit_with_rax();
-        } else {
-            self.emit(&[0x55]);
-            self.emit(&[0x48, 0x89, 0xE5]);
-            self.emit(&[0x48, 0x83, 0xEC, 0x40]);
+        for stmt in main_body {
+            self.generate_statement(stmt);
+        }
+        self.end_frame(frame_pos);
 
-            for stmt in &main_func.body {
-                self.generate_statement(stmt);
-            }
+        self.emit_exit(0);
 
-            self.emit_exit(0);
+        if let Some(err) = self.error.take() {
+            return Err(err);
         }
 
-        MachineCode {
+        Ok(MachineCode {
             code: self.code.clone(),
             data: self.data.clone(),
             entry_point: 0,
-        }
+        })
     }
 
     #[allow(dead_code)]
     fn generate_function(&mut self, func: &Function) {
         self.emit(&[0x55]);
         self.emit(&[0x48, 0x89, 0xE5]);
-
-        self.emit(&[0x48, 0x83, 0xEC, 0x40]);
+        let frame_pos = self.begin_frame();
 
         for stmt in &func.body {
             self.generate_statement(stmt);
         }
+        self.end_frame(frame_pos);
 
         self.emit(&[0x48, 0x89, 0xEC]);
         self.emit(&[0x5D]);
         self.emit(&[0xC3]);
     }
 
+    /// Emits `sub rsp, imm32` with a placeholder size and returns where to patch it.
+    fn begin_frame(&mut self) -> usize {
+        self.stack_offset = 0;
+        self.frame_size = 0;
+        self.emit(&[0x48, 0x81, 0xEC]);
+        let pos = self.code.len();
+        self.emit_i32(0);
+        pos
+    }
+
+    fn end_frame(&mut self, pos: usize) {
+        let size = (self.frame_size + 15) & !15;
+        self.patch_i32(pos, size);
+    }
+
+    fn alloc_slot(&mut self, bytes: i32) -> i32 {
+        self.stack_offset -= bytes;
+        self.frame_size = self.frame_size.max(-self.stack_offset);
+        self.stack_offset
+    }
+
+    fn generate_block(&mut self, body: &[Statement]) {
+        let saved_vars = self.variables.clone();
+        let saved_strings = self.string_vars.clone();
+        let saved_arrays = self.arrays.clone();
+        let saved_pointers = self.pointer_vars.clone();
+        for stmt in body {
+            self.generate_statement(stmt);
+        }
+        self.variables = saved_vars;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
+    }
+
+    /// Pointers step over 8-byte elements, so `p + n` advances by `n * 8` bytes.
+    fn is_pointer_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::AddressOf { .. } => true,
+            Expression::Identifier(name) => self.pointer_vars.contains(name),
+            Expression::Binary { op: BinaryOp::Add | BinaryOp::Sub, left, right } => {
+                self.is_pointer_expression(left) != self.is_pointer_expression(right)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_string_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::String(_) | Expression::TemplateString { .. } | Expression::Slice { .. } => true,
+            Expression::Identifier(name) => self.string_vars.contains(name),
+            Expression::Call { function, .. } => match &self.current_module {
+                Some(module) if self.program.and_then(|p| p.module_function(module, function)).is_some() => {
+                    self.string_functions.contains(&format!("{}_{}", module, function))
+                }
+                _ => self.string_functions.contains(function),
+            },
+            Expression::ModuleCall { module, function, .. } => {
+                self.string_functions.contains(&format!("{}_{}", module, function))
+            }
+            _ => false,
+        }
+    }
+
+    fn is_byte_array(&self, name: &str) -> bool {
+        matches!(self.arrays.get(name), Some(&(_, 1)))
+    }
+
+    /// Turns a negative index in rax into one counted from the end of the array.
+    fn wrap_array_index(&mut self, name: &str) {
+        if let Some(&(length, _)) = self.arrays.get(name) {
+            self.emit(&[0x48, 0x8D, 0x88]);
+            self.emit_i32(length as i32);
+            self.emit(&[0x48, 0x85, 0xC0]);
+            self.emit(&[0x48, 0x0F, 0x48, 0xC1]);
+        }
+    }
+
     fn generate_statement(&mut self, stmt: &Statement) {
+        self.source.advance();
         match stmt {
-            Statement::VarDecl { name, var_type: _, value } => {
-                if let Some(expr) = value {
-                    self.generate_expression(expr);
-                    self.stack_offset -= 8;
+            Statement::VarDecl { name, var_type, value } => {
+                let is_string = var_type.as_deref() == Some("string")
+                    || value.as_ref().is_some_and(|v| self.is_string_expression(v));
+                if is_string {
+                    self.string_vars.insert(name.clone());
+                } else {
+                    self.string_vars.remove(name);
+                }
+                self.arrays.remove(name);
+                let is_pointer = var_type.as_deref().is_some_and(|t| t.starts_with('*'))
+                    || value.as_ref().is_some_and(|v| self.is_pointer_expression(v));
+                if is_pointer {
+                    self.pointer_vars.insert(name.clone());
+                } else {
+                    self.pointer_vars.remove(name);
+                }
+
+                match value {
+                    Some(expr) => self.generate_expression(expr),
+                    None => self.emit(&[0x31, 0xC0]),
+                }
+                self.alloc_slot(8);
+                self.variables.insert(name.clone(), self.stack_offset);
+                self.emit(&[0x48, 0x89, 0x85]);
+                self.emit_i32(self.stack_offset);
+            }
+            Statement::MultiVarDecl { names, value } => {
+                self.generate_expression(value);
+                for (name, modrm) in names.iter().zip([0x85, 0x95]) {
+                    self.alloc_slot(8);
                     self.variables.insert(name.clone(), self.stack_offset);
-                    self.emit(&[0x48, 0x89, 0x85]);
+                    self.emit(&[0x48, 0x89, modrm]);
                     self.emit_i32(self.stack_offset);
                 }
             }
-            Statement::ArrayDecl { name, element_type: _, size } => {
-                let array_size = (*size as i32) * 8;
-                self.stack_offset -= array_size;
+            Statement::ArrayDecl { name, element_type, size } => {
+                let element_size = array_element_size(element_type);
+                let words = (size * element_size).div_ceil(8);
+                self.alloc_slot(words as i32 * 8);
                 self.variables.insert(name.clone(), self.stack_offset);
-                for i in 0..*size {
-                    let offset = self.stack_offset + (i as i32 * 8);
-                    self.emit(&[0x48, 0xC7, 0x85]);
-                    self.emit_i32(offset);
-                    self.emit_i32(0);
+                self.arrays.insert(name.clone(), (*size, element_size));
+                self.pointer_vars.remove(name);
+                if words <= 2 {
+                    for i in 0..words {
+                        let offset = self.stack_offset + (i as i32 * 8);
+                        self.emit(&[0x48, 0xC7, 0x85]);
+                        self.emit_i32(offset);
+                        self.emit_i32(0);
+                    }
+                } else {
+                    self.emit(&[0x48, 0x8D, 0xBD]);
+                    self.emit_i32(self.stack_offset);
+                    self.emit(&[0xB9]);
+                    self.emit_i32(words as i32);
+                    self.emit(&[0x31, 0xC0]);
+                    self.emit(&[0xF3, 0x48, 0xAB]);
                 }
             }
             Statement::ArrayAssignment { name, index, value } => {
@@ -105,9 +259,21 @@ impl<'a> CodeGen<'a> {
                 self.emit(&[0x50]);
 
                 self.generate_expression(index);
+                self.wrap_array_index(name);
 
-                if let Some(&base_offset) = self.variables.get(name) {
-                    self.emit(&[0x48, 0x6B, 0xC0, 0x08]);
+                if self.pointer_vars.contains(name) {
+                    if let Some(&offset) = self.variables.get(name) {
+                        self.emit(&[0x48, 0x8B, 0x8D]);
+                        self.emit_i32(offset);
+                        self.emit(&[0x48, 0x8D, 0x04, 0xC1]);
+                        self.emit(&[0x59]);
+                        self.emit(&[0x48, 0x89, 0x08]);
+                    }
+                } else if let Some(&base_offset) = self.variables.get(name) {
+                    let is_bytes = self.is_byte_array(name);
+                    if !is_bytes {
+                        self.emit(&[0x48, 0x6B, 0xC0, 0x08]);
+                    }
                     if base_offset >= -128 && base_offset < 128 {
                         self.emit(&[0x48, 0x83, 0xC0, (base_offset as u8)]);
                     } else {
@@ -117,7 +283,11 @@ impl<'a> CodeGen<'a> {
                     self.emit(&[0x48, 0x01, 0xE8]);
 
                     self.emit(&[0x59]);
-                    self.emit(&[0x48, 0x89, 0x08]);
+                    if is_bytes {
+                        self.emit(&[0x88, 0x08]);
+                    } else {
+                        self.emit(&[0x48, 0x89, 0x08]);
+                    }
                 }
             }
             Statement::Assignment { name, value } => {
@@ -148,7 +318,7 @@ impl<'a> CodeGen<'a> {
                         self.emit_exit_with_rax();
                     } else {
                         self.emit(&[0x89, 0xC1]);
-                        self.emit(&[0x48, 0x83, 0xEC, 0x20]);
+                        self.emit_win_frame_enter(0x20);
                         self.emit(&[0xFF, 0x15]);
                         self.emit_i32(0x10000000u32 as i32);
                     }
@@ -158,7 +328,9 @@ impl<'a> CodeGen<'a> {
                 self.generate_expression(expr);
             }
             Statement::InlineAsm { .. } => {
+                self.fail("inline asm is not supported by the PE machine-code backend (it has no assembler)".to_string());
             }
+            Statement::Block(body) => self.generate_block(body),
             Statement::If { condition, then_body, else_body } => {
                 self.generate_expression(condition);
 
@@ -168,9 +340,7 @@ impl<'a> CodeGen<'a> {
                 let else_jump_pos = self.code.len();
                 self.emit_i32(0);
 
-                for stmt in then_body {
-                    self.generate_statement(stmt);
-                }
+                self.generate_block(then_body);
 
                 self.emit(&[0xE9]);
                 let end_jump_pos = self.code.len();
@@ -181,45 +351,51 @@ impl<'a> CodeGen<'a> {
                 self.patch_i32(else_jump_pos, else_offset);
 
                 if let Some(body) = else_body {
-                    for stmt in body {
-                        self.generate_statement(stmt);
-                    }
+                    self.generate_block(body);
                 }
 
                 let end_label = self.code.len();
                 let end_offset = (end_label as i32) - (end_jump_pos as i32) - 4;
                 self.patch_i32(end_jump_pos, end_offset);
             }
-            Statement::For { init: _, condition, post: _, body } => {
+            Statement::For { init, condition, post, body } => {
+                let saved_vars = self.variables.clone();
+                let saved_strings = self.string_vars.clone();
+                let saved_arrays = self.arrays.clone();
+                let saved_pointers = self.pointer_vars.clone();
+                if let Some(init) = init {
+                    self.generate_statement(init);
+                }
+
                 let loop_start = self.code.len();
 
-                if let Some(cond) = condition {
+                let end_jump_pos = condition.as_ref().map(|cond| {
                     self.generate_expression(cond);
                     self.emit(&[0x48, 0x85, 0xC0]);
                     self.emit(&[0x0F, 0x84]);
                     let end_jump_pos = self.code.len();
                     self.emit_i32(0);
+                    end_jump_pos
+                });
 
-                    for stmt in body {
-                        self.generate_statement(stmt);
-                    }
+                self.generate_block(body);
+                if let Some(post) = post {
+                    self.generate_statement(post);
+                }
 
-                    self.emit(&[0xE9]);
-                    let back_offset = (loop_start as i32) - (self.code.len() as i32) - 4;
-                    self.emit_i32(back_offset);
+                self.emit(&[0xE9]);
+                let back_offset = (loop_start as i32) - (self.code.len() as i32) - 4;
+                self.emit_i32(back_offset);
 
+                if let Some(end_jump_pos) = end_jump_pos {
                     let end_label = self.code.len();
                     let end_offset = (end_label as i32) - (end_jump_pos as i32) - 4;
                     self.patch_i32(end_jump_pos, end_offset);
-                } else {
-                    for stmt in body {
-                        self.generate_statement(stmt);
-                    }
-
-                    self.emit(&[0xE9]);
-                    let back_offset = (loop_start as i32) - (self.code.len() as i32) - 4;
-                    self.emit_i32(back_offset);
                 }
+                self.variables = saved_vars;
+                self.string_vars = saved_strings;
+                self.arrays = saved_arrays;
+                self.pointer_vars = saved_pointers;
             }
         }
     }
@@ -236,6 +412,77 @@ impl<'a> CodeGen<'a> {
                     self.emit_i32(offset);
                 }
             }
+            Expression::Binary { op: op @ (BinaryOp::Div | BinaryOp::Mod), left, right }
+                if right.power_of_two_shift().is_some() =>
+            {
+                let shift = right.power_of_two_shift().unwrap_or(1) as u8;
+                self.generate_expression(left);
+                self.emit(&[0x48, 0x89, 0xC2]);
+                self.emit(&[0x48, 0xC1, 0xFA, 0x3F]);
+                self.emit(&[0x48, 0xC1, 0xEA, 64 - shift]);
+                self.emit(&[0x48, 0x01, 0xD0]);
+                if *op == BinaryOp::Div {
+                    self.emit(&[0x48, 0xC1, 0xF8, shift]);
+                } else {
+                    self.emit(&[0x48, 0x25]);
+                    self.emit_i32(((1i64 << shift) - 1) as i32);
+                    self.emit(&[0x48, 0x29, 0xD0]);
+                }
+            }
+            Expression::Binary { op: op @ (BinaryOp::Add | BinaryOp::Sub), left, right }
+                if self.is_pointer_expression(left) || self.is_pointer_expression(right) =>
+            {
+                let (pointer, offset) = if self.is_pointer_expression(left) { (left, right) } else { (right, left) };
+                let pointer_difference = self.is_pointer_expression(offset);
+                self.generate_expression(offset);
+                if !pointer_difference {
+                    self.emit(&[0x48, 0xC1, 0xE0, 0x03]);
+                }
+                self.emit(&[0x50]);
+                self.generate_expression(pointer);
+                self.emit(&[0x59]);
+                if *op == BinaryOp::Add {
+                    self.emit(&[0x48, 0x01, 0xC8]);
+                } else {
+                    self.emit(&[0x48, 0x29, 0xC8]);
+                }
+                if pointer_difference {
+                    self.emit(&[0x48, 0xC1, 0xF8, 0x03]);
+                }
+            }
+            Expression::Binary { op: BinaryOp::Mul, left, .. } if self.is_string_expression(left) => {
+                self.fail("string repetition is only supported on the ELF target".to_string());
+            }
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                self.generate_expression(left);
+                self.emit(&[0x48, 0x85, 0xC0]);
+                if *op == BinaryOp::And {
+                    self.emit(&[0x0F, 0x84]);
+                } else {
+                    self.emit(&[0x0F, 0x85]);
+                }
+                let short_jump_pos = self.code.len();
+                self.emit_i32(0);
+
+                self.generate_expression(right);
+                self.emit(&[0x48, 0x85, 0xC0]);
+                self.emit(&[0x0F, 0x95, 0xC0]);
+                self.emit(&[0x48, 0x0F, 0xB6, 0xC0]);
+                self.emit(&[0xE9]);
+                let end_jump_pos = self.code.len();
+                self.emit_i32(0);
+
+                let short_label = self.code.len();
+                self.patch_i32(short_jump_pos, (short_label as i32) - (short_jump_pos as i32) - 4);
+                if *op == BinaryOp::And {
+                    self.emit(&[0x31, 0xC0]);
+                } else {
+                    self.emit(&[0xB8, 0x01, 0x00, 0x00, 0x00]);
+                }
+
+                let end_label = self.code.len();
+                self.patch_i32(end_jump_pos, (end_label as i32) - (end_jump_pos as i32) - 4);
+            }
             Expression::Binary { op, left, right } => {
                 self.generate_expression(right);
                 self.emit(&[0x50]);
@@ -254,7 +501,7 @@ impl<'a> CodeGen<'a> {
                         self.emit(&[0x48, 0x0F, 0xAF, 0xC1]);
                     }
                     BinaryOp::Div => {
-                        self.emit(&[0x48, 0x31, 0xD2]);
+                        self.emit(&[0x48, 0x99]);
                         self.emit(&[0x48, 0xF7, 0xF9]);
                     }
                     BinaryOp::Equal => {
@@ -288,7 +535,7 @@ impl<'a> CodeGen<'a> {
                         self.emit(&[0x48, 0x0F, 0xB6, 0xC0]);
                     }
                     BinaryOp::Mod => {
-                        self.emit(&[0x48, 0x31, 0xD2]);
+                        self.emit(&[0x48, 0x99]);
                         self.emit(&[0x48, 0xF7, 0xF9]);
                         self.emit(&[0x48, 0x89, 0xD0]);
                     }
@@ -310,11 +557,24 @@ impl<'a> CodeGen<'a> {
                     }
                 }
             }
+            Expression::ArrayAccess { name, index } if self.string_vars.contains(name) => {
+                self.generate_string_index(&Expression::Identifier(name.clone()), index);
+            }
             Expression::ArrayAccess { name, index } => {
                 self.generate_expression(index);
+                self.wrap_array_index(name);
 
-                if let Some(&base_offset) = self.variables.get(name) {
-                    self.emit(&[0x48, 0x6B, 0xC0, 0x08]);
+                if self.pointer_vars.contains(name) {
+                    if let Some(&offset) = self.variables.get(name) {
+                        self.emit(&[0x48, 0x8B, 0x8D]);
+                        self.emit_i32(offset);
+                        self.emit(&[0x48, 0x8B, 0x04, 0xC1]);
+                    }
+                } else if let Some(&base_offset) = self.variables.get(name) {
+                    let is_bytes = self.is_byte_array(name);
+                    if !is_bytes {
+                        self.emit(&[0x48, 0x6B, 0xC0, 0x08]);
+                    }
                     if base_offset >= -128 && base_offset < 128 {
                         self.emit(&[0x48, 0x83, 0xC0, (base_offset as u8)]);
                     } else {
@@ -323,7 +583,11 @@ impl<'a> CodeGen<'a> {
                     }
                     self.emit(&[0x48, 0x01, 0xE8]);
 
-                    self.emit(&[0x48, 0x8B, 0x00]);
+                    if is_bytes {
+                        self.emit(&[0x48, 0x0F, 0xB6, 0x00]);
+                    } else {
+                        self.emit(&[0x48, 0x8B, 0x00]);
+                    }
                 }
             }
             Expression::Call { function, args } => {
@@ -348,6 +612,43 @@ impl<'a> CodeGen<'a> {
                     } else {
                         self.emit(&[0x48, 0x31, 0xC0]);
                     }
+                } else if self.is_builtin(function, "itoa", args, 1) {
+                    self.fail("itoa is not supported on the PE target: it has no heap to hold the digits".to_string());
+                } else if self.is_builtin(function, "parseInt", args, 1) {
+                    self.generate_expression(&args[0]);
+                    self.emit(&[0x48, 0x89, 0xC6]);
+                    self.emit_parse_int();
+                } else if self.is_builtin(function, "min", args, 2) || self.is_builtin(function, "max", args, 2) {
+                    self.generate_expression(&args[0]);
+                    self.emit(&[0x50]);
+                    self.generate_expression(&args[1]);
+                    self.emit(&[0x48, 0x89, 0xC1]);
+                    self.emit(&[0x58]);
+                    self.emit(&[0x48, 0x39, 0xC8]);
+                    let cmov = if function == "min" { 0x4F } else { 0x4C };
+                    self.emit(&[0x48, 0x0F, cmov, 0xC1]);
+                } else if self.is_builtin(function, "abs", args, 1) {
+                    self.generate_expression(&args[0]);
+                    self.emit(&[0x48, 0x99]);
+                    self.emit(&[0x48, 0x31, 0xD0]);
+                    self.emit(&[0x48, 0x29, 0xD0]);
+                } else if self.is_builtin(function, "assert", args, 1) {
+                    self.generate_expression(&args[0]);
+                    self.emit(&[0x48, 0x85, 0xC0]);
+                    self.emit(&[0x0F, 0x85]);
+                    let ok_jump_pos = self.code.len();
+                    self.emit_i32(0);
+                    self.emit_line(&format!("{}: assertion failed: {}", self.source.location(), args[0]), true);
+                    self.emit_exit(1);
+                    let ok_offset = (self.code.len() as i32) - (ok_jump_pos as i32) - 4;
+                    self.patch_i32(ok_jump_pos, ok_offset);
+                } else if self.is_builtin(function, "panic", args, 1) {
+                    if let Expression::String(message) = &args[0] {
+                        self.emit_line(&format!("panic: {}", message), true);
+                        self.emit_exit(1);
+                    } else {
+                        self.fail("panic on the PE target needs a string literal message".to_string());
+                    }
                 } else if function == "concat" && args.len() == 2 {
                     self.emit(&[0x48, 0x31, 0xC0]);
                 } else if function == "compare" && args.len() == 2 {
@@ -362,6 +663,10 @@ impl<'a> CodeGen<'a> {
                     } else {
                         self.emit(&[0x48, 0x31, 0xC0]);
                     }
+                } else if let Some(module) = self.current_module.clone()
+                    .filter(|m| self.program.and_then(|p| p.module_function(m, function)).is_some())
+                {
+                    self.generate_module_call(&module, function, args);
                 } else {
                     self.generate_iperine_call(function, args);
                 }
@@ -370,18 +675,27 @@ impl<'a> CodeGen<'a> {
                 self.generate_module_call(module, function, args);
             }
             Expression::StringIndex { string, index } => {
-                if let Expression::String(_s) = string.as_ref() {
-                    self.generate_expression(index);
-                }
+                self.generate_string_index(string, index);
             }
             Expression::AddressOf { operand } => {
                 if let Expression::Identifier(name) = operand.as_ref() {
                     if let Some(&offset) = self.variables.get(name) {
                         self.emit(&[0x48, 0x8D, 0x85]);
                         self.emit_i32(offset);
+                    } else {
+                        self.fail(format!(
+                            "cannot take the address of '{}' on the PE target: functions are inlined and have no address",
+                            name
+                        ));
                     }
                 }
             }
+            Expression::Slice { .. } => {
+                self.fail("string slices are not supported on the PE target".to_string());
+            }
+            Expression::IndirectCall { .. } => {
+                self.fail("indirect calls are not supported on the PE target: functions are inlined and have no address".to_string());
+            }
             Expression::Deref { operand } => {
                 self.generate_expression(operand);
                 self.emit(&[0x48, 0x8B, 0x00]);
@@ -393,8 +707,7 @@ impl<'a> CodeGen<'a> {
                 if s.contains("$(") {
                     self.generate_string_interpolation(s);
                 } else {
-                    self.emit(&[0xEB]);
-                    self.emit(&[(s.len() + 1) as u8]);
+                    self.emit_skip(s.len() + 1);
                     let addr = self.code.len();
                     self.code.extend_from_slice(s.as_bytes());
                     self.code.push(0);
@@ -404,7 +717,19 @@ impl<'a> CodeGen<'a> {
                     self.emit_i32(offset);
                 }
             }
-            _ => {}
+            Expression::Tuple(values) => {
+                if let [first, second] = values.as_slice() {
+                    self.generate_expression(second);
+                    self.emit(&[0x50]);
+                    self.generate_expression(first);
+                    self.emit(&[0x5A]);
+                } else if let Some(first) = values.first() {
+                    self.generate_expression(first);
+                }
+            }
+            Expression::TemplateString { .. } => {
+                self.fail("template strings can only be printed on the PE target".to_string());
+            }
         }
     }
     
@@ -447,7 +772,7 @@ impl<'a> CodeGen<'a> {
     }
     
     fn copy_literal_to_buffer(&mut self, lit: &str) {
-        self.emit(&[0xEB, (lit.len() + 1) as u8]);
+        self.emit_skip(lit.len() + 1);
         let addr = self.code.len();
         self.code.extend_from_slice(lit.as_bytes());
         self.code.push(0);
@@ -464,8 +789,7 @@ impl<'a> CodeGen<'a> {
         self.emit(&[0x41, 0x88, 0x04, 0x24]);
         self.emit(&[0x49, 0xFF, 0xC4]);
         self.emit(&[0x48, 0xFF, 0xC6]);
-        let back = (loop_start as i32) - (self.code.len() as i32) - 2;
-        self.emit(&[0xEB, (back as u8)]);
+        self.emit_jump_back(0xEB, loop_start);
     }
     
     fn copy_variable_to_buffer(&mut self, var_name: &str) {
@@ -493,8 +817,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0xFF, 0xCF]);
             self.emit(&[0x48, 0x89, 0xC3]);
             self.emit(&[0x48, 0x85, 0xC0]);
-            let back = (digit_loop as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0x75, ((-back) as u8)]);
+            self.emit_jump_back(0x75, digit_loop);
             
             self.emit(&[0x48, 0xFF, 0xC7]);
             let copy_loop = self.code.len();
@@ -504,13 +827,31 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x41, 0x88, 0x04, 0x24]);
             self.emit(&[0x49, 0xFF, 0xC4]);
             self.emit(&[0x48, 0xFF, 0xC7]);
-            let back2 = (copy_loop as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0xEB, (back2 as u8)]);
+            self.emit_jump_back(0xEB, copy_loop);
             
             self.emit(&[0x48, 0x83, 0xC4, 0x20]);
         }
     }
 
+    /// Aligns `rsp` to 16 bytes and reserves at least `size` bytes (including the
+    /// 32-byte shadow space) for a Win64 call. Must be paired with
+    /// `emit_win_frame_leave(size)` unless the callee never returns.
+    fn emit_win_frame_enter(&mut self, size: u8) {
+        self.emit(&[0x54]);
+        self.emit(&[0xFF, 0x34, 0x24]);
+        self.emit(&[0x48, 0x83, 0xE4, 0xF0]);
+        self.emit(&[0x48, 0x83, 0xEC, Self::win_frame_size(size)]);
+    }
+
+    fn emit_win_frame_leave(&mut self, size: u8) {
+        self.emit(&[0x48, 0x83, 0xC4, Self::win_frame_size(size)]);
+        self.emit(&[0x48, 0x8B, 0x64, 0x24, 0x08]);
+    }
+
+    fn win_frame_size(size: u8) -> u8 {
+        (size.max(0x20) + 15) & !15
+    }
+
     fn emit(&mut self, bytes: &[u8]) {
         self.code.extend_from_slice(bytes);
     }
@@ -528,19 +869,58 @@ impl<'a> CodeGen<'a> {
         self.code[pos..pos + 4].copy_from_slice(&bytes);
     }
 
+    /// Points the short jump whose displacement byte is at `pos` to the current position.
+    fn patch_i8(&mut self, pos: usize) {
+        let distance = self.code.len() - pos - 1;
+        match i8::try_from(distance) {
+            Ok(rel8) => self.code[pos] = rel8 as u8,
+            Err(_) => self.fail(format!("short jump of {} bytes is out of range", distance)),
+        }
+    }
+
+    /// Jumps to the already emitted `target` with the short jump `opcode` (`EB`, or a `7x`
+    /// conditional), widening it to the rel32 form when the distance does not fit in a byte.
+    fn emit_jump_back(&mut self, opcode: u8, target: usize) {
+        let short = target as i64 - (self.code.len() as i64 + 2);
+        if let Ok(rel8) = i8::try_from(short) {
+            self.emit(&[opcode, rel8 as u8]);
+            return;
+        }
+        if opcode == 0xEB {
+            self.emit(&[0xE9]);
+        } else {
+            self.emit(&[0x0F, opcode + 0x10]);
+        }
+        self.emit_i32((target as i64 - (self.code.len() as i64 + 4)) as i32);
+    }
+
+    /// Jumps over the `len` bytes of inline data emitted next.
+    fn emit_skip(&mut self, len: usize) {
+        match i8::try_from(len) {
+            Ok(rel8) => self.emit(&[0xEB, rel8 as u8]),
+            Err(_) => {
+                self.emit(&[0xE9]);
+                self.emit_i32(len as i32);
+            }
+        }
+    }
+
     fn emit_println(&mut self, text: &str) {
+        self.emit_line(text, false);
+    }
+
+    fn emit_line(&mut self, text: &str, stderr: bool) {
         if self.target == "elf" {
             let str_len = text.len() + 1;
 
-            self.emit(&[0xEB]);
-            self.emit(&[(str_len as u8) & 0xFF]);
+            self.emit_skip(str_len);
 
             let string_addr = self.code.len();
             self.code.extend_from_slice(text.as_bytes());
             self.code.push(b'\n');
 
             self.emit(&[0x48, 0xC7, 0xC0, 0x01, 0x00, 0x00, 0x00]);
-            self.emit(&[0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00]);
+            self.emit(&[0x48, 0xC7, 0xC7, if stderr { 0x02 } else { 0x01 }, 0x00, 0x00, 0x00]);
 
             let lea_instr_pos = self.code.len() + 7;
             let offset = (string_addr as i32) - (lea_instr_pos as i32);
@@ -554,16 +934,15 @@ impl<'a> CodeGen<'a> {
         } else {
             let str_len = text.len() + 1;
 
-            self.emit(&[0x48, 0x83, 0xEC, 0x38]);
+            self.emit_win_frame_enter(0x38);
 
-            self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
+            self.emit(&[0xB9, if stderr { 0xF4 } else { 0xF5 }, 0xFF, 0xFF, 0xFF]);
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20000000u32 as i32);
 
             self.emit(&[0x48, 0x89, 0xC3]);
 
-            self.emit(&[0xEB]);
-            self.emit(&[(str_len as u8) & 0xFF]);
+            self.emit_skip(str_len);
 
             let string_addr = self.code.len();
             self.code.extend_from_slice(text.as_bytes());
@@ -586,7 +965,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20080000u32 as i32);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x38]);
+            self.emit_win_frame_leave(0x38);
         }
     }
 
@@ -597,7 +976,7 @@ impl<'a> CodeGen<'a> {
             self.emit_i32(code);
             self.emit(&[0x0F, 0x05]);
         } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x20]);
+            self.emit_win_frame_enter(0x20);
 
             self.emit(&[0xB9]);
             self.emit_i32(code);
@@ -614,22 +993,31 @@ impl<'a> CodeGen<'a> {
     }
 
     fn emit_println_int(&mut self) {
+        self.emit_write_int(true);
+    }
 
+    fn emit_write_int(&mut self, newline: bool) {
         if self.target == "elf" {
             self.emit(&[0x48, 0x83, 0xEC, 0x20]);
-            self.emit(&[0x48, 0x8D, 0x7C, 0x24, 0x1E]);
-            self.emit(&[0xC6, 0x07, 0x0A]);
-            self.emit(&[0x48, 0xFF, 0xCF]);
+            self.emit(&[0x48, 0x8D, 0x7C, 0x24, 0x1F]);
+            if newline {
+                self.emit(&[0xC6, 0x07, 0x0A]);
+                self.emit(&[0x48, 0xFF, 0xCF]);
+            }
 
             self.emit(&[0x48, 0x89, 0xC3]);
             self.emit(&[0x48, 0x85, 0xC0]);
-            self.emit(&[0x75, 0x05]);
+            self.emit(&[0x75, 0x00]);
+            let not_zero_patch = self.code.len() - 1;
             self.emit(&[0xC6, 0x07, 0x30]);
-            self.emit(&[0xEB, 0x29]);
+            self.emit(&[0x48, 0xFF, 0xCF]);
+            self.emit(&[0xEB, 0x00]);
+            let done_patch = self.code.len() - 1;
+            self.patch_i8(not_zero_patch);
 
             self.emit(&[0x48, 0x31, 0xC9]);
             self.emit(&[0x48, 0x85, 0xDB]);
-            self.emit(&[0x79, 0x0F]);
+            self.emit(&[0x79, 0x10]);
             self.emit(&[0x48, 0x89, 0xDA]);
             self.emit(&[0x48, 0xC1, 0xFA, 0x3F]);
             self.emit(&[0x48, 0x31, 0xD3]);
@@ -647,13 +1035,13 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0xFF, 0xCF]);
             self.emit(&[0x48, 0x89, 0xC3]);
             self.emit(&[0x48, 0x85, 0xC0]);
-            let back = (loop_start as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0x75, (back as u8)]);
+            self.emit_jump_back(0x75, loop_start);
 
             self.emit(&[0x48, 0x85, 0xC9]);
-            self.emit(&[0x74, 0x03]);
+            self.emit(&[0x74, 0x06]);
             self.emit(&[0xC6, 0x07, 0x2D]);
             self.emit(&[0x48, 0xFF, 0xCF]);
+            self.patch_i8(done_patch);
 
             self.emit(&[0x48, 0xFF, 0xC7]);
             self.emit(&[0x48, 0x8D, 0x74, 0x24, 0x20]);
@@ -665,11 +1053,13 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x0F, 0x05]);
             self.emit(&[0x48, 0x83, 0xC4, 0x20]);
         } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x60]);
+            self.emit_win_frame_enter(0x60);
 
-            self.emit(&[0x48, 0x8D, 0x4C, 0x24, 0x5E]);
-            self.emit(&[0xC6, 0x01, 0x0A]);
-            self.emit(&[0x48, 0xFF, 0xC9]);
+            self.emit(&[0x48, 0x8D, 0x4C, 0x24, 0x5F]);
+            if newline {
+                self.emit(&[0xC6, 0x01, 0x0A]);
+                self.emit(&[0x48, 0xFF, 0xC9]);
+            }
 
             self.emit(&[0x48, 0x85, 0xC0]);
             self.emit(&[0x0F, 0x85]);
@@ -677,6 +1067,7 @@ impl<'a> CodeGen<'a> {
             self.emit_i32(0);
 
             self.emit(&[0xC6, 0x01, 0x30]);
+            self.emit(&[0x48, 0xFF, 0xC9]);
             self.emit(&[0xE9]);
             let done_patch1 = self.code.len();
             self.emit_i32(0);
@@ -689,7 +1080,6 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0x31, 0xD0]);
             self.emit(&[0x48, 0x29, 0xD0]);
 
-            self.emit(&[0x4C, 0x89, 0xD3]);
             self.emit(&[0x49, 0x89, 0xD3]);
 
             self.emit(&[0x41, 0xB8, 0x0A, 0x00, 0x00, 0x00]);
@@ -700,15 +1090,13 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x88, 0x11]);
             self.emit(&[0x48, 0xFF, 0xC9]);
             self.emit(&[0x48, 0x85, 0xC0]);
-            let loop_back = (loop_pos as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0x75, (loop_back as u8)]);
+            self.emit_jump_back(0x75, loop_pos);
 
             self.emit(&[0x4D, 0x85, 0xDB]);
-            self.emit(&[0x79, 0x03]);
+            self.emit(&[0x79, 0x06]);
             self.emit(&[0xC6, 0x01, 0x2D]);
             self.emit(&[0x48, 0xFF, 0xC9]);
 
-            self.emit(&[0x4C, 0x89, 0xDA]);
             let done_pos = self.code.len();
             self.patch_i32(done_patch1, (done_pos as i32) - (done_patch1 as i32) - 4);
 
@@ -732,172 +1120,149 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20080000u32 as i32);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x60]);
+            self.emit_win_frame_leave(0x60);
+        }
+    }
+
+    fn is_builtin(&self, function: &str, builtin: &str, args: &[Expression], arity: usize) -> bool {
+        function == builtin
+            && args.len() == arity
+            && !self.program.map(|p| p.functions.iter().any(|f| f.name == function)).unwrap_or(false)
+    }
+
+    fn generate_string_index(&mut self, string: &Expression, index: &Expression) {
+        self.generate_expression(string);
+        self.emit(&[0x50]);
+        self.generate_expression(index);
+        self.emit(&[0x59]);
+        self.emit(&[0x48, 0x01, 0xC1]);
+        self.emit(&[0x48, 0x0F, 0xB6, 0x01]);
+    }
+
+    /// Evaluates all arguments left to right into fresh slots before any parameter
+    /// name is bound, so an argument never sees the callee's parameters.
+    fn bind_inline_params(&mut self, params: &[Parameter], args: &[Expression]) {
+        let mut slots = Vec::new();
+        for arg in args.iter().take(params.len()) {
+            self.generate_expression(arg);
+            self.alloc_slot(8);
+            self.emit(&[0x48, 0x89, 0x85]);
+            self.emit_i32(self.stack_offset);
+            slots.push(self.stack_offset);
+        }
+
+        for (param, offset) in params.iter().zip(slots) {
+            self.variables.insert(param.name.clone(), offset);
+            if param.param_type == "string" {
+                self.string_vars.insert(param.name.clone());
+            } else {
+                self.string_vars.remove(&param.name);
+            }
+            if param.param_type.starts_with('*') {
+                self.pointer_vars.insert(param.name.clone());
+            }
         }
     }
 
     fn generate_iperine_call(&mut self, function: &str, args: &[Expression]) {
         let saved_vars = self.variables.clone();
+        let saved_strings = self.string_vars.clone();
+        let saved_arrays = std::mem::take(&mut self.arrays);
+        let saved_pointers = std::mem::take(&mut self.pointer_vars);
         let saved_offset = self.stack_offset;
         let saved_in_main = self.in_main;
         self.in_main = false;
+        let saved_module = self.current_module.clone();
 
         if let Some(prog) = self.program {
             if let Some(func) = prog.functions.iter().find(|f| f.name == function) {
-                for (i, arg) in args.iter().enumerate() {
-                    if i < func.params.len() {
-                        self.generate_expression(arg);
-                        self.stack_offset -= 8;
-                        self.variables.insert(func.params[i].name.clone(), self.stack_offset);
-                        self.emit(&[0x48, 0x89, 0x85]);
-                        self.emit_i32(self.stack_offset);
-                    }
-                }
+                self.bind_inline_params(&func.params, args);
 
+                self.current_module = None;
+                let saved_source = std::mem::replace(&mut self.source, SourceCursor::new(func));
                 for stmt in &func.body {
                     self.generate_statement(stmt);
                 }
+                self.source = saved_source;
             }
         }
 
         self.variables = saved_vars;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
         self.stack_offset = saved_offset;
         self.in_main = saved_in_main;
+        self.current_module = saved_module;
     }
 
-    fn generate_stdio_println(&mut self, value: &Expression) {
-        self.generate_expression(value);
-        self.emit_println_int();
+    /// Prints `value` as text when it is a string and in decimal otherwise, the way ELF's
+    /// `Print`/`Println` choose between `%s` and `%ld`.
+    fn generate_print_value(&mut self, value: &Expression, newline: bool) {
+        match value {
+            Expression::String(s) if newline => self.emit_println(s),
+            Expression::String(s) => self.emit_print_str(s),
+            Expression::TemplateString { parts } => {
+                for part in parts {
+                    match part {
+                        TemplateStringPart::Literal(text) if !text.is_empty() => self.emit_print_str(text),
+                        TemplateStringPart::Literal(_) => {}
+                        TemplateStringPart::Expression { expr, format } => {
+                            if format.is_some() {
+                                self.fail("template format specs are not supported on the PE target".to_string());
+                            }
+                            self.generate_print_value(expr, false);
+                        }
+                    }
+                }
+                if newline {
+                    self.emit_print_str("\n");
+                }
+            }
+            _ if self.is_string_expression(value) => {
+                self.generate_expression(value);
+                self.emit_print_cstr();
+                if newline {
+                    self.emit_print_str("\n");
+                }
+            }
+            _ => {
+                self.generate_expression(value);
+                self.emit_write_int(newline);
+            }
+        }
     }
 
-    fn emit_print_int(&mut self) {
-        if self.target == "elf" {
-            self.emit(&[0x48, 0x83, 0xEC, 0x20]);
-            self.emit(&[0x48, 0x8D, 0x7C, 0x24, 0x1E]);
-            self.emit(&[0xC6, 0x07, 0x00]);
-            self.emit(&[0x48, 0xFF, 0xCF]);
-
-            self.emit(&[0x48, 0x89, 0xC3]);
-            self.emit(&[0x48, 0x85, 0xC0]);
-            self.emit(&[0x75, 0x05]);
-            self.emit(&[0xC6, 0x07, 0x30]);
-            self.emit(&[0xEB, 0x29]);
-
-            self.emit(&[0x48, 0x31, 0xC9]);
-            self.emit(&[0x48, 0x85, 0xDB]);
-            self.emit(&[0x79, 0x0F]);
-            self.emit(&[0x48, 0x89, 0xDA]);
-            self.emit(&[0x48, 0xC1, 0xFA, 0x3F]);
-            self.emit(&[0x48, 0x31, 0xD3]);
-            self.emit(&[0x48, 0x29, 0xD3]);
-            self.emit(&[0x48, 0xFF, 0xC1]);
-
-            self.emit(&[0x41, 0xB8, 0x0A, 0x00, 0x00, 0x00]);
-
-            let loop_start = self.code.len();
-            self.emit(&[0x48, 0x89, 0xD8]);
-            self.emit(&[0x48, 0x31, 0xD2]);
-            self.emit(&[0x49, 0xF7, 0xF0]);
-            self.emit(&[0x80, 0xC2, 0x30]);
-            self.emit(&[0x88, 0x17]);
-            self.emit(&[0x48, 0xFF, 0xCF]);
-            self.emit(&[0x48, 0x89, 0xC3]);
-            self.emit(&[0x48, 0x85, 0xC0]);
-            let back = (loop_start as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0x75, (back as u8)]);
-
-            self.emit(&[0x48, 0x85, 0xC9]);
-            self.emit(&[0x74, 0x03]);
-            self.emit(&[0xC6, 0x07, 0x2D]);
-            self.emit(&[0x48, 0xFF, 0xCF]);
-
-            self.emit(&[0x48, 0xFF, 0xC7]);
-            self.emit(&[0x48, 0x8D, 0x74, 0x24, 0x20]);
-            self.emit(&[0x48, 0x29, 0xFE]);
-            self.emit(&[0x48, 0x89, 0xF2]);
-            self.emit(&[0x48, 0x89, 0xFE]);
-            self.emit(&[0x48, 0xC7, 0xC0, 0x01, 0x00, 0x00, 0x00]);
-            self.emit(&[0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00]);
-            self.emit(&[0x0F, 0x05]);
-            self.emit(&[0x48, 0x83, 0xC4, 0x20]);
-        } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x60]);
-
-            self.emit(&[0x48, 0x8D, 0x4C, 0x24, 0x5E]);
-            self.emit(&[0xC6, 0x01, 0x00]);
-            self.emit(&[0x48, 0xFF, 0xC9]);
-
-            self.emit(&[0x48, 0x85, 0xC0]);
-            self.emit(&[0x0F, 0x85]);
-            let not_zero_patch = self.code.len();
-            self.emit_i32(0);
-
-            self.emit(&[0xC6, 0x01, 0x30]);
-            self.emit(&[0xE9]);
-            let done_patch1 = self.code.len();
-            self.emit_i32(0);
-
-            let not_zero_pos = self.code.len();
-            self.patch_i32(not_zero_patch, (not_zero_pos as i32) - (not_zero_patch as i32) - 4);
-
-            self.emit(&[0x48, 0x89, 0xC2]);
-            self.emit(&[0x48, 0xC1, 0xFA, 0x3F]);
-            self.emit(&[0x48, 0x31, 0xD0]);
-            self.emit(&[0x48, 0x29, 0xD0]);
-
-            self.emit(&[0x4C, 0x89, 0xD3]);
-            self.emit(&[0x49, 0x89, 0xD3]);
-
-            self.emit(&[0x41, 0xB8, 0x0A, 0x00, 0x00, 0x00]);
-            let loop_pos = self.code.len();
-            self.emit(&[0x48, 0x31, 0xD2]);
-            self.emit(&[0x49, 0xF7, 0xF0]);
-            self.emit(&[0x80, 0xC2, 0x30]);
-            self.emit(&[0x88, 0x11]);
-            self.emit(&[0x48, 0xFF, 0xC9]);
-            self.emit(&[0x48, 0x85, 0xC0]);
-            let loop_back = (loop_pos as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0x75, (loop_back as u8)]);
-
-            self.emit(&[0x4D, 0x85, 0xDB]);
-            self.emit(&[0x79, 0x03]);
-            self.emit(&[0xC6, 0x01, 0x2D]);
-            self.emit(&[0x48, 0xFF, 0xC9]);
-
-            self.emit(&[0x4C, 0x89, 0xDA]);
-            let done_pos = self.code.len();
-            self.patch_i32(done_patch1, (done_pos as i32) - (done_patch1 as i32) - 4);
-
-            self.emit(&[0x48, 0xFF, 0xC1]);
-
-            self.emit(&[0x48, 0x8D, 0x44, 0x24, 0x60]);
-            self.emit(&[0x48, 0x29, 0xC8]);
-
-            self.emit(&[0x48, 0x89, 0x4C, 0x24, 0x28]);
-            self.emit(&[0x48, 0x89, 0x44, 0x24, 0x30]);
-
-            self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
-            self.emit(&[0xFF, 0x15]);
-            self.emit_i32(0x20000000u32 as i32);
-
-            self.emit(&[0x48, 0x89, 0xC1]);
-            self.emit(&[0x48, 0x8B, 0x54, 0x24, 0x28]);
-            self.emit(&[0x4C, 0x8B, 0x44, 0x24, 0x30]);
-            self.emit(&[0x4C, 0x8D, 0x4C, 0x24, 0x38]);
-            self.emit(&[0x48, 0xC7, 0x44, 0x24, 0x20, 0x00, 0x00, 0x00, 0x00]);
-            self.emit(&[0xFF, 0x15]);
-            self.emit_i32(0x20080000u32 as i32);
-
-            self.emit(&[0x48, 0x83, 0xC4, 0x60]);
-        }
+    /// Writes the NUL-terminated string at `rax` to stdout. Clobbers `rsi` and `rdi`.
+    fn emit_print_cstr(&mut self) {
+        self.emit(&[0x48, 0x89, 0xC6]);
+        self.emit(&[0x48, 0x89, 0xF7]);
+        self.emit(&[0x80, 0x3F, 0x00]);
+        self.emit(&[0x74, 0x05]);
+        self.emit(&[0x48, 0xFF, 0xC7]);
+        self.emit(&[0xEB, 0xF6]);
+        self.emit(&[0x48, 0x29, 0xF7]);
+
+        self.emit_win_frame_enter(0x38);
+        self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
+        self.emit(&[0xFF, 0x15]);
+        self.emit_i32(0x20000000u32 as i32);
+
+        self.emit(&[0x48, 0x89, 0xC1]);
+        self.emit(&[0x48, 0x89, 0xF2]);
+        self.emit(&[0x41, 0x89, 0xF8]);
+        self.emit(&[0x4C, 0x8D, 0x4C, 0x24, 0x28]);
+        self.emit(&[0x48, 0xC7, 0x44, 0x24, 0x20, 0x00, 0x00, 0x00, 0x00]);
+        self.emit(&[0xFF, 0x15]);
+        self.emit_i32(0x20080000u32 as i32);
+        self.emit_win_frame_leave(0x38);
     }
 
     fn emit_print_str(&mut self, text: &str) {
         if self.target == "elf" {
             let str_len = text.len();
 
-            self.emit(&[0xEB]);
-            self.emit(&[(str_len as u8) & 0xFF]);
+            self.emit_skip(str_len);
 
             let string_addr = self.code.len();
             self.code.extend_from_slice(text.as_bytes());
@@ -917,7 +1282,7 @@ impl<'a> CodeGen<'a> {
         } else {
             let str_len = text.len();
 
-            self.emit(&[0x48, 0x83, 0xEC, 0x38]);
+            self.emit_win_frame_enter(0x38);
 
             self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
             self.emit(&[0xFF, 0x15]);
@@ -925,8 +1290,7 @@ impl<'a> CodeGen<'a> {
 
             self.emit(&[0x48, 0x89, 0xC3]);
 
-            self.emit(&[0xEB]);
-            self.emit(&[(str_len as u8) & 0xFF]);
+            self.emit_skip(str_len);
 
             let string_addr = self.code.len();
             self.code.extend_from_slice(text.as_bytes());
@@ -948,7 +1312,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20080000u32 as i32);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x38]);
+            self.emit_win_frame_leave(0x38);
         }
     }
 
@@ -964,7 +1328,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0x83, 0xC4, 0x10]);
         } else {
             
-            self.emit(&[0x48, 0x83, 0xEC, 0x48]);
+            self.emit_win_frame_enter(0x48);
             self.emit(&[0x88, 0x44, 0x24, 0x30]);
 
             
@@ -981,10 +1345,49 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20080000u32 as i32);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x48]);
+            self.emit_win_frame_leave(0x48);
         }
     }
 
+    /// Prints `rax` as `0x` followed by 16 hex digits.
+    fn emit_print_ptr(&mut self) {
+        self.emit_win_frame_enter(0x58);
+        self.emit(&[0x66, 0xC7, 0x44, 0x24, 0x30, b'0', b'x']);
+        self.emit(&[0x48, 0x8D, 0x7C, 0x24, 0x41]);
+        self.emit_hex_digits();
+
+        self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
+        self.emit(&[0xFF, 0x15]);
+        self.emit_i32(0x20000000u32 as i32);
+
+        self.emit(&[0x48, 0x89, 0xC1]);
+        self.emit(&[0x48, 0x8D, 0x54, 0x24, 0x30]);
+        self.emit(&[0x41, 0xB8, 0x12, 0x00, 0x00, 0x00]);
+        self.emit(&[0x4C, 0x8D, 0x4C, 0x24, 0x48]);
+        self.emit(&[0x48, 0xC7, 0x44, 0x24, 0x20, 0x00, 0x00, 0x00, 0x00]);
+        self.emit(&[0xFF, 0x15]);
+        self.emit_i32(0x20080000u32 as i32);
+
+        self.emit_win_frame_leave(0x58);
+    }
+
+    /// Writes the 16 hex digits of `rax` backwards from `rdi`. Clobbers `rax`, `rcx`, `rdx` and `rdi`.
+    fn emit_hex_digits(&mut self) {
+        self.emit(&[0xB9, 0x10, 0x00, 0x00, 0x00]);
+        let loop_start = self.code.len();
+        self.emit(&[0x89, 0xC2]);
+        self.emit(&[0x83, 0xE2, 0x0F]);
+        self.emit(&[0x83, 0xFA, 0x0A]);
+        self.emit(&[0x72, 0x03]);
+        self.emit(&[0x83, 0xC2, 0x27]);
+        self.emit(&[0x83, 0xC2, 0x30]);
+        self.emit(&[0x88, 0x17]);
+        self.emit(&[0x48, 0xFF, 0xCF]);
+        self.emit(&[0x48, 0xC1, 0xE8, 0x04]);
+        self.emit(&[0xFF, 0xC9]);
+        self.emit_jump_back(0x75, loop_start);
+    }
+
     fn emit_read_int(&mut self) {
         if self.target == "elf" {
             self.emit(&[0x48, 0x83, 0xEC, 0x20]);
@@ -995,37 +1398,12 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0xC7, 0xC2, 0x14, 0x00, 0x00, 0x00]);
             self.emit(&[0x0F, 0x05]);
 
-            self.emit(&[0x48, 0x31, 0xC0]);
-            self.emit(&[0x48, 0x31, 0xC9]);
             self.emit(&[0x48, 0x89, 0xE6]);
-
-            self.emit(&[0x80, 0x3E, 0x2D]);
-            self.emit(&[0x75, 0x07]);
-            self.emit(&[0x48, 0xFF, 0xC1]);
-            self.emit(&[0x48, 0xFF, 0xC6]);
-
-            let loop_start = self.code.len();
-            self.emit(&[0x0F, 0xB6, 0x1E]);
-            self.emit(&[0x80, 0xFB, 0x30]);
-            self.emit(&[0x72, 0x13]);
-            self.emit(&[0x80, 0xFB, 0x39]);
-            self.emit(&[0x77, 0x0F]);
-            
-            self.emit(&[0x48, 0x6B, 0xC0, 0x0A]);
-            self.emit(&[0x80, 0xEB, 0x30]);
-            self.emit(&[0x48, 0x0F, 0xB6, 0xDB]);
-            self.emit(&[0x48, 0x01, 0xD8]);
-            self.emit(&[0x48, 0xFF, 0xC6]);
-            let back = (loop_start as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0xEB, (back as u8)]);
-
-            self.emit(&[0x48, 0x85, 0xC9]);
-            self.emit(&[0x74, 0x03]);
-            self.emit(&[0x48, 0xF7, 0xD8]);
+            self.emit_parse_int();
 
             self.emit(&[0x48, 0x83, 0xC4, 0x20]);
         } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x48]);
+            self.emit_win_frame_enter(0x48);
 
             self.emit(&[0xB9, 0xF6, 0xFF, 0xFF, 0xFF]);
             self.emit(&[0xFF, 0x15]);
@@ -1039,36 +1417,41 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20100000u32 as i32);
 
-            self.emit(&[0x48, 0x31, 0xC0]);
-            self.emit(&[0x48, 0x31, 0xC9]);
             self.emit(&[0x48, 0x8D, 0x74, 0x24, 0x30]);
+            self.emit_parse_int();
 
-            self.emit(&[0x80, 0x3E, 0x2D]);
-            self.emit(&[0x75, 0x07]);
-            self.emit(&[0x48, 0xFF, 0xC1]);
-            self.emit(&[0x48, 0xFF, 0xC6]);
+            self.emit_win_frame_leave(0x48);
+        }
+    }
 
-            let loop_start = self.code.len();
-            self.emit(&[0x0F, 0xB6, 0x1E]);
-            self.emit(&[0x80, 0xFB, 0x30]);
-            self.emit(&[0x72, 0x13]);
-            self.emit(&[0x80, 0xFB, 0x39]);
-            self.emit(&[0x77, 0x0F]);
-            
-            self.emit(&[0x48, 0x6B, 0xC0, 0x0A]);
-            self.emit(&[0x80, 0xEB, 0x30]);
-            self.emit(&[0x48, 0x0F, 0xB6, 0xDB]);
-            self.emit(&[0x48, 0x01, 0xD8]);
-            self.emit(&[0x48, 0xFF, 0xC6]);
-            let back = (loop_start as i32) - (self.code.len() as i32) - 2;
-            self.emit(&[0xEB, (back as u8)]);
+    /// Parses an optionally signed decimal number at `rsi` into `rax`,
+    /// stopping at the first non-digit. Clobbers `rbx`, `rcx` and `rsi`.
+    fn emit_parse_int(&mut self) {
+        self.emit(&[0x48, 0x31, 0xC0]);
+        self.emit(&[0x48, 0x31, 0xC9]);
 
-            self.emit(&[0x48, 0x85, 0xC9]);
-            self.emit(&[0x74, 0x03]);
-            self.emit(&[0x48, 0xF7, 0xD8]);
+        self.emit(&[0x80, 0x3E, 0x2D]);
+        self.emit(&[0x75, 0x07]);
+        self.emit(&[0x48, 0xFF, 0xC1]);
+        self.emit(&[0x48, 0xFF, 0xC6]);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x48]);
-        }
+        let loop_start = self.code.len();
+        self.emit(&[0x0F, 0xB6, 0x1E]);
+        self.emit(&[0x80, 0xFB, 0x30]);
+        self.emit(&[0x72, 0x13]);
+        self.emit(&[0x80, 0xFB, 0x39]);
+        self.emit(&[0x77, 0x0F]);
+
+        self.emit(&[0x48, 0x6B, 0xC0, 0x0A]);
+        self.emit(&[0x80, 0xEB, 0x30]);
+        self.emit(&[0x48, 0x0F, 0xB6, 0xDB]);
+        self.emit(&[0x48, 0x01, 0xD8]);
+        self.emit(&[0x48, 0xFF, 0xC6]);
+        self.emit_jump_back(0xEB, loop_start);
+
+        self.emit(&[0x48, 0x85, 0xC9]);
+        self.emit(&[0x74, 0x03]);
+        self.emit(&[0x48, 0xF7, 0xD8]);
     }
 
     fn emit_read_char(&mut self) {
@@ -1084,7 +1467,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0x0F, 0xB6, 0x04, 0x24]);
             self.emit(&[0x48, 0x83, 0xC4, 0x10]);
         } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x48]);
+            self.emit_win_frame_enter(0x48);
 
             self.emit(&[0xB9, 0xF6, 0xFF, 0xFF, 0xFF]);
             self.emit(&[0xFF, 0x15]);
@@ -1099,7 +1482,7 @@ impl<'a> CodeGen<'a> {
             self.emit_i32(0x20100000u32 as i32);
 
             self.emit(&[0x48, 0x0F, 0xB6, 0x44, 0x24, 0x30]);
-            self.emit(&[0x48, 0x83, 0xC4, 0x48]);
+            self.emit_win_frame_leave(0x48);
         }
     }
 
@@ -1109,7 +1492,7 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0x48, 0xC7, 0xC7, 0x01, 0x00, 0x00, 0x00]);
             self.emit(&[0x0F, 0x05]);
         } else {
-            self.emit(&[0x48, 0x83, 0xEC, 0x28]);
+            self.emit_win_frame_enter(0x28);
 
             self.emit(&[0xB9, 0xF5, 0xFF, 0xFF, 0xFF]);
             self.emit(&[0xFF, 0x15]);
@@ -1119,33 +1502,36 @@ impl<'a> CodeGen<'a> {
             self.emit(&[0xFF, 0x15]);
             self.emit_i32(0x20180000u32 as i32);
 
-            self.emit(&[0x48, 0x83, 0xC4, 0x28]);
+            self.emit_win_frame_leave(0x28);
         }
     }
 
     fn generate_module_call(&mut self, module: &str, function: &str, args: &[Expression]) {
+        if module == "sys" {
+            self.fail(format!("sys.{} is only supported on ELF targets", function));
+            return;
+        }
+        if module == "time" && function == "Rdtsc" && args.is_empty() {
+            self.emit(&[0x0F, 0x31]);
+            self.emit(&[0x48, 0xC1, 0xE2, 0x20]);
+            self.emit(&[0x48, 0x09, 0xD0]);
+            return;
+        }
         if module == "stdio" {
-            if function == "Println" && args.len() == 1 {
-                self.generate_stdio_println(&args[0]);
-                return;
-            } else if function == "Print" && args.len() == 1 {
-                self.generate_expression(&args[0]);
-                self.emit_print_int();
+            if matches!(function, "Println" | "PrintlnStr") && args.len() == 1 {
+                self.generate_print_value(&args[0], true);
                 return;
-            } else if function == "PrintlnStr" && args.len() == 1 {
-                if let Expression::String(s) = &args[0] {
-                    self.emit_println(s);
-                }
-                return;
-            } else if function == "PrintStr" && args.len() == 1 {
-                if let Expression::String(s) = &args[0] {
-                    self.emit_print_str(s);
-                }
+            } else if matches!(function, "Print" | "PrintStr") && args.len() == 1 {
+                self.generate_print_value(&args[0], false);
                 return;
             } else if function == "PrintChar" && args.len() == 1 {
                 self.generate_expression(&args[0]);
                 self.emit_print_char();
                 return;
+            } else if function == "PrintPtr" && args.len() == 1 {
+                self.generate_expression(&args[0]);
+                self.emit_print_ptr();
+                return;
             } else if function == "ReadInt" && args.is_empty() {
                 self.emit_read_int();
                 return;
@@ -1158,30 +1544,25 @@ impl<'a> CodeGen<'a> {
             }
         }
         let saved_vars = self.variables.clone();
+        let saved_strings = self.string_vars.clone();
+        let saved_arrays = std::mem::take(&mut self.arrays);
+        let saved_pointers = std::mem::take(&mut self.pointer_vars);
         let saved_offset = self.stack_offset;
         let saved_in_main = self.in_main;
         self.in_main = false;
+        let saved_module = self.current_module.clone();
 
         if let Some(prog) = self.program {
             if let Some(module_def) = prog.modules.get(module) {
                 if let Some(func) = module_def.functions.iter().find(|f| f.name == function) {
-                    if !func.is_exported {
-                        panic!("Function '{}' is not exported from module '{}'", function, module);
-                    }
-
-                    for (i, arg) in args.iter().enumerate() {
-                        if i < func.params.len() {
-                            self.generate_expression(arg);
-                            self.stack_offset -= 8;
-                            self.variables.insert(func.params[i].name.clone(), self.stack_offset);
-                            self.emit(&[0x48, 0x89, 0x85]);
-                            self.emit_i32(self.stack_offset);
-                        }
-                    }
+                    self.bind_inline_params(&func.params, args);
 
+                    self.current_module = Some(module.to_string());
+                    let saved_source = std::mem::replace(&mut self.source, SourceCursor::new(func));
                     for stmt in &func.body {
                         self.generate_statement(stmt);
                     }
+                    self.source = saved_source;
                 } else {
                     panic!("Function '{}' not found in module '{}'", function, module);
                 }
@@ -1191,8 +1572,12 @@ impl<'a> CodeGen<'a> {
         }
 
         self.variables = saved_vars;
+        self.string_vars = saved_strings;
+        self.arrays = saved_arrays;
+        self.pointer_vars = saved_pointers;
         self.stack_offset = saved_offset;
         self.in_main = saved_in_main;
+        self.current_module = saved_module;
     }
 }
 
@@ -1,6 +1,4 @@
 use crate::pe::codegen::MachineCode;
-use std::fs::File;
-use std::io::{self, Write};
 
 const IMAGE_DOS_SIGNATURE: u16 = 0x5A4D;
 const IMAGE_NT_SIGNATURE: u32 = 0x00004550;
@@ -21,7 +19,7 @@ impl PEWriter {
         }
     }
 
-    pub fn write(&mut self, filename: &str, machine_code: &MachineCode) -> io::Result<()> {
+    pub fn build(&mut self, machine_code: &MachineCode) -> Vec<u8> {
         let mut buffer = Vec::new();
 
         let has_imports = machine_code.code.windows(6).any(|w| {
@@ -102,10 +100,7 @@ impl PEWriter {
             }
         }
 
-        let mut file = File::create(filename)?;
-        file.write_all(&buffer)?;
-
-        Ok(())
+        buffer
     }
 
     fn write_dos_header(&self, buffer: &mut Vec<u8>) {
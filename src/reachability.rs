@@ -0,0 +1,241 @@
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FunctionRef {
+    Global(String),
+    Module(String, String),
+}
+
+struct Reachability<'a> {
+    program: &'a Program,
+    live: HashSet<FunctionRef>,
+    worklist: Vec<FunctionRef>,
+    known_names: HashMap<String, FunctionRef>,
+}
+
+impl<'a> Reachability<'a> {
+    fn new(program: &'a Program) -> Self {
+        let mut known_names = HashMap::new();
+        for func in &program.functions {
+            known_names.insert(func.name.clone(), FunctionRef::Global(func.name.clone()));
+        }
+        for (module_name, module) in &program.modules {
+            for func in &module.functions {
+                let func_ref = FunctionRef::Module(module_name.clone(), func.name.clone());
+                known_names.insert(format!("{}_{}", module_name, func.name), func_ref);
+            }
+        }
+
+        Reachability {
+            program,
+            live: HashSet::new(),
+            worklist: Vec::new(),
+            known_names,
+        }
+    }
+
+    fn mark(&mut self, func_ref: FunctionRef) {
+        if self.live.insert(func_ref.clone()) {
+            self.worklist.push(func_ref);
+        }
+    }
+
+    fn find(&self, func_ref: &FunctionRef) -> Option<&'a Function> {
+        match func_ref {
+            FunctionRef::Global(name) => self.program.functions.iter().find(|f| &f.name == name),
+            FunctionRef::Module(module, name) => self.program.modules.get(module)
+                .and_then(|m| m.functions.iter().find(|f| &f.name == name)),
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some(func_ref) = self.worklist.pop() {
+            let scope = match &func_ref {
+                FunctionRef::Module(module, _) => Some(module.clone()),
+                FunctionRef::Global(_) => None,
+            };
+            if let Some(func) = self.find(&func_ref) {
+                for stmt in &func.body {
+                    self.visit_statement(stmt, scope.as_deref());
+                }
+            }
+        }
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement, scope: Option<&str>) {
+        match stmt {
+            Statement::VarDecl { value, .. } => {
+                if let Some(expr) = value {
+                    self.visit_expression(expr, scope);
+                }
+            }
+            Statement::MultiVarDecl { value, .. } => self.visit_expression(value, scope),
+            Statement::ArrayDecl { .. } => {}
+            Statement::Assignment { value, .. } => self.visit_expression(value, scope),
+            Statement::ArrayAssignment { index, value, .. } => {
+                self.visit_expression(index, scope);
+                self.visit_expression(value, scope);
+            }
+            Statement::PointerAssignment { target, value } => {
+                self.visit_expression(target, scope);
+                self.visit_expression(value, scope);
+            }
+            Statement::If { condition, then_body, else_body } => {
+                self.visit_expression(condition, scope);
+                for s in then_body {
+                    self.visit_statement(s, scope);
+                }
+                if let Some(body) = else_body {
+                    for s in body {
+                        self.visit_statement(s, scope);
+                    }
+                }
+            }
+            Statement::For { init, condition, post, body } => {
+                if let Some(s) = init {
+                    self.visit_statement(s, scope);
+                }
+                if let Some(cond) = condition {
+                    self.visit_expression(cond, scope);
+                }
+                if let Some(s) = post {
+                    self.visit_statement(s, scope);
+                }
+                for s in body {
+                    self.visit_statement(s, scope);
+                }
+            }
+            Statement::Return(value) => {
+                if let Some(expr) = value {
+                    self.visit_expression(expr, scope);
+                }
+            }
+            Statement::Expression(expr) => self.visit_expression(expr, scope),
+            Statement::Block(body) => {
+                for s in body {
+                    self.visit_statement(s, scope);
+                }
+            }
+            Statement::InlineAsm { parts } => {
+                for part in parts {
+                    if let AsmPart::Literal(text) = part {
+                        self.visit_asm_text(text);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit_asm_text(&mut self, text: &str) {
+        for word in text.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+            let name = word.strip_prefix("fn_")
+                .or_else(|| word.strip_prefix("func_"))
+                .unwrap_or(word);
+            if let Some(func_ref) = self.known_names.get(name).cloned() {
+                self.mark(func_ref);
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expression, scope: Option<&str>) {
+        match expr {
+            Expression::Number(_) | Expression::String(_) | Expression::Identifier(_) => {}
+            Expression::TemplateString { parts } => {
+                for part in parts {
+                    if let TemplateStringPart::Expression { expr, .. } = part {
+                        self.visit_expression(expr, scope);
+                    }
+                }
+            }
+            Expression::Binary { left, right, .. } => {
+                self.visit_expression(left, scope);
+                self.visit_expression(right, scope);
+            }
+            Expression::AddressOf { operand } => {
+                if let Expression::Identifier(name) = operand.as_ref() {
+                    if self.program.functions.iter().any(|f| &f.name == name) {
+                        self.mark(FunctionRef::Global(name.clone()));
+                    }
+                }
+                self.visit_expression(operand, scope);
+            }
+            Expression::Unary { operand, .. }
+            | Expression::Deref { operand } => self.visit_expression(operand, scope),
+            Expression::Call { function, args } => {
+                let in_module = scope.and_then(|module| {
+                    self.program.modules.get(module)
+                        .filter(|m| m.functions.iter().any(|f| &f.name == function))
+                        .map(|_| FunctionRef::Module(module.to_string(), function.clone()))
+                });
+                match in_module {
+                    Some(func_ref) => self.mark(func_ref),
+                    None => self.mark(FunctionRef::Global(function.clone())),
+                }
+                for arg in args {
+                    self.visit_expression(arg, scope);
+                }
+            }
+            Expression::IndirectCall { callee, args } => {
+                self.visit_expression(callee, scope);
+                for arg in args {
+                    self.visit_expression(arg, scope);
+                }
+            }
+            Expression::ModuleCall { module, function, args } => {
+                self.mark(FunctionRef::Module(module.clone(), function.clone()));
+                for arg in args {
+                    self.visit_expression(arg, scope);
+                }
+            }
+            Expression::ArrayAccess { index, .. } => self.visit_expression(index, scope),
+            Expression::StringIndex { string, index } => {
+                self.visit_expression(string, scope);
+                self.visit_expression(index, scope);
+            }
+            Expression::Slice { base, start, end } => {
+                self.visit_expression(base, scope);
+                for bound in [start, end].into_iter().flatten() {
+                    self.visit_expression(bound, scope);
+                }
+            }
+            Expression::Eval { instruction } => self.visit_expression(instruction, scope),
+            Expression::Tuple(values) => {
+                for value in values {
+                    self.visit_expression(value, scope);
+                }
+            }
+        }
+    }
+}
+
+/// Drops functions unreachable from `entry` and returns how many were removed.
+pub fn eliminate_dead_functions(program: &mut Program, entry: &str, keep_exported: bool) -> usize {
+    let live = {
+        let mut reach = Reachability::new(program);
+        reach.mark(FunctionRef::Global(entry.to_string()));
+        if keep_exported {
+            for (module_name, module) in &program.modules {
+                for func in module.functions.iter().filter(|f| f.is_exported) {
+                    reach.mark(FunctionRef::Module(module_name.clone(), func.name.clone()));
+                }
+            }
+        }
+        reach.run();
+        reach.live
+    };
+
+    let mut pruned = 0;
+
+    let before = program.functions.len();
+    program.functions.retain(|f| live.contains(&FunctionRef::Global(f.name.clone())));
+    pruned += before - program.functions.len();
+
+    for (module_name, module) in program.modules.iter_mut() {
+        let before = module.functions.len();
+        module.functions.retain(|f| live.contains(&FunctionRef::Module(module_name.clone(), f.name.clone())));
+        pruned += before - module.functions.len();
+    }
+
+    pruned
+}
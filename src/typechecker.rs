@@ -1,5 +1,14 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::error::CompileWarning;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Modules the backends implement themselves; functions in a file of that name are never generated.
+const COMPILER_MODULES: [&str; 5] = ["stdio", "novaria", "vga", "sys", "time"];
+/// Calls the PE backend handles before looking at user functions.
+const PE_BUILTINS: [&str; 5] = ["len", "concat", "compare", "exit", "println"];
+/// Builtins and their arity; an unqualified call inside a module reaches these before the module's own functions.
+const BUILTINS: [(&str, usize); 7] = [("itoa", 1), ("parseInt", 1), ("min", 2), ("max", 2), ("abs", 1), ("assert", 1), ("panic", 1)];
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
@@ -13,6 +22,7 @@ pub enum Type {
     String,
     Ptr(Box<Type>),
     Array(Box<Type>, usize),
+    Tuple(Vec<Type>),
     Void,
     Unknown,
 }
@@ -25,7 +35,7 @@ impl Type {
             "i8" => Type::I8,
             "u64" => Type::U64,
             "u32" => Type::U32,
-            "u8" => Type::U8,
+            "u8" | "byte" => Type::U8,
             "bool" => Type::Bool,
             "string" => Type::String,
             "void" => Type::Void,
@@ -34,6 +44,12 @@ impl Type {
                     let inner = Type::from_string(&s[1..]);
                     return Type::Ptr(Box::new(inner));
                 }
+                if s.starts_with('(') && s.ends_with(')') {
+                    let types = s[1..s.len()-1].split(',')
+                        .map(|t| Type::from_string(t.trim()))
+                        .collect();
+                    return Type::Tuple(types);
+                }
                 if s.starts_with('[') && s.ends_with(']') {
                     let inner = &s[1..s.len()-1];
                     if let Some(semicolon_pos) = inner.find(';') {
@@ -67,6 +83,18 @@ impl Type {
             return true;
         }
         
+        if let (Type::Ptr(a), Type::Ptr(b)) = (self, other) {
+            return a == b || matches!(**a, Type::Unknown) || matches!(**b, Type::Unknown);
+        }
+
+        if let (Type::Tuple(a), Type::Tuple(b)) = (self, other) {
+            return a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.can_assign_to(y));
+        }
+
+        if matches!(self, Type::Tuple(_)) || matches!(other, Type::Tuple(_)) {
+            return false;
+        }
+
         if matches!(self, Type::Unknown) || matches!(other, Type::Unknown) {
             return true;
         }
@@ -78,20 +106,48 @@ impl Type {
 pub struct TypeChecker {
     variables: HashMap<String, Type>,
     functions: HashMap<String, FunctionSignature>,
+    private_functions: HashSet<String>,
     errors: Vec<TypeError>,
+    warnings: Vec<CompileWarning>,
     current_function: Option<String>,
+    current_file: String,
+    statement_lines: Vec<usize>,
+    next_statement: usize,
+    current_line: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionSignature {
     pub params: Vec<(String, Type)>,
     pub return_type: Type,
+    pub variadic: bool,
+}
+
+impl FunctionSignature {
+    fn accepts_arg_count(&self, count: usize) -> bool {
+        if self.variadic {
+            count + 1 >= self.params.len()
+        } else {
+            count == self.params.len()
+        }
+    }
+
+    fn describe_arity(&self) -> String {
+        if self.variadic {
+            format!("at least {}", self.params.len() - 1)
+        } else {
+            self.params.len().to_string()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TypeError {
     pub message: String,
     pub location: String,
+    pub file: String,
+    /// Source line of the statement being checked; 0 when unknown.
+    pub line: usize,
 }
 
 impl TypeChecker {
@@ -99,39 +155,99 @@ impl TypeChecker {
         let mut checker = Self {
             variables: HashMap::new(),
             functions: HashMap::new(),
+            private_functions: HashSet::new(),
             errors: Vec::new(),
+            warnings: Vec::new(),
             current_function: None,
+            current_file: String::new(),
+            statement_lines: Vec::new(),
+            next_statement: 0,
+            current_line: 0,
         };
         
         checker.functions.insert("stdio.Print".to_string(), FunctionSignature {
             params: vec![("value".to_string(), Type::Unknown)],
             return_type: Type::Void,
+            variadic: false,
         });
         checker.functions.insert("stdio.Println".to_string(), FunctionSignature {
             params: vec![("value".to_string(), Type::Unknown)],
             return_type: Type::Void,
+            variadic: false,
         });
         checker.functions.insert("stdio.PrintStr".to_string(), FunctionSignature {
             params: vec![("s".to_string(), Type::String)],
             return_type: Type::Void,
+            variadic: false,
         });
         checker.functions.insert("stdio.PrintlnStr".to_string(), FunctionSignature {
             params: vec![("s".to_string(), Type::String)],
             return_type: Type::Void,
+            variadic: false,
+        });
+        checker.functions.insert("stdio.PrintPtr".to_string(), FunctionSignature {
+            params: vec![("p".to_string(), Type::Unknown)],
+            return_type: Type::Void,
+            variadic: false,
+        });
+        checker.functions.insert("stdio.Printf".to_string(), FunctionSignature {
+            params: vec![("format".to_string(), Type::String), ("args".to_string(), Type::Unknown)],
+            return_type: Type::Void,
+            variadic: true,
+        });
+        checker.functions.insert("itoa".to_string(), FunctionSignature {
+            params: vec![("n".to_string(), Type::I64)],
+            return_type: Type::String,
+            variadic: false,
+        });
+        checker.functions.insert("parseInt".to_string(), FunctionSignature {
+            params: vec![("s".to_string(), Type::String)],
+            return_type: Type::I64,
+            variadic: false,
+        });
+        for name in ["min", "max"] {
+            checker.functions.insert(name.to_string(), FunctionSignature {
+                params: vec![("a".to_string(), Type::I64), ("b".to_string(), Type::I64)],
+                return_type: Type::I64,
+                variadic: false,
+            });
+        }
+        checker.functions.insert("abs".to_string(), FunctionSignature {
+            params: vec![("x".to_string(), Type::I64)],
+            return_type: Type::I64,
+            variadic: false,
+        });
+        checker.functions.insert("assert".to_string(), FunctionSignature {
+            params: vec![("cond".to_string(), Type::Unknown)],
+            return_type: Type::Void,
+            variadic: false,
+        });
+        checker.functions.insert("panic".to_string(), FunctionSignature {
+            params: vec![("msg".to_string(), Type::String)],
+            return_type: Type::Void,
+            variadic: false,
         });
         
         checker
     }
 
     pub fn check_program(&mut self, program: &Program) -> Result<(), Vec<TypeError>> {
+        self.check_duplicate_definitions(program);
+        self.check_builtin_conflicts(program);
+
         for func in &program.functions {
             self.collect_function_signature(func);
         }
         
-        for (_module_name, module) in &program.modules {
+        for (module_name, module) in &program.modules {
             for func in &module.functions {
                 if func.is_exported {
                     self.collect_function_signature(func);
+                    if let Some(sig) = self.functions.get(&func.name).cloned() {
+                        self.functions.entry(format!("{}.{}", module_name, func.name)).or_insert(sig);
+                    }
+                } else {
+                    self.private_functions.insert(format!("{}.{}", module_name, func.name));
                 }
             }
         }
@@ -147,7 +263,106 @@ impl TypeChecker {
         }
     }
 
+    fn check_duplicate_definitions(&mut self, program: &Program) {
+        let mut seen = HashSet::new();
+        for func in &program.functions {
+            if !seen.insert(func.name.as_str()) {
+                self.errors.push(TypeError {
+                    message: format!("Function '{}' is defined more than once", func.name),
+                    location: "global".to_string(),
+                    file: func.file.clone(),
+                    line: func.line,
+                });
+            }
+            self.check_duplicate_params(func, &func.name);
+        }
+
+        for (module_name, module) in &program.modules {
+            let mut seen = HashSet::new();
+            for func in &module.functions {
+                let full_name = format!("{}.{}", module_name, func.name);
+                if !seen.insert(func.name.as_str()) {
+                    self.errors.push(TypeError {
+                        message: format!(
+                            "Function '{}' is defined more than once in module '{}'",
+                            func.name, module_name
+                        ),
+                        location: module_name.clone(),
+                        file: func.file.clone(),
+                        line: func.line,
+                    });
+                }
+                self.check_duplicate_params(func, &full_name);
+            }
+        }
+    }
+
+    /// Warns about definitions a builtin silently takes the place of.
+    fn check_builtin_conflicts(&mut self, program: &Program) {
+        for func in &program.functions {
+            if PE_BUILTINS.contains(&func.name.as_str()) {
+                self.warn(func, format!("function '{}' has the name of a builtin; the PE target calls the builtin instead", func.name));
+            }
+        }
+
+        for (module_name, module) in &program.modules {
+            let from_stdlib = |func: &Function| {
+                Path::new(&func.file).parent().and_then(Path::file_name).is_some_and(|dir| dir == "stdlib")
+            };
+            if COMPILER_MODULES.contains(&module_name.as_str()) {
+                if let Some(func) = module.functions.iter().find(|f| !from_stdlib(f)) {
+                    self.warn(func, format!(
+                        "module '{}' shadows the built-in module of that name; its functions are never called",
+                        module_name
+                    ));
+                }
+                continue;
+            }
+            for func in &module.functions {
+                if PE_BUILTINS.contains(&func.name.as_str()) {
+                    self.warn(func, format!(
+                        "function '{}.{}' has the name of a builtin; the PE target calls the builtin instead",
+                        module_name, func.name
+                    ));
+                } else if BUILTINS.contains(&(func.name.as_str(), func.params.len())) {
+                    self.warn(func, format!(
+                        "function '{}.{}' has the name of a builtin; calls to `{}` inside the module run the builtin, call it as `{}.{}`",
+                        module_name, func.name, func.name, module_name, func.name
+                    ));
+                }
+            }
+        }
+    }
+
+    fn warn(&mut self, func: &Function, message: String) {
+        self.warnings.push(CompileWarning {
+            message,
+            file: func.file.clone(),
+            line: func.line,
+            column: 0,
+        });
+    }
+
+    fn check_duplicate_params(&mut self, func: &Function, location: &str) {
+        let mut seen = HashSet::new();
+        for param in &func.params {
+            if !seen.insert(param.name.as_str()) {
+                self.errors.push(TypeError {
+                    message: format!(
+                        "Parameter '{}' is declared more than once in function '{}'",
+                        param.name, func.name
+                    ),
+                    location: location.to_string(),
+                    file: func.file.clone(),
+                    line: func.line,
+                });
+            }
+        }
+    }
+
     fn collect_function_signature(&mut self, func: &Function) {
+        self.current_file = func.file.clone();
+        self.current_line = func.line;
         let params: Vec<(String, Type)> = func.params.iter()
             .map(|p| (p.name.clone(), Type::from_string(&p.param_type)))
             .collect();
@@ -155,30 +370,115 @@ impl TypeChecker {
         let return_type = func.return_type.as_ref()
             .map(|t| Type::from_string(t))
             .unwrap_or(Type::Void);
+
+        if let Type::Tuple(types) = &return_type {
+            if types.len() > 2 {
+                self.add_error(format!(
+                    "Function '{}' returns {} values; at most 2 are supported",
+                    func.name, types.len()
+                ));
+            }
+        }
         
         self.functions.insert(func.name.clone(), FunctionSignature {
             params,
             return_type,
+            variadic: func.params.last().map(|p| p.is_variadic).unwrap_or(false),
         });
     }
 
     fn check_function(&mut self, func: &Function) {
         self.current_function = Some(func.name.clone());
+        self.current_file = func.file.clone();
+        self.statement_lines = func.statement_lines.clone();
+        self.next_statement = 0;
+        self.current_line = func.line;
         self.variables.clear();
         
         for param in &func.params {
-            let param_type = Type::from_string(&param.param_type);
+            let mut param_type = Type::from_string(&param.param_type);
+            if param.is_variadic {
+                param_type = Type::Ptr(Box::new(param_type));
+            }
             self.variables.insert(param.name.clone(), param_type);
         }
         
         for stmt in &func.body {
             self.check_statement(stmt);
         }
+        self.check_unreachable(func, &func.body, &mut 0);
+
+        self.current_line = func.line;
+        if let Some(sig) = self.functions.get(&func.name) {
+            if !matches!(sig.return_type, Type::Void) && !always_returns(&func.body) {
+                let message = format!(
+                    "Function '{}' is missing a return: it must return {:?} on every path",
+                    func.name, sig.return_type
+                );
+                self.add_error(message);
+            }
+        }
         
         self.current_function = None;
     }
 
+    /// Warns about the first statement after one that never falls through, once per body.
+    /// `cursor` walks `func.statement_lines`, which the parser records in pre-order.
+    fn check_unreachable(&mut self, func: &Function, body: &[Statement], cursor: &mut usize) {
+        let mut exited = false;
+        let mut warned = false;
+        for stmt in body {
+            let line = func.statement_lines.get(*cursor).copied().unwrap_or(func.line);
+            *cursor += 1;
+            if exited && !warned {
+                warned = true;
+                self.warnings.push(CompileWarning {
+                    message: format!("unreachable statement `{}` in function '{}'", stmt.summary(), func.name),
+                    file: func.file.clone(),
+                    line,
+                    column: 0,
+                });
+            }
+            match stmt {
+                Statement::If { then_body, else_body, .. } => {
+                    self.check_unreachable(func, then_body, cursor);
+                    if let Some(else_body) = else_body {
+                        self.check_unreachable(func, else_body, cursor);
+                    }
+                }
+                Statement::For { init, post, body, .. } => {
+                    *cursor += usize::from(init.is_some());
+                    self.check_unreachable(func, body, cursor);
+                    *cursor += usize::from(post.is_some());
+                }
+                Statement::Block(body) => self.check_unreachable(func, body, cursor),
+                _ => {}
+            }
+            exited |= stmt.always_returns()
+                || matches!(stmt, Statement::Expression(Expression::ModuleCall { module, function, .. })
+                    if module == "novaria" && function == "Exit");
+        }
+    }
+
+    pub fn warnings(&self) -> &[CompileWarning] {
+        &self.warnings
+    }
+
+    fn check_block(&mut self, body: &[Statement]) {
+        let saved = self.variables.clone();
+        for stmt in body {
+            self.check_statement(stmt);
+        }
+        self.variables = saved;
+    }
+
+    /// `next_statement` walks `statement_lines` in the parser's pre-order, so errors carry the
+    /// line of the statement that raised them.
     fn check_statement(&mut self, stmt: &Statement) {
+        if let Some(&line) = self.statement_lines.get(self.next_statement) {
+            self.current_line = line;
+        }
+        self.next_statement += 1;
         match stmt {
             Statement::VarDecl { name, var_type, value } => {
                 let declared_type = var_type.as_ref()
@@ -188,7 +488,12 @@ impl TypeChecker {
                 if let Some(expr) = value {
                     let expr_type = self.infer_expression(expr);
                     
-                    if !declared_type.can_assign_to(&expr_type) && !expr_type.can_assign_to(&declared_type) {
+                    if let Type::Tuple(types) = &expr_type {
+                        self.add_error(format!(
+                            "Variable '{}' initialized with {} values; use `var a, b = ...`",
+                            name, types.len()
+                        ));
+                    } else if !declared_type.can_assign_to(&expr_type) && !expr_type.can_assign_to(&declared_type) {
                         self.add_error(format!(
                             "Type mismatch in variable '{}': declared as {:?}, but initialized with {:?}",
                             name, declared_type, expr_type
@@ -207,6 +512,32 @@ impl TypeChecker {
                 }
             }
             
+            Statement::MultiVarDecl { names, value } => {
+                let value_type = self.infer_expression(value);
+                match value_type {
+                    Type::Tuple(types) if types.len() == names.len() => {
+                        for (name, ty) in names.iter().zip(types) {
+                            self.variables.insert(name.clone(), ty);
+                        }
+                    }
+                    Type::Tuple(types) => {
+                        self.add_error(format!(
+                            "Cannot assign {} values to {} variables",
+                            types.len(), names.len()
+                        ));
+                    }
+                    other => {
+                        self.add_error(format!(
+                            "Expected {} values, got {:?}",
+                            names.len(), other
+                        ));
+                    }
+                }
+                for name in names {
+                    self.variables.entry(name.clone()).or_insert(Type::Unknown);
+                }
+            }
+
             Statement::Assignment { name, value } => {
                 let expr_type = self.infer_expression(value);
                 
@@ -218,14 +549,23 @@ impl TypeChecker {
                         ));
                     }
                 } else {
-                    self.add_error(format!("Variable '{}' not declared", name));
+                    self.add_error(format!(
+                        "Variable '{}' not declared; declare it with `var {} = ...` before assigning to it",
+                        name, name
+                    ));
                 }
             }
             
             Statement::ArrayAssignment { name, index, value } => {
                 let var_type_opt = self.variables.get(name).cloned();
                 if let Some(var_type) = var_type_opt {
-                    if let Type::Array(elem_type, _) = var_type {
+                    if let Type::Array(_, length) = var_type {
+                        self.check_array_index(name, index, length);
+                    }
+                    if let Type::Ptr(elem_type) = &var_type {
+                        self.check_pointer_index(name, elem_type);
+                    }
+                    if let Type::Array(elem_type, _) | Type::Ptr(elem_type) = var_type {
                         let index_type = self.infer_expression(index);
                         if !index_type.is_integer() {
                             self.add_error(format!(
@@ -261,18 +601,17 @@ impl TypeChecker {
                     ));
                 }
                 
-                for stmt in then_body {
-                    self.check_statement(stmt);
-                }
+                self.check_block(then_body);
                 
                 if let Some(else_stmts) = else_body {
-                    for stmt in else_stmts {
-                        self.check_statement(stmt);
-                    }
+                    self.check_block(else_stmts);
                 }
             }
             
+            Statement::Block(body) => self.check_block(body),
+
             Statement::For { init, condition, post, body } => {
+                let saved = self.variables.clone();
                 if let Some(init_stmt) = init {
                     self.check_statement(init_stmt);
                 }
@@ -287,22 +626,26 @@ impl TypeChecker {
                     }
                 }
                 
+                self.check_block(body);
+
                 if let Some(post_stmt) = post {
                     self.check_statement(post_stmt);
                 }
-                
-                for stmt in body {
-                    self.check_statement(stmt);
-                }
+                self.variables = saved;
             }
             
             Statement::Return(value) => {
-                if let Some(func_name) = &self.current_function {
-                    let sig_opt = self.functions.get(func_name).cloned();
+                if let Some(func_name) = self.current_function.clone() {
+                    let sig_opt = self.functions.get(&func_name).cloned();
                     if let Some(sig) = sig_opt {
                         if let Some(expr) = value {
                             let expr_type = self.infer_expression(expr);
-                            if !expr_type.can_assign_to(&sig.return_type) {
+                            if matches!(sig.return_type, Type::Void) {
+                                self.add_error(format!(
+                                    "Function '{}' has no return type but returns a value; declare one with `-> type`",
+                                    func_name
+                                ));
+                            } else if !expr_type.can_assign_to(&sig.return_type) {
                                 self.add_error(format!(
                                     "Return type mismatch: expected {:?}, got {:?}",
                                     sig.return_type, expr_type
@@ -319,12 +662,19 @@ impl TypeChecker {
             }
             
             Statement::Expression(expr) => {
-                self.infer_expression(expr);
+                if let Type::Tuple(types) = self.infer_expression(expr) {
+                    self.add_error(format!(
+                        "{} return values are discarded; bind them with `var a, b = ...`",
+                        types.len()
+                    ));
+                }
             }
             
             Statement::PointerAssignment { target, value } => {
                 let target_type = self.infer_expression(target);
-                if !matches!(target_type, Type::Ptr(_) | Type::Unknown) {
+                if target_type == Type::String {
+                    self.add_error("Cannot write through a string: string literals are read-only".to_string());
+                } else if !matches!(target_type, Type::Ptr(_) | Type::Unknown) {
                     self.add_error(format!(
                         "Pointer dereference assignment requires a pointer type, got {:?}",
                         target_type
@@ -365,6 +715,10 @@ impl TypeChecker {
                 let right_type = self.infer_expression(right);
                 
                 match op {
+                    BinaryOp::Add | BinaryOp::Sub if matches!(left_type, Type::Ptr(_)) && right_type.is_integer() => left_type,
+                    BinaryOp::Add if left_type.is_integer() && matches!(right_type, Type::Ptr(_)) => right_type,
+                    BinaryOp::Sub if matches!(left_type, Type::Ptr(_)) && left_type == right_type => Type::I64,
+                    BinaryOp::Mul if left_type == Type::String && right_type.is_integer() => Type::String,
                     BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
                         if !left_type.is_numeric() {
                             self.add_error(format!(
@@ -420,13 +774,17 @@ impl TypeChecker {
             Expression::Call { function, args } => {
                 let sig_opt = self.functions.get(function).cloned();
                 if let Some(sig) = sig_opt {
-                    if args.len() != sig.params.len() {
+                    if !sig.accepts_arg_count(args.len()) {
                         self.add_error(format!(
                             "Function '{}' expects {} arguments, got {}",
-                            function, sig.params.len(), args.len()
+                            function, sig.describe_arity(), args.len()
                         ));
                     } else {
-                        for (i, (arg, (_, param_type))) in args.iter().zip(sig.params.iter()).enumerate() {
+                        let variadic_type = sig.params.last().filter(|_| sig.variadic).map(|(_, t)| t.clone());
+                        let fixed = if sig.variadic { sig.params.len() - 1 } else { sig.params.len() };
+                        let param_types = sig.params[..fixed].iter().map(|(_, t)| t)
+                            .chain(std::iter::repeat(variadic_type.as_ref().unwrap_or(&Type::Unknown)));
+                        for (i, (arg, param_type)) in args.iter().zip(param_types).enumerate() {
                             let arg_type = self.infer_expression(arg);
                             if !arg_type.can_assign_to(param_type) {
                                 self.add_error(format!(
@@ -434,6 +792,7 @@ impl TypeChecker {
                                     i, function, param_type, arg_type
                                 ));
                             }
+                            self.check_byte_argument(function, i, arg, param_type);
                         }
                     }
                     sig.return_type.clone()
@@ -445,16 +804,28 @@ impl TypeChecker {
             
             Expression::ModuleCall { module, function, args } => {
                 let full_name = format!("{}.{}", module, function);
+                for arg in args {
+                    self.infer_expression(arg);
+                }
                 let sig_opt = self.functions.get(&full_name).cloned();
                 if let Some(sig) = sig_opt {
-                    if args.len() != sig.params.len() {
+                    if !sig.accepts_arg_count(args.len()) {
                         self.add_error(format!(
                             "Function '{}' expects {} arguments, got {}",
-                            full_name, sig.params.len(), args.len()
+                            full_name, sig.describe_arity(), args.len()
                         ));
                     }
+                    for (i, (arg, (_, param_type))) in args.iter().zip(&sig.params).enumerate() {
+                        self.check_byte_argument(&full_name, i, arg, param_type);
+                    }
                     sig.return_type.clone()
                 } else {
+                    if self.private_functions.contains(&full_name) {
+                        self.add_error(format!(
+                            "Function '{}' is not exported from module '{}'; capitalize its name or mark it `pub` to export it",
+                            function, module
+                        ));
+                    }
                     Type::Unknown
                 }
             }
@@ -470,8 +841,16 @@ impl TypeChecker {
                 
                 let var_type_opt = self.variables.get(name).cloned();
                 if let Some(var_type) = var_type_opt {
-                    if let Type::Array(elem_type, _) = var_type {
+                    if let Type::Array(_, length) = var_type {
+                        self.check_array_index(name, index, length);
+                    }
+                    if let Type::Ptr(elem_type) = &var_type {
+                        self.check_pointer_index(name, elem_type);
+                    }
+                    if let Type::Array(elem_type, _) | Type::Ptr(elem_type) = var_type {
                         (*elem_type).clone()
+                    } else if var_type == Type::String {
+                        Type::U8
                     } else {
                         self.add_error(format!(
                             "Cannot index into non-array type {:?}",
@@ -486,7 +865,13 @@ impl TypeChecker {
             }
             
             Expression::StringIndex { string, index } => {
-                let _string_type = self.infer_expression(string);
+                let string_type = self.infer_expression(string);
+                if !matches!(string_type, Type::String | Type::Unknown) {
+                    self.add_error(format!(
+                        "Cannot index into non-string type {:?}",
+                        string_type
+                    ));
+                }
                 let index_type = self.infer_expression(index);
                 
                 if !index_type.is_integer() {
@@ -499,9 +884,76 @@ impl TypeChecker {
                 Type::U8
             }
             
+            Expression::Slice { base, start, end } => {
+                let base_type = self.infer_expression(base);
+                if !matches!(base_type, Type::String | Type::Unknown) {
+                    self.add_error(format!(
+                        "Cannot slice non-string type {:?}",
+                        base_type
+                    ));
+                }
+
+                for bound in [start, end].into_iter().flatten() {
+                    let bound_type = self.infer_expression(bound);
+                    if !bound_type.is_integer() && bound_type != Type::Unknown {
+                        self.add_error(format!(
+                            "Slice bound must be an integer, got {:?}",
+                            bound_type
+                        ));
+                    }
+                }
+
+                let start_value = start.as_ref().and_then(|s| s.constant_value()).or(start.is_none().then_some(0));
+                let end_value = end.as_ref().and_then(|e| e.constant_value());
+                let length = match base.as_ref() {
+                    Expression::String(s) => Some(s.len() as i64),
+                    _ => None,
+                };
+                let out_of_range = start_value.is_some_and(|s| s < 0 || length.is_some_and(|l| s > l))
+                    || end_value.is_some_and(|e| e < 0 || length.is_some_and(|l| e > l))
+                    || matches!((start_value, end_value), (Some(s), Some(e)) if e < s);
+                if out_of_range {
+                    self.add_error(format!(
+                        "Slice bounds [{}:{}] are out of range",
+                        start.as_ref().map(|s| s.to_string()).unwrap_or_default(),
+                        end.as_ref().map(|e| e.to_string()).unwrap_or_default()
+                    ));
+                }
+
+                Type::String
+            }
+            
             Expression::AddressOf { operand } => {
-                let inner_type = self.infer_expression(operand);
-                Type::Ptr(Box::new(inner_type))
+                if self.points_into_string(operand) {
+                    self.add_error(
+                        "Cannot take the address of string data: string literals are read-only".to_string(),
+                    );
+                    return Type::Unknown;
+                }
+                if let Expression::Identifier(name) = operand.as_ref() {
+                    if !self.variables.contains_key(name) {
+                        if self.functions.contains_key(name) {
+                            return Type::I64;
+                        }
+                        self.add_error(format!("'{}' is neither a variable nor a function", name));
+                        return Type::Unknown;
+                    }
+                }
+                match self.infer_expression(operand) {
+                    Type::Array(elem_type, _) => Type::Ptr(elem_type),
+                    inner_type => Type::Ptr(Box::new(inner_type)),
+                }
+            }
+
+            Expression::IndirectCall { callee, args } => {
+                let callee_type = self.infer_expression(callee);
+                if !callee_type.is_integer() && callee_type != Type::Unknown {
+                    self.add_error(format!("Cannot call a value of type {:?}", callee_type));
+                }
+                for arg in args {
+                    self.infer_expression(arg);
+                }
+                Type::Unknown
             }
             
             Expression::Deref { operand } => {
@@ -521,6 +973,55 @@ impl TypeChecker {
                 self.infer_expression(instruction);
                 Type::Unknown
             }
+
+            Expression::Tuple(values) => {
+                Type::Tuple(values.iter().map(|v| self.infer_expression(v)).collect())
+            }
+        }
+    }
+
+    /// Whether taking the address of `expr` would yield a pointer into string data,
+    /// which for literals lives in a read-only section.
+    fn points_into_string(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::String(_) | Expression::TemplateString { .. } => true,
+            Expression::StringIndex { .. } | Expression::Slice { .. } => true,
+            Expression::ArrayAccess { name, .. } => matches!(self.variables.get(name), Some(Type::String)),
+            _ => false,
+        }
+    }
+
+    /// A constant passed to a `u8` parameter, such as a port value or VGA attribute, must fit in a byte.
+    fn check_byte_argument(&mut self, function: &str, index: usize, arg: &Expression, param_type: &Type) {
+        if let (Type::U8, Some(value)) = (param_type, arg.constant_value()) {
+            if !(0..=255).contains(&value) {
+                self.add_error(format!(
+                    "Argument {} of function '{}' is {}, which does not fit in u8 (0 to 255)",
+                    index, function, value
+                ));
+            }
+        }
+    }
+
+    fn check_array_index(&mut self, name: &str, index: &Expression, length: usize) {
+        if let Some(value) = index.constant_value() {
+            if value >= length as i64 || value < -(length as i64) {
+                self.add_error(format!(
+                    "Array index {} is out of bounds for '{}' of length {}",
+                    value, name, length
+                ));
+            }
+        }
+    }
+
+    /// Pointer indexing steps over 8-byte elements on every backend, so a byte pointer
+    /// would read and write the wrong cells.
+    fn check_pointer_index(&mut self, name: &str, elem_type: &Type) {
+        if *elem_type == Type::U8 {
+            self.add_error(format!(
+                "Cannot index through '{}': pointer indexing steps over 8-byte elements, and u8 is 1 byte wide",
+                name
+            ));
         }
     }
 
@@ -529,12 +1030,8 @@ impl TypeChecker {
         self.errors.push(TypeError {
             message,
             location,
+            file: self.current_file.clone(),
+            line: self.current_line,
         });
     }
-
-    pub fn print_errors(&self) {
-        for error in &self.errors {
-            eprintln!("Type error in {}: {}", error.location, error.message);
-        }
-    }
 }
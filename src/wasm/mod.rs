@@ -0,0 +1,3 @@
+pub mod wat_generator;
+
+pub use wat_generator::WatGenerator;
@@ -0,0 +1,576 @@
+use crate::ast::*;
+use crate::error::{CompileError, ErrorKind, Result};
+use crate::nvm::codegen::{NVMCodeGen, PrintfPiece};
+use std::collections::HashMap;
+
+pub struct WatGenerator {
+    output: String,
+    label_counter: u32,
+    local_vars: HashMap<String, String>,
+    declared: Vec<String>,
+    param_count: usize,
+    indent: usize,
+    current_function: String,
+    current_module: Option<String>,
+    compile_time_strings: HashMap<String, String>,
+    source: SourceCursor,
+    entry: String,
+    error: Option<CompileError>,
+}
+
+impl WatGenerator {
+    pub fn new() -> Self {
+        Self {
+            output: String::new(),
+            label_counter: 0,
+            local_vars: HashMap::new(),
+            declared: Vec::new(),
+            param_count: 0,
+            indent: 0,
+            current_function: String::new(),
+            current_module: None,
+            compile_time_strings: HashMap::new(),
+            source: SourceCursor::default(),
+            entry: "main".to_string(),
+            error: None,
+        }
+    }
+
+    pub fn set_entry(&mut self, name: &str) {
+        self.entry = name.to_string();
+    }
+
+    /// Records the first error, at the statement being generated.
+    fn fail(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(
+                ErrorKind::CodeGenError,
+                message,
+                self.source.file().to_string(),
+                self.source.line(),
+                1,
+            ));
+        }
+    }
+
+    fn unsupported(&mut self, what: &str) {
+        self.fail(format!("the WASM target does not support {}", what));
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.output.push_str("    ");
+        for _ in 0..self.indent {
+            self.output.push_str("  ");
+        }
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    pub fn generate(&mut self, program: &Program) -> Result<String> {
+        self.output.push_str(";; Generated by Perano Language Compiler\n");
+        self.output.push_str("(module\n");
+        self.output.push_str("  (import \"env\" \"print_int\" (func $print_int (param i64)))\n");
+        self.output.push_str("  (import \"env\" \"print_char\" (func $print_char (param i32)))\n\n");
+
+        if let Some(main_func) = program.functions.iter().find(|f| f.name == self.entry) {
+            self.generate_function(main_func, &main_func.name, program);
+        }
+
+        for func in &program.functions {
+            if func.name != self.entry {
+                self.generate_function(func, &func.name, program);
+            }
+        }
+
+        for (module_name, module) in &program.modules {
+            if matches!(module_name.as_str(), "stdio" | "vga" | "novaria" | "sys" | "time") {
+                continue;
+            }
+            self.current_module = Some(module_name.clone());
+            for func in &module.functions {
+                let full_name = format!("{}.{}", module_name, func.name);
+                self.generate_function(func, &full_name, program);
+            }
+            self.current_module = None;
+        }
+
+        self.output.push_str(")\n");
+
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self.output.clone()),
+        }
+    }
+
+    fn generate_function(&mut self, func: &Function, full_name: &str, program: &Program) {
+        self.current_function = full_name.to_string();
+        self.local_vars.clear();
+        self.declared.clear();
+        self.compile_time_strings.clear();
+        self.source = SourceCursor::new(func);
+
+        if func.params.iter().any(|p| p.is_variadic) {
+            self.fail(format!("variadic function '{}' is not supported on the WASM target", full_name));
+        }
+
+        let mut header = format!("  (func ${}", full_name);
+        if self.current_module.is_none() && func.name == self.entry {
+            header.push_str(" (export \"main\")");
+        }
+        for param in &func.params {
+            let wat_name = self.declare(&param.name);
+            header.push_str(&format!(" (param {} i64)", wat_name));
+        }
+        self.param_count = self.declared.len();
+        let arity = NVMCodeGen::return_arity(func);
+        header.push_str(" (result");
+        for _ in 0..arity {
+            header.push_str(" i64");
+        }
+        header.push_str(")\n");
+        self.output.push_str(&header);
+
+        let locals_pos = self.output.len();
+
+        for stmt in &func.body {
+            self.generate_statement(stmt, program);
+        }
+
+        if always_returns(&func.body) {
+            self.emit("unreachable");
+        } else {
+            for _ in 0..arity {
+                self.emit("i64.const 0");
+            }
+        }
+        self.output.push_str("  )\n\n");
+
+        let locals: String = self.declared[self.param_count..].iter()
+            .map(|name| format!("    (local {} i64)\n", name))
+            .collect();
+        self.output.insert_str(locals_pos, &locals);
+    }
+
+    /// Binds `name` to a fresh function-wide local; shadowed names get a numeric suffix.
+    fn declare(&mut self, name: &str) -> String {
+        let mut wat_name = format!("${}", name);
+        let mut suffix = 1;
+        while self.declared.contains(&wat_name) {
+            suffix += 1;
+            wat_name = format!("${}_{}", name, suffix);
+        }
+        self.declared.push(wat_name.clone());
+        self.local_vars.insert(name.to_string(), wat_name.clone());
+        wat_name
+    }
+
+    fn generate_label(&mut self, prefix: &str) -> String {
+        self.label_counter += 1;
+        format!("${}_{}", prefix, self.label_counter)
+    }
+
+    fn generate_block(&mut self, body: &[Statement], program: &Program) {
+        let saved_vars = self.local_vars.clone();
+        let saved_strings = self.compile_time_strings.clone();
+        for stmt in body {
+            self.generate_statement(stmt, program);
+        }
+        self.local_vars = saved_vars;
+        self.compile_time_strings = saved_strings;
+    }
+
+    fn generate_nested(&mut self, body: &[Statement], program: &Program) {
+        self.indent += 1;
+        self.generate_block(body, program);
+        self.indent -= 1;
+    }
+
+    fn generate_statement(&mut self, stmt: &Statement, program: &Program) {
+        self.source.advance();
+        match stmt {
+            Statement::VarDecl { name, value, .. } => {
+                match value {
+                    Some(Expression::String(s)) => {
+                        self.compile_time_strings.insert(name.clone(), s.clone());
+                        self.emit("i64.const 0");
+                    }
+                    Some(init_expr) => self.generate_expression(init_expr, program),
+                    None => self.emit("i64.const 0"),
+                }
+                let wat_name = self.declare(name);
+                self.emit(&format!("local.set {}", wat_name));
+            }
+
+            Statement::MultiVarDecl { names, value } => {
+                self.generate_expression(value, program);
+                let slots: Vec<String> = names.iter().map(|name| self.declare(name)).collect();
+                for wat_name in slots.iter().rev() {
+                    self.emit(&format!("local.set {}", wat_name));
+                }
+            }
+
+            Statement::Assignment { name, value } => {
+                self.generate_expression(value, program);
+                match self.local_vars.get(name).cloned() {
+                    Some(wat_name) => self.emit(&format!("local.set {}", wat_name)),
+                    None => self.fail(format!("Variable not found: {}", name)),
+                }
+            }
+
+            Statement::If { condition, then_body, else_body } => {
+                self.generate_condition(condition, program);
+                self.emit("if");
+                self.generate_nested(then_body, program);
+                if let Some(else_stmts) = else_body {
+                    self.emit("else");
+                    self.generate_nested(else_stmts, program);
+                }
+                self.emit("end");
+            }
+
+            Statement::Block(body) => self.generate_block(body, program),
+
+            Statement::For { init, condition, post, body } => {
+                let saved_vars = self.local_vars.clone();
+                let saved_strings = self.compile_time_strings.clone();
+                if let Some(init_stmt) = init {
+                    self.generate_statement(init_stmt, program);
+                }
+
+                let loop_end = self.generate_label("for_end");
+                let loop_start = self.generate_label("for_start");
+
+                self.emit(&format!("block {}", loop_end));
+                self.indent += 1;
+                self.emit(&format!("loop {}", loop_start));
+                self.indent += 1;
+
+                if let Some(cond) = condition {
+                    self.generate_expression(cond, program);
+                    self.emit("i64.eqz");
+                    self.emit(&format!("br_if {}", loop_end));
+                }
+
+                self.generate_block(body, program);
+
+                if let Some(post_stmt) = post {
+                    self.generate_statement(post_stmt, program);
+                }
+
+                self.emit(&format!("br {}", loop_start));
+                self.indent -= 1;
+                self.emit("end");
+                self.indent -= 1;
+                self.emit("end");
+                self.local_vars = saved_vars;
+                self.compile_time_strings = saved_strings;
+            }
+
+            Statement::Return(value) => {
+                match value {
+                    Some(expr) => self.generate_expression(expr, program),
+                    None => self.emit("i64.const 0"),
+                }
+                self.emit("return");
+            }
+
+            Statement::Expression(expr) => {
+                self.generate_expression(expr, program);
+                for _ in 0..NVMCodeGen::values_left(expr) {
+                    self.emit("drop");
+                }
+            }
+
+            Statement::ArrayDecl { .. } | Statement::ArrayAssignment { .. } => self.unsupported("arrays"),
+            Statement::PointerAssignment { .. } => self.unsupported("writing through a pointer"),
+            Statement::InlineAsm { .. } => self.unsupported("inline asm"),
+        }
+    }
+
+    /// Leaves the truth value of `expr` as the `i32` that `if` and `br_if` consume.
+    fn generate_condition(&mut self, expr: &Expression, program: &Program) {
+        self.generate_expression(expr, program);
+        self.emit("i64.const 0");
+        self.emit("i64.ne");
+    }
+
+    fn generate_expression(&mut self, expr: &Expression, program: &Program) {
+        match expr {
+            Expression::Number(n) => self.emit(&format!("i64.const {}", n)),
+
+            Expression::TemplateString { parts } => {
+                for part in parts {
+                    match part {
+                        TemplateStringPart::Literal(lit) => self.emit_print_chars(lit),
+                        TemplateStringPart::Expression { expr, format } => {
+                            match NVMCodeGen::template_text(&self.compile_time_strings, expr, format.as_ref()) {
+                                Some(text) => self.emit_print_chars(&text),
+                                None => {
+                                    if let Some(message) = NVMCodeGen::runtime_format_error(format.as_ref(), "WASM") {
+                                        self.fail(message);
+                                    }
+                                    self.generate_expression(expr, program);
+                                    self.emit("call $print_int");
+                                }
+                            }
+                        }
+                    }
+                }
+                self.emit("i64.const 0");
+            }
+
+            Expression::Identifier(name) => match self.local_vars.get(name).cloned() {
+                Some(wat_name) => self.emit(&format!("local.get {}", wat_name)),
+                None => {
+                    self.fail(format!("Variable not found: {}", name));
+                    self.emit("i64.const 0");
+                }
+            },
+
+            Expression::Binary { op: op @ (BinaryOp::And | BinaryOp::Or), left, right } => {
+                self.generate_condition(left, program);
+                self.emit("if (result i64)");
+                self.indent += 1;
+                if *op == BinaryOp::And {
+                    self.generate_condition(right, program);
+                    self.emit("i64.extend_i32_u");
+                } else {
+                    self.emit("i64.const 1");
+                }
+                self.indent -= 1;
+                self.emit("else");
+                self.indent += 1;
+                if *op == BinaryOp::And {
+                    self.emit("i64.const 0");
+                } else {
+                    self.generate_condition(right, program);
+                    self.emit("i64.extend_i32_u");
+                }
+                self.indent -= 1;
+                self.emit("end");
+            }
+
+            Expression::Binary { op, left, right } => {
+                self.generate_expression(left, program);
+                self.generate_expression(right, program);
+
+                let (instr, compare) = match op {
+                    BinaryOp::Add => ("i64.add", false),
+                    BinaryOp::Sub => ("i64.sub", false),
+                    BinaryOp::Mul => ("i64.mul", false),
+                    BinaryOp::Div => ("i64.div_s", false),
+                    BinaryOp::Mod => ("i64.rem_s", false),
+                    BinaryOp::Equal => ("i64.eq", true),
+                    BinaryOp::NotEqual => ("i64.ne", true),
+                    BinaryOp::Less => ("i64.lt_s", true),
+                    BinaryOp::LessEqual => ("i64.le_s", true),
+                    BinaryOp::Greater => ("i64.gt_s", true),
+                    BinaryOp::GreaterEqual => ("i64.ge_s", true),
+                    _ => {
+                        self.unsupported(&format!("the '{}' operator", op.symbol()));
+                        return;
+                    }
+                };
+                self.emit(instr);
+                if compare {
+                    self.emit("i64.extend_i32_u");
+                }
+            }
+
+            Expression::Unary { op: UnaryOp::Neg, operand } => {
+                self.emit("i64.const 0");
+                self.generate_expression(operand, program);
+                self.emit("i64.sub");
+            }
+
+            Expression::Unary { op: UnaryOp::Not, operand } => {
+                self.generate_expression(operand, program);
+                self.emit("i64.eqz");
+                self.emit("i64.extend_i32_u");
+            }
+
+            Expression::Call { function, args }
+                if NVMCodeGen::is_int_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                self.generate_int_builtin(function, args, program);
+            }
+
+            Expression::Call { function, args }
+                if NVMCodeGen::is_fatal_builtin(function, args.len()) && !program.functions.iter().any(|f| &f.name == function) =>
+            {
+                let Some(message) = NVMCodeGen::fatal_message(&self.compile_time_strings, function, &args[0], &self.source) else {
+                    self.fail("panic needs a string literal message on the WASM target".to_string());
+                    return;
+                };
+                if function == "assert" {
+                    self.generate_condition(&args[0], program);
+                    self.emit("if");
+                    self.emit("else");
+                    self.emit_print_chars(&message);
+                    self.emit("unreachable");
+                    self.emit("end");
+                } else {
+                    self.emit_print_chars(&message);
+                    self.emit("unreachable");
+                }
+                self.emit("i64.const 0");
+            }
+
+            Expression::Call { function, args } => {
+                let target = match self.current_module.clone().filter(|m| program.module_function(m, function).is_some()) {
+                    Some(module) => format!("{}.{}", module, function),
+                    None if program.functions.iter().any(|f| &f.name == function) => function.clone(),
+                    None => {
+                        self.unsupported(&format!("'{}'", function));
+                        self.emit("i64.const 0");
+                        return;
+                    }
+                };
+                for arg in args {
+                    self.generate_expression(arg, program);
+                }
+                self.emit(&format!("call ${}", target));
+            }
+
+            Expression::ModuleCall { module, function, args } if module == "stdio" => {
+                self.generate_stdio_call(function, args, program);
+            }
+
+            Expression::ModuleCall { module, function, args } => {
+                if matches!(module.as_str(), "vga" | "novaria" | "sys" | "time") || program.module_function(module, function).is_none() {
+                    self.unsupported(&format!("{}.{}", module, function));
+                    self.emit("i64.const 0");
+                    return;
+                }
+                for arg in args {
+                    self.generate_expression(arg, program);
+                }
+                self.emit(&format!("call ${}.{}", module, function));
+            }
+
+            Expression::Tuple(values) => {
+                for value in values {
+                    self.generate_expression(value, program);
+                }
+            }
+
+            Expression::String(_) | Expression::StringIndex { .. } | Expression::Slice { .. } => {
+                self.unsupported("string values");
+                self.emit("i64.const 0");
+            }
+            Expression::ArrayAccess { .. } => {
+                self.unsupported("arrays");
+                self.emit("i64.const 0");
+            }
+            Expression::AddressOf { .. } | Expression::Deref { .. } | Expression::IndirectCall { .. } => {
+                self.unsupported("pointers");
+                self.emit("i64.const 0");
+            }
+            Expression::Eval { .. } => {
+                self.unsupported("eval");
+                self.emit("i64.const 0");
+            }
+        }
+    }
+
+    fn generate_stdio_call(&mut self, function: &str, args: &[Expression], program: &Program) {
+        let print_name = NVMCodeGen::stdio_print_name(function, args);
+        match (print_name, args.first()) {
+            ("Print" | "Println", Some(arg)) => {
+                match arg {
+                    Expression::String(s) => self.emit_print_chars(s),
+                    Expression::Identifier(name) if self.compile_time_strings.contains_key(name) => {
+                        let text = self.compile_time_strings[name].clone();
+                        self.emit_print_chars(&text);
+                    }
+                    Expression::TemplateString { .. } => {
+                        self.generate_expression(arg, program);
+                        self.emit("drop");
+                    }
+                    _ => {
+                        self.generate_expression(arg, program);
+                        self.emit("call $print_int");
+                    }
+                }
+                if print_name == "Println" {
+                    self.emit_print_chars("\n");
+                }
+            }
+            ("PrintChar", Some(arg)) => {
+                self.generate_expression(arg, program);
+                self.emit("i32.wrap_i64");
+                self.emit("call $print_char");
+            }
+            ("Printf", _) => self.generate_printf(args, program),
+            ("Flush", None) => {}
+            _ => self.unsupported(&format!("stdio.{}", function)),
+        }
+        self.emit("i64.const 0");
+    }
+
+    fn generate_printf(&mut self, args: &[Expression], program: &Program) {
+        let pieces = match args.first() {
+            Some(Expression::String(format)) => NVMCodeGen::parse_printf_format(format),
+            _ => Err("stdio.Printf needs a string literal format on the WASM target".to_string()),
+        };
+        let pieces = match pieces {
+            Ok(pieces) => pieces,
+            Err(message) => {
+                self.fail(message);
+                return;
+            }
+        };
+
+        let mut values = args[1..].iter();
+        for piece in pieces {
+            match piece {
+                PrintfPiece::Text(text) => self.emit_print_chars(&text),
+                PrintfPiece::Int => match values.next() {
+                    Some(value) => {
+                        self.generate_expression(value, program);
+                        self.emit("call $print_int");
+                    }
+                    None => self.fail("stdio.Printf has more conversions than arguments".to_string()),
+                },
+                PrintfPiece::Str => match values.next() {
+                    Some(Expression::String(s)) => self.emit_print_chars(s),
+                    Some(_) => self.fail("stdio.Printf '%s' needs a string literal on the WASM target".to_string()),
+                    None => self.fail("stdio.Printf has more conversions than arguments".to_string()),
+                },
+            }
+        }
+    }
+
+    fn generate_int_builtin(&mut self, function: &str, args: &[Expression], program: &Program) {
+        let slots: Vec<String> = args.iter().map(|arg| {
+            self.generate_expression(arg, program);
+            let slot = self.declare(&format!("__{}", function));
+            self.emit(&format!("local.set {}", slot));
+            slot
+        }).collect();
+
+        if function == "abs" {
+            self.emit("i64.const 0");
+            self.emit(&format!("local.get {}", slots[0]));
+            self.emit("i64.sub");
+            self.emit(&format!("local.get {}", slots[0]));
+            self.emit(&format!("local.get {}", slots[0]));
+            self.emit("i64.const 0");
+            self.emit("i64.lt_s");
+        } else {
+            self.emit(&format!("local.get {}", slots[0]));
+            self.emit(&format!("local.get {}", slots[1]));
+            self.emit(&format!("local.get {}", slots[0]));
+            self.emit(&format!("local.get {}", slots[1]));
+            self.emit(if function == "max" { "i64.gt_s" } else { "i64.lt_s" });
+        }
+        self.emit("select");
+    }
+
+    fn emit_print_chars(&mut self, text: &str) {
+        for ch in text.bytes() {
+            self.emit(&format!("i32.const {}", ch));
+            self.emit("call $print_char");
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Helpers shared by the integration tests: compile a program for a target and run it here.
+#![allow(dead_code)]
+
+use perano_lang::{Compiler, Target};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Compiles `source` for `target`, panicking with the compiler's message on failure.
+pub fn compile(source: &str, target: Target) -> Vec<u8> {
+    match Compiler::new(target).compile(source) {
+        Ok(output) => output.code,
+        Err(e) => panic!("{:?} compile failed: {}", target, e.message),
+    }
+}
+
+fn scratch_path(name: &str) -> PathBuf {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("perano-test-{}-{}-{}", std::process::id(), id, name))
+}
+
+/// Builds `source` as an x86-64 ELF executable with `cc` and returns what it prints.
+pub fn run_elf(source: &str) -> String {
+    let asm = compile(source, Target::Elf);
+    let exe = scratch_path("elf");
+    let asm_file = exe.with_extension("s");
+    std::fs::write(&asm_file, asm).unwrap();
+    let output = link_and_run(&asm_file, &exe, &["-no-pie"]);
+    let _ = std::fs::remove_file(&asm_file);
+    output
+}
+
+/// Emits `source` as C with `Target::PeC`, builds it with `cc` and returns what it prints.
+pub fn run_c(source: &str) -> String {
+    let c = compile(source, Target::PeC);
+    let exe = scratch_path("c");
+    let c_file = exe.with_extension("c");
+    std::fs::write(&c_file, c).unwrap();
+    let output = link_and_run(&c_file, &exe, &[]);
+    let _ = std::fs::remove_file(&c_file);
+    output
+}
+
+fn link_and_run(input: &std::path::Path, exe: &std::path::Path, flags: &[&str]) -> String {
+    let cc = std::env::var("CC").ok().filter(|cc| !cc.is_empty()).unwrap_or_else(|| "gcc".to_string());
+    let status = Command::new(&cc).args(flags).arg("-o").arg(exe).arg(input).status()
+        .unwrap_or_else(|e| panic!("failed to run {}: {}", cc, e));
+    assert!(status.success(), "{} failed on {}", cc, input.display());
+    let output = Command::new(exe).output().unwrap();
+    let _ = std::fs::remove_file(exe);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Compiles `source` to NVM bytecode and runs it on a reference model of the VM. The model
+/// covers the opcodes a straight-line `main` and the print helpers use, with `div` and `mod`
+/// truncating as the language spec assumes; calls share one set of local slots.
+pub fn run_nvm(source: &str) -> String {
+    let code = compile(source, Target::Novaria);
+    assert_eq!(&code[..4], b"NVM0", "expected a version 0 image");
+    let operand = |at: usize| u32::from_be_bytes(code[at..at + 4].try_into().unwrap()) as usize;
+    let mut stack: Vec<i32> = Vec::new();
+    let mut locals = [0i32; 256];
+    let mut stdout = Vec::new();
+    let mut pc = 4;
+    loop {
+        let op = code[pc];
+        match op {
+            0x02 => {
+                stack.push(operand(pc + 1) as i32);
+                pc += 5;
+            }
+            0x04 => {
+                stack.pop().unwrap();
+                pc += 1;
+            }
+            0x06 => {
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+                pc += 1;
+            }
+            0x10..=0x14 | 0x21..=0x24 => {
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                stack.push(match op {
+                    0x10 => a.wrapping_add(b),
+                    0x11 => a.wrapping_sub(b),
+                    0x12 => a.wrapping_mul(b),
+                    0x13 => a.wrapping_div(b),
+                    0x14 => a.wrapping_rem(b),
+                    0x21 => (a == b) as i32,
+                    0x22 => (a != b) as i32,
+                    0x23 => (a > b) as i32,
+                    _ => (a < b) as i32,
+                });
+                pc += 1;
+            }
+            0x30 => pc = operand(pc + 1),
+            0x31 | 0x32 => {
+                let taken = (stack.pop().unwrap() == 0) == (op == 0x31);
+                pc = if taken { operand(pc + 1) } else { pc + 5 };
+            }
+            0x33 => {
+                stack.push(pc as i32 + 5);
+                pc = operand(pc + 1);
+            }
+            0x34 => pc = stack.pop().unwrap() as usize,
+            0x40 => {
+                stack.push(locals[code[pc + 1] as usize]);
+                pc += 2;
+            }
+            0x41 => {
+                locals[code[pc + 1] as usize] = stack.pop().unwrap();
+                pc += 2;
+            }
+            0x50 => match code[pc + 1] {
+                0x0F => {
+                    stdout.push(stack.pop().unwrap() as u8);
+                    pc += 2;
+                }
+                0x00 => break,
+                other => panic!("syscall {:#04x} is not modelled", other),
+            },
+            _ => panic!("opcode {:#04x} at {:#x} is not modelled", op, pc),
+        }
+    }
+    String::from_utf8(stdout).unwrap()
+}
+
+/// Builds `source` as a PE image and runs it in-process: the sections are mapped by hand, the
+/// kernel32 imports are bound to Linux shims and the entry point is called in a forked child.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn run_pe(source: &str) -> String {
+    pe_loader::run(&compile(source, Target::Pe))
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod pe_loader {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    const PROT_RWX: i32 = 0x7;
+    const MAP_PRIVATE_ANONYMOUS: i32 = 0x22;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> i32;
+        fn fork() -> i32;
+        fn pipe(fds: *mut i32) -> i32;
+        fn dup2(old: i32, new: i32) -> i32;
+        fn close(fd: i32) -> i32;
+        fn write(fd: i32, buf: *const u8, len: usize) -> isize;
+        fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        fn _exit(code: i32) -> !;
+    }
+
+    extern "win64" fn get_std_handle(_which: u32) -> u64 {
+        1
+    }
+
+    extern "win64" fn write_file(_handle: u64, buf: *const u8, len: u32, written: *mut u32, _overlapped: u64) -> i32 {
+        let mut done = 0;
+        while done < len as usize {
+            let n = unsafe { write(1, buf.add(done), len as usize - done) };
+            if n <= 0 {
+                return 0;
+            }
+            done += n as usize;
+        }
+        if !written.is_null() {
+            unsafe { *written = len };
+        }
+        1
+    }
+
+    extern "win64" fn exit_process(code: u32) -> ! {
+        unsafe { _exit(code as i32) }
+    }
+
+    extern "win64" fn unsupported_import() -> ! {
+        unsafe { _exit(127) }
+    }
+
+    fn u16_at(image: &[u8], offset: usize) -> usize {
+        u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(image: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    fn c_str_at(memory: &[u8], offset: usize) -> &str {
+        let end = memory[offset..].iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&memory[offset..offset + end]).unwrap()
+    }
+
+    fn shim_for(name: &str) -> u64 {
+        match name {
+            "GetStdHandle" => get_std_handle as *const () as u64,
+            "WriteFile" => write_file as *const () as u64,
+            "ExitProcess" => exit_process as *const () as u64,
+            _ => unsupported_import as *const () as u64,
+        }
+    }
+
+    pub fn run(image: &[u8]) -> String {
+        assert_eq!(&image[0..2], b"MZ", "not a PE image");
+        let pe = u32_at(image, 0x3C);
+        assert_eq!(&image[pe..pe + 4], b"PE\0\0", "missing PE signature");
+        let sections = u16_at(image, pe + 6);
+        let optional = pe + 24;
+        let entry = u32_at(image, optional + 16);
+        let image_size = u32_at(image, optional + 56);
+        let headers_size = u32_at(image, optional + 60);
+        let import_rva = u32_at(image, optional + 120);
+        let section_table = optional + u16_at(image, pe + 20);
+
+        let base = unsafe { mmap(std::ptr::null_mut(), image_size, PROT_RWX, MAP_PRIVATE_ANONYMOUS, -1, 0) };
+        assert!(base as isize != -1, "mmap failed");
+        let memory = unsafe { std::slice::from_raw_parts_mut(base as *mut u8, image_size) };
+
+        memory[..headers_size].copy_from_slice(&image[..headers_size]);
+        for i in 0..sections {
+            let header = section_table + i * 40;
+            let rva = u32_at(image, header + 12);
+            let raw_size = u32_at(image, header + 16);
+            let raw_offset = u32_at(image, header + 20);
+            memory[rva..rva + raw_size].copy_from_slice(&image[raw_offset..raw_offset + raw_size]);
+        }
+
+        if import_rva != 0 {
+            let mut descriptor = import_rva;
+            while u32_at(memory, descriptor + 12) != 0 {
+                let lookup = u32_at(memory, descriptor);
+                let address = u32_at(memory, descriptor + 16);
+                let mut slot = 0;
+                loop {
+                    let name_rva = u32_at(memory, lookup + slot * 8);
+                    if name_rva == 0 {
+                        break;
+                    }
+                    let shim = shim_for(c_str_at(memory, name_rva + 2));
+                    let target = address + slot * 8;
+                    memory[target..target + 8].copy_from_slice(&shim.to_le_bytes());
+                    slot += 1;
+                }
+                descriptor += 20;
+            }
+        }
+
+        let mut fds = [0i32; 2];
+        assert_eq!(unsafe { pipe(fds.as_mut_ptr()) }, 0, "pipe failed");
+        let pid = unsafe { fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            unsafe {
+                dup2(fds[1], 1);
+                close(fds[0]);
+                let start: extern "win64" fn() = std::mem::transmute(memory.as_ptr().add(entry));
+                start();
+                _exit(0);
+            }
+        }
+
+        unsafe { close(fds[1]) };
+        let mut stdout = String::new();
+        unsafe { File::from_raw_fd(fds[0]) }.read_to_string(&mut stdout).unwrap();
+        let mut status = 0;
+        unsafe {
+            waitpid(pid, &mut status, 0);
+            munmap(base, image_size);
+        }
+        assert_eq!(status & 0x7F, 0, "PE program was killed by signal {}", status & 0x7F);
+        stdout
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use perano_lang::Target;
+
+/// `/` truncates toward zero and `%` takes the sign of the dividend on every backend.
+/// The operands live in variables so nothing is folded at compile time, and each has its
+/// own declaration because the C backend does not lower plain assignments.
+const TABLE: &str = r#"package main
+
+import "stdio"
+
+fn main() {
+    var minus7 i64 = 0 - 7
+    var minus2 i64 = 0 - 2
+    var minus1 i64 = 0 - 1
+    var two i64 = 2
+    var three i64 = 3
+    var four i64 = 4
+    var seven i64 = 7
+    stdio.Println(minus7 / two)
+    stdio.Println(minus7 % two)
+    stdio.Println(seven / minus2)
+    stdio.Println(seven % minus2)
+    stdio.Println(minus7 / minus2)
+    stdio.Println(minus7 % minus2)
+    stdio.Println(minus7 / three)
+    stdio.Println(minus7 % three)
+    stdio.Println(minus1 / four)
+    stdio.Println(minus1 % four)
+}
+"#;
+
+// -7/2, -7%2, 7/-2, 7%-2, -7/-2, -7%-2, -7/3, -7%3, -1/4, -1%4
+const EXPECTED: &str = "-3\n-1\n-3\n1\n3\n-1\n-2\n-1\n0\n-1\n";
+
+#[test]
+fn elf_truncates() {
+    assert_eq!(common::run_elf(TABLE), EXPECTED);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn pe_truncates() {
+    assert_eq!(common::run_pe(TABLE), EXPECTED);
+}
+
+#[test]
+fn c_truncates() {
+    assert_eq!(common::run_c(TABLE), EXPECTED);
+}
+
+#[test]
+fn nvm_truncates_on_a_truncating_vm() {
+    assert_eq!(common::run_nvm(TABLE), EXPECTED);
+}
+
+// Neither target can run here; both instruction sets define signed division to truncate,
+// so check that the signed forms are the ones emitted.
+
+#[test]
+fn arm64_uses_sdiv_and_msub() {
+    let asm = String::from_utf8(common::compile(TABLE, Target::ElfArm64)).unwrap();
+    assert_eq!(asm.matches("sdiv").count(), 10);
+    assert_eq!(asm.matches("msub").count(), 5);
+    assert!(!asm.contains("udiv"));
+}
+
+#[test]
+fn wasm_uses_signed_div_and_rem() {
+    let wat = String::from_utf8(common::compile(TABLE, Target::Wasm)).unwrap();
+    assert_eq!(wat.matches("i64.div_s").count(), 5);
+    assert_eq!(wat.matches("i64.rem_s").count(), 5);
+    assert!(!wat.contains("i64.div_u") && !wat.contains("i64.rem_u"));
+}
@@ -0,0 +1,213 @@
+//! Builds every example that states what it should do and checks that it does it. An example
+//! opts in with trailing comments:
+//!
+//! - `// expected output:` as the last comment block, followed by one `// line` per line
+//!   printed to stdout or stderr (blank lines are ignored, and `...` matches any text);
+//! - `// expected exit code: N`, before the output block;
+//! - `// input: a b c` to feed those words to stdin, one per line;
+//! - `//   perano-lang <file> <args>` to build with extra arguments or further files;
+//! - `// expected novaria bytecode ...:` as the last comment block, followed by disassembly
+//!   lines whose hex bytes (the column after a run of spaces) must appear in that order in
+//!   the `--novaria` build.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Expectations {
+    output: Option<Vec<String>>,
+    bytecode: Option<Vec<u8>>,
+    exit_code: Option<i32>,
+    input: Option<String>,
+    build_args: Option<Vec<String>>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations {
+            output: None,
+            bytecode: None,
+            exit_code: None,
+            input: None,
+            build_args: None,
+        };
+        for line in source.lines() {
+            let comment = match line.strip_prefix("//") {
+                Some(comment) => comment,
+                None => continue,
+            };
+            if let Some(output) = expectations.output.as_mut() {
+                let text = comment.strip_prefix(' ').unwrap_or(comment);
+                if !text.is_empty() {
+                    output.push(text.to_string());
+                }
+            } else if let Some(bytecode) = expectations.bytecode.as_mut() {
+                if let Some(hex) = comment.trim().rsplit("  ").next().filter(|hex| !hex.is_empty()) {
+                    for byte in hex.split_whitespace() {
+                        bytecode.push(u8::from_str_radix(byte, 16).expect("bytecode must be hex bytes"));
+                    }
+                }
+            } else if comment.trim() == "expected output:" {
+                expectations.output = Some(Vec::new());
+            } else if comment.trim().starts_with("expected novaria bytecode") {
+                expectations.bytecode = Some(Vec::new());
+            } else if let Some(code) = comment.trim().strip_prefix("expected exit code:") {
+                expectations.exit_code = Some(code.trim().parse().expect("exit code must be a number"));
+            } else if let Some(words) = comment.trim().strip_prefix("input:") {
+                expectations.input = Some(words.split_whitespace().map(|w| format!("{}\n", w)).collect());
+            } else if let Some(command) = comment.trim().strip_prefix("perano-lang ") {
+                expectations.build_args = Some(command.split_whitespace().map(str::to_string).collect());
+            }
+        }
+        expectations
+    }
+
+    fn is_empty(&self) -> bool {
+        self.output.is_none() && self.exit_code.is_none() && self.bytecode.is_none()
+    }
+
+    fn runs(&self) -> bool {
+        self.output.is_some() || self.exit_code.is_some()
+    }
+}
+
+/// Whether `line` matches `pattern`, where each `...` in the pattern stands for any text.
+fn matches_line(pattern: &str, line: &str) -> bool {
+    let mut pieces = pattern.split("...");
+    let first = pieces.next().unwrap_or("");
+    let mut rest = match line.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let pieces: Vec<&str> = pieces.collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i + 1 == pieces.len() {
+            return rest.ends_with(piece);
+        }
+        match rest.find(piece) {
+            Some(at) => rest = &rest[at + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+fn scratch_executable(example: &Path) -> PathBuf {
+    let name = example.strip_prefix("examples").unwrap_or(example).with_extension("");
+    let name = name.to_string_lossy().replace('/', "-");
+    std::env::temp_dir().join(format!("perano-example-{}-{}", std::process::id(), name))
+}
+
+/// Builds and runs one example, returning a description of the first way it falls short.
+fn check(example: &Path, expectations: &Expectations) -> Result<(), String> {
+    if let Some(expected) = &expectations.bytecode {
+        check_bytecode(example, expected)?;
+    }
+    if expectations.runs() {
+        check_run(example, expectations)?;
+    }
+    Ok(())
+}
+
+fn check_bytecode(example: &Path, expected: &[u8]) -> Result<(), String> {
+    let out = scratch_executable(example).with_extension("bin");
+    let built = Command::new(env!("CARGO_BIN_EXE_perano-lang"))
+        .arg(example)
+        .arg("--novaria")
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .map_err(|e| format!("could not run the compiler: {}", e))?;
+    if !built.status.success() {
+        return Err(format!("does not build for novaria:\n{}", String::from_utf8_lossy(&built.stderr)));
+    }
+    let bytecode = std::fs::read(&out).unwrap();
+    let _ = std::fs::remove_file(&out);
+    if !bytecode.windows(expected.len()).any(|window| window == expected) {
+        return Err(format!("novaria bytecode does not contain {:02x?}", expected));
+    }
+    Ok(())
+}
+
+fn check_run(example: &Path, expectations: &Expectations) -> Result<(), String> {
+    let exe = scratch_executable(example);
+    let mut build = Command::new(env!("CARGO_BIN_EXE_perano-lang"));
+    match &expectations.build_args {
+        Some(args) => {
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                if arg == "-o" {
+                    args.next();
+                } else {
+                    build.arg(arg);
+                }
+            }
+        }
+        None => {
+            build.arg(example);
+        }
+    }
+    build.arg("--elf").arg("-o").arg(&exe);
+    let built = build.output().map_err(|e| format!("could not run the compiler: {}", e))?;
+    if !built.status.success() {
+        return Err(format!("does not build:\n{}", String::from_utf8_lossy(&built.stderr)));
+    }
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg("exec \"$0\" 2>&1")
+        .arg(&exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run {}: {}", exe.display(), e))?;
+    let input = expectations.input.clone().unwrap_or_default();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let run = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&exe);
+
+    if let Some(expected) = &expectations.output {
+        let stdout = String::from_utf8_lossy(&run.stdout);
+        let actual: Vec<&str> = stdout.lines().filter(|line| !line.is_empty()).collect();
+        let same = actual.len() == expected.len()
+            && expected.iter().zip(&actual).all(|(pattern, line)| matches_line(pattern, line));
+        if !same {
+            return Err(format!("printed\n{}\nbut expected\n{}", actual.join("\n"), expected.join("\n")));
+        }
+    }
+    if let Some(code) = expectations.exit_code {
+        if run.status.code() != Some(code) {
+            return Err(format!("exited with {:?}, expected {}", run.status.code(), code));
+        }
+    }
+    Ok(())
+}
+
+/// `examples/*.per`, plus the `main.per` of each multi-file example under `examples/<dir>/`.
+fn examples() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir("examples").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| if path.is_dir() { path.join("main.per") } else { path })
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "per"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn examples_do_what_their_comments_say() {
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for example in examples() {
+        let expectations = Expectations::parse(&std::fs::read_to_string(&example).unwrap());
+        if expectations.is_empty() {
+            continue;
+        }
+        checked += 1;
+        if let Err(reason) = check(&example, &expectations) {
+            failures.push(format!("{}: {}", example.display(), reason));
+        }
+    }
+    assert!(checked > 0, "no example states an expected output");
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
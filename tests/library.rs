@@ -0,0 +1,48 @@
+mod common;
+
+use perano_lang::{Compiler, Target};
+
+/// One program built through the library for x86-64 and both NVM targets: calls, loops,
+/// templates, and pointers taken and read through `&` and `*`.
+const PROGRAM: &str = r#"package main
+
+import "stdio"
+
+fn square(n i64) i64 {
+    return n * n
+}
+
+fn main() {
+    var total i64 = 0
+    for var i i64 = 1; i <= 3; i = i + 1 {
+        total = total + square(i)
+    }
+    var p *i64 = &total
+    if *p > 10 {
+        stdio.Println("total: $(total)")
+    }
+    stdio.Println(*p)
+}
+"#;
+
+#[test]
+fn the_same_source_compiles_for_elf_and_nvm() {
+    for target in [Target::Elf, Target::NvmCode, Target::Novaria] {
+        let output = Compiler::new(target).compile(PROGRAM)
+            .unwrap_or_else(|e| panic!("{:?}: {}", target, e.message));
+        assert!(!output.code.is_empty(), "{:?} produced no code", target);
+    }
+}
+
+#[test]
+fn the_elf_build_runs() {
+    assert_eq!(common::run_elf(PROGRAM), "total: 14\n14\n");
+}
+
+#[test]
+fn compile_matches_a_default_compiler() {
+    for target in [Target::Elf, Target::Novaria] {
+        let code = perano_lang::compile(PROGRAM, target).unwrap();
+        assert_eq!(code, Compiler::new(target).compile(PROGRAM).unwrap().code);
+    }
+}
@@ -0,0 +1,42 @@
+mod common;
+
+/// Print and Println must write the same bytes on ELF and PE: newlines only from Println,
+/// string literals, string variables and templates as text, everything else as integers.
+const PRINTS: &str = r#"package main
+
+import "stdio"
+
+fn main() {
+    var name = "world"
+    var n i64 = 42
+    stdio.Print(1)
+    stdio.Print(2)
+    stdio.Println(3)
+    stdio.Print("a")
+    stdio.Println("b")
+    stdio.PrintStr("c")
+    stdio.PrintlnStr("d")
+    stdio.Println(0 - 4)
+    stdio.Println(name)
+    stdio.Print(name)
+    stdio.Println("!")
+    stdio.Println("hello $(name), n=$(n)")
+    stdio.Print("$(n + 1)")
+    stdio.Println("")
+    stdio.PrintlnStr("")
+    stdio.Println("end")
+}
+"#;
+
+const EXPECTED: &str = "123\nab\ncd\n-4\nworld\nworld!\nhello world, n=42\n43\n\nend\n";
+
+#[test]
+fn elf_prints_the_golden_output() {
+    assert_eq!(common::run_elf(PRINTS), EXPECTED);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn pe_prints_what_elf_prints() {
+    assert_eq!(common::run_pe(PRINTS), common::run_elf(PRINTS));
+}